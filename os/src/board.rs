@@ -0,0 +1,377 @@
+//! What the device tree passed by the SBI says about the machine, read once
+//! on the boot hart before paging is on. The compiled defaults stand in for
+//! anything it leaves out.
+
+use crate::config::{CPU_NUM, DEFAULT_CLOCK_FREQ, DEFAULT_MEMORY_END};
+use crate::fdt::{self, Fdt, Token};
+use crate::plic::{MODE_PER_HART, PLIC_BASE, PLIC_SIZE};
+use crate::uart::{SERIAL_ADDRESS_STRIDE, SERIAL_BASE_ADDRESS, SERIAL_IRQ_BASE, SERIAL_NUM};
+use heapless::Vec;
+use spin::Once;
+
+const MAX_DEPTH: usize = 16;
+const MAX_MEMORY_REGIONS: usize = 8;
+const MAX_UARTS: usize = 16;
+
+const UART_COMPATIBLE: &[&str] = &["ns16550a", "ns16550", "xlnx,xps-uart16550-2.00.a"];
+const PLIC_COMPATIBLE: &[&str] = &["riscv,plic0", "sifive,plic-1.0.0"];
+
+/// Local interrupts a PLIC context is wired to, by mode
+const IRQ_M_EXT: usize = 11;
+const IRQ_S_EXT: usize = 9;
+const IRQ_U_EXT: usize = 8;
+
+#[derive(Debug, Clone, Copy)]
+pub struct SerialInfo {
+    pub base: usize,
+    pub size: usize,
+    pub irq: u16,
+}
+
+#[derive(Debug)]
+pub struct PlicInfo {
+    pub base: usize,
+    pub size: usize,
+    pub context_num: usize,
+    /// Context of each hart in M, S and U mode
+    pub contexts: [[usize; MODE_PER_HART]; CPU_NUM],
+}
+
+#[derive(Debug)]
+pub struct BoardInfo {
+    /// End of the memory the kernel hands out as frames
+    pub memory_end: usize,
+    pub hart_num: usize,
+    pub clock_freq: usize,
+    /// UARTs the kernel drives, without the one the SBI console uses
+    pub serials: Vec<SerialInfo, SERIAL_NUM>,
+    pub plic: PlicInfo,
+}
+
+static BOARD: Once<BoardInfo> = Once::new();
+
+/// Read the device tree at physical address `dtb`, 0 if there is none.
+pub fn init(dtb: usize) {
+    let board = BOARD.call_once(|| match unsafe { Fdt::from_addr(dtb) } {
+        Some(fdt) => BoardInfo::from_fdt(&fdt),
+        None => {
+            warn!("No device tree at {:#x}, using defaults", dtb);
+            BoardInfo::from_probe(Probe::default())
+        }
+    });
+    debug!(
+        "memory end {:#x}, {} harts, timebase {} Hz",
+        board.memory_end, board.hart_num, board.clock_freq
+    );
+    for (i, serial) in board.serials.iter().enumerate() {
+        debug!("serial {} at {:#x}, irq {}", i, serial.base, serial.irq);
+    }
+    debug!(
+        "PLIC at {:#x}, {} contexts",
+        board.plic.base, board.plic.context_num
+    );
+}
+
+pub fn board() -> &'static BoardInfo {
+    BOARD.get().expect("board info used before board::init")
+}
+
+pub fn memory_end() -> usize {
+    board().memory_end
+}
+
+pub fn hart_num() -> usize {
+    board().hart_num
+}
+
+pub fn clock_freq() -> usize {
+    board().clock_freq
+}
+
+/// A node on the path being walked, with the properties of interest.
+#[derive(Default)]
+struct Node<'a> {
+    name: &'a str,
+    /// Cells in the reg of the children
+    address_cells: usize,
+    size_cells: usize,
+    compatible: &'a [u8],
+    device_type: &'a [u8],
+    status: &'a [u8],
+    reg: &'a [u8],
+    interrupts: &'a [u8],
+    interrupts_extended: &'a [u8],
+    phandle: Option<usize>,
+    timebase: Option<usize>,
+    stdout_path: &'a [u8],
+}
+
+impl<'a> Node<'a> {
+    fn new(name: &'a str) -> Self {
+        Self {
+            name,
+            // defaults of the spec
+            address_cells: 2,
+            size_cells: 1,
+            ..Default::default()
+        }
+    }
+
+    fn is_compatible(&self, names: &[&str]) -> bool {
+        fdt::strings(self.compatible).any(|s| names.contains(&s))
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.status.is_empty() || matches!(fdt::strings(self.status).next(), Some("okay" | "ok"))
+    }
+
+    fn is_device_type(&self, device_type: &str) -> bool {
+        fdt::strings(self.device_type).next() == Some(device_type)
+    }
+
+    /// Entries of reg as (address, size), with the cells of `parent`.
+    fn reg(&self, parent: &Node) -> impl Iterator<Item = (usize, usize)> + 'a {
+        let (ac, sc) = (parent.address_cells, parent.size_cells);
+        let reg: &'a [u8] = self.reg;
+        reg.chunks_exact((ac + sc).max(1) * 4)
+            .filter_map(move |entry| {
+                let address = fdt::read_cells(entry, ac)?;
+                let size = if sc == 0 {
+                    0
+                } else {
+                    fdt::read_cells(&entry[ac * 4..], sc)?
+                };
+                Some((address, size))
+            })
+    }
+}
+
+/// Everything found in the device tree, before defaults are filled in.
+#[derive(Default)]
+struct Probe<'a> {
+    memory: Vec<(usize, usize), MAX_MEMORY_REGIONS>,
+    /// Largest enabled hart id
+    max_hart: Option<usize>,
+    timebase: Option<usize>,
+    uarts: Vec<SerialInfo, MAX_UARTS>,
+    /// Unit address of the UART in stdout-path
+    stdout: Option<usize>,
+    /// reg entry and interrupts-extended of the PLIC
+    plic: Option<((usize, usize), &'a [u8])>,
+    /// Interrupt controllers of the harts, by phandle
+    intcs: Vec<(usize, usize), CPU_NUM>,
+}
+
+impl<'a> Probe<'a> {
+    /// Take in `node` when it ends, its ancestors are still on `stack`.
+    fn visit(&mut self, node: &Node<'a>, stack: &[Node<'a>]) {
+        let depth = stack.len();
+        let parent = match stack.last() {
+            Some(parent) => parent,
+            None => return,
+        };
+        if depth == 1 && node.name == "chosen" {
+            self.stdout = stdout_address(node.stdout_path);
+        } else if depth == 1 && node.name == "cpus" {
+            self.timebase = self.timebase.or(node.timebase);
+        } else if node.is_device_type("memory") {
+            for region in node.reg(parent) {
+                if self.memory.push(region).is_err() {
+                    warn!("Too many memory regions in the device tree");
+                    break;
+                }
+            }
+        } else if node.is_device_type("cpu") && parent.name == "cpus" {
+            if let (true, Some((hart, _))) = (node.is_enabled(), node.reg(parent).next()) {
+                if hart >= CPU_NUM {
+                    warn!("Hart {} is beyond CPU_NUM, left alone", hart);
+                } else {
+                    self.max_hart = Some(self.max_hart.map_or(hart, |max| max.max(hart)));
+                }
+                self.timebase = self.timebase.or(node.timebase);
+            }
+        } else if node.is_compatible(UART_COMPATIBLE) && node.is_enabled() {
+            let reg = node.reg(parent).next();
+            let irq = fdt::read_cells(node.interrupts, 1);
+            if let (Some((base, size)), Some(irq)) = (reg, irq) {
+                let uart = SerialInfo {
+                    base,
+                    size,
+                    irq: irq as u16,
+                };
+                if self.uarts.push(uart).is_err() {
+                    warn!("Too many UARTs in the device tree");
+                }
+            }
+        } else if node.is_compatible(PLIC_COMPATIBLE) {
+            if let Some(reg) = node.reg(parent).next() {
+                self.plic = Some((reg, node.interrupts_extended));
+            }
+        } else if let (Some(phandle), Some(grandparent)) = (
+            node.phandle,
+            depth.checked_sub(2).and_then(|i| stack.get(i)),
+        ) {
+            // the interrupt controller of a hart, which the PLIC refers to
+            if parent.is_device_type("cpu") {
+                if let Some((hart, _)) = parent.reg(grandparent).next() {
+                    if hart < CPU_NUM {
+                        let _ = self.intcs.push((phandle, hart));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Unit address of the node in stdout-path, aliases are not followed.
+fn stdout_address(path: &[u8]) -> Option<usize> {
+    let path = fdt::strings(path).next()?;
+    let path = path.split(':').next()?;
+    let (_, unit_address) = path.rsplit_once('@')?;
+    usize::from_str_radix(unit_address, 16).ok()
+}
+
+impl BoardInfo {
+    fn from_fdt(fdt: &Fdt) -> Self {
+        let mut stack: Vec<Node, MAX_DEPTH> = Vec::new();
+        let mut probe = Probe::default();
+        for token in fdt.tokens() {
+            match token {
+                Token::BeginNode(name) => {
+                    if stack.push(Node::new(name)).is_err() {
+                        warn!("Device tree nested too deep");
+                        break;
+                    }
+                }
+                Token::EndNode => {
+                    if let Some(node) = stack.pop() {
+                        probe.visit(&node, &stack);
+                    }
+                }
+                Token::Prop(name, value) => {
+                    let node = match stack.last_mut() {
+                        Some(node) => node,
+                        None => continue,
+                    };
+                    match name {
+                        "#address-cells" => {
+                            node.address_cells = fdt::read_cells(value, 1).unwrap_or(2)
+                        }
+                        "#size-cells" => node.size_cells = fdt::read_cells(value, 1).unwrap_or(1),
+                        "compatible" => node.compatible = value,
+                        "device_type" => node.device_type = value,
+                        "status" => node.status = value,
+                        "reg" => node.reg = value,
+                        "interrupts" => node.interrupts = value,
+                        "interrupts-extended" => node.interrupts_extended = value,
+                        "phandle" | "linux,phandle" => node.phandle = fdt::read_cells(value, 1),
+                        "timebase-frequency" => {
+                            node.timebase = fdt::read_cells(value, value.len() / 4)
+                        }
+                        "stdout-path" | "linux,stdout-path" => node.stdout_path = value,
+                        _ => {}
+                    }
+                }
+            }
+        }
+        Self::from_probe(probe)
+    }
+
+    fn from_probe(probe: Probe) -> Self {
+        extern "C" {
+            fn ekernel();
+        }
+        let kernel_end = ekernel as usize;
+        // the region the kernel is loaded into
+        let memory_end = probe
+            .memory
+            .iter()
+            .find(|(base, size)| (*base..base + size).contains(&kernel_end))
+            .map(|(base, size)| base + size)
+            .unwrap_or_else(|| {
+                warn!("No memory holding the kernel in the device tree");
+                DEFAULT_MEMORY_END
+            });
+        // the trace buffer and the frames must not overlap
+        #[cfg(feature = "trace")]
+        let memory_end = memory_end.min(crate::config::TRACE_BASE);
+
+        let hart_num = probe.max_hart.map_or(CPU_NUM, |max| max + 1);
+        let clock_freq = probe.timebase.unwrap_or_else(|| {
+            warn!("No timebase-frequency in the device tree");
+            DEFAULT_CLOCK_FREQ
+        });
+
+        let mut uarts = probe.uarts;
+        uarts.sort_unstable_by_key(|uart| uart.base);
+        // the SBI console keeps its UART
+        let mut serials: Vec<SerialInfo, SERIAL_NUM> = uarts
+            .iter()
+            .filter(|uart| Some(uart.base) != probe.stdout)
+            .take(SERIAL_NUM)
+            .cloned()
+            .collect();
+        if serials.is_empty() {
+            serials = (0..SERIAL_NUM)
+                .map(|i| SerialInfo {
+                    base: SERIAL_BASE_ADDRESS + i * SERIAL_ADDRESS_STRIDE,
+                    size: SERIAL_ADDRESS_STRIDE,
+                    irq: SERIAL_IRQ_BASE + i as u16,
+                })
+                .collect();
+        }
+
+        let mut plic = PlicInfo {
+            base: PLIC_BASE,
+            size: PLIC_SIZE,
+            context_num: CPU_NUM * MODE_PER_HART,
+            contexts: array_init::array_init(|hart| {
+                array_init::array_init(|mode| hart * MODE_PER_HART + mode)
+            }),
+        };
+        if let Some(((base, size), interrupts_extended)) = probe.plic {
+            plic.base = base;
+            plic.size = size;
+            plic.context_num = interrupts_extended.len() / 8;
+            let mut contexts = [[None; MODE_PER_HART]; CPU_NUM];
+            for (context, entry) in interrupts_extended.chunks_exact(8).enumerate() {
+                let phandle = fdt::read_cells(entry, 1);
+                let hart = probe
+                    .intcs
+                    .iter()
+                    .find(|(intc, _)| Some(*intc) == phandle)
+                    .map(|(_, hart)| *hart);
+                let mode = match fdt::read_cells(&entry[4..], 1) {
+                    Some(IRQ_M_EXT) => 0,
+                    Some(IRQ_S_EXT) => 1,
+                    Some(IRQ_U_EXT) => 2,
+                    _ => continue,
+                };
+                if let Some(hart) = hart {
+                    contexts[hart][mode] = Some(context);
+                }
+            }
+            // without the N extension there is no U context in the device
+            // tree, though the PLIC may still have one
+            if contexts[..hart_num].iter().flatten().all(Option::is_some) {
+                for (hart, modes) in contexts.iter().enumerate() {
+                    for (mode, context) in modes.iter().enumerate() {
+                        if let Some(context) = context {
+                            plic.contexts[hart][mode] = *context;
+                        }
+                    }
+                }
+            } else {
+                warn!("PLIC contexts in the device tree are incomplete, using defaults");
+            }
+        }
+
+        Self {
+            memory_end,
+            hart_num,
+            clock_freq,
+            serials,
+            plic,
+        }
+    }
+}
@@ -1,13 +1,19 @@
 pub const USER_STACK_SIZE: usize = 0x4000;
 pub const KERNEL_STACK_SIZE: usize = 0x4000;
 pub const KERNEL_HEAP_SIZE: usize = 0x20_0000;
+/// Frames added at least each time the kernel heap runs out
+pub const KERNEL_HEAP_GROW_PAGES: usize = 64;
 
+/// Where the trace buffer starts, user programs push events here too
 #[cfg(feature = "board_qemu")]
-pub const MEMORY_END: usize = 0x82000000;
+pub const TRACE_BASE: usize = 0x82000000;
 
 #[cfg(feature = "board_lrv")]
-// pub const MEMORY_END: usize = 0x100A00000;
-pub const MEMORY_END: usize = 0x101000000;
+// pub const TRACE_BASE: usize = 0x100A00000;
+pub const TRACE_BASE: usize = 0x101000000;
+
+/// End of memory if the device tree has none
+pub const DEFAULT_MEMORY_END: usize = TRACE_BASE;
 
 pub const PAGE_SIZE: usize = 0x1000;
 pub const PAGE_SIZE_BITS: usize = 0xc;
@@ -15,12 +21,23 @@ pub const PAGE_SIZE_BITS: usize = 0xc;
 pub const TRAMPOLINE: usize = usize::MAX - PAGE_SIZE + 1;
 pub const TRAP_CONTEXT: usize = TRAMPOLINE - PAGE_SIZE;
 pub const USER_TRAP_BUFFER: usize = TRAP_CONTEXT - PAGE_SIZE;
+/// Kernel stacks go down from `TRAMPOLINE` and must stay in the upper half
+/// of Sv39, the lower half is the identity map of memory and devices
+pub const MAX_KERNEL_STACKS: usize =
+    (TRAMPOLINE - !((1 << 38) - 1)) / (KERNEL_STACK_SIZE + PAGE_SIZE);
+/// Thread `i > 0` keeps its TrapContext at `USER_TRAP_BUFFER - i * PAGE_SIZE`
+pub const MAX_THREADS: usize = 64;
+/// Thread user stacks grow down from here, separated by guard pages
+pub const THREAD_STACK_TOP: usize = USER_TRAP_BUFFER - (MAX_THREADS + 1) * PAGE_SIZE;
 
+/// Timebase if the device tree has none
 #[cfg(feature = "board_qemu")]
-pub const CLOCK_FREQ: usize = 12500000;
+pub const DEFAULT_CLOCK_FREQ: usize = 12500000;
 
 #[cfg(feature = "board_lrv")]
-pub const CLOCK_FREQ: usize = 10_000_000;
+pub const DEFAULT_CLOCK_FREQ: usize = 10_000_000;
 
+/// Most harts the kernel has boot stacks and per-hart state for, the device
+/// tree tells how many there are
 pub const CPU_NUM: usize = 4;
 pub const TRACE_SIZE: usize = 0x1000_0000; // 256M
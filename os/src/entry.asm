@@ -2,6 +2,10 @@
     .globl _start
 _start:
     # a0: hart id
+    # a1: device tree, passed on to rust_main
+    # harts past CPU_NUM have no boot stack, park them
+    li t0, {CPU_NUM}
+    bgeu a0, t0, park
     mv tp, a0
     la sp, boot_stack
     # li t1, 4096 * 16 # t1 = 4096 * 16 64KB
@@ -10,9 +14,13 @@ _start:
     add sp, sp, t0  # sp = sp + t0
     call rust_main
 
+park:
+    wfi
+    j park
+
     .section .bss.stack
     .globl boot_stack
 boot_stack:
-    .space 4096 * 16 * 4
+    .space 4096 * 16 * {CPU_NUM}
     .globl boot_stack_top
 boot_stack_top:
\ No newline at end of file
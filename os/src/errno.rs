@@ -0,0 +1,53 @@
+use crate::trap::UserTrapError;
+
+/// Error numbers returned to user space as `-(errno as isize)`.
+///
+/// Values follow Linux so that user programs can share the table, see
+/// `user_lib::errno`.
+#[repr(isize)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Errno {
+    EPERM = 1,
+    ENOENT = 2,
+    ESRCH = 3,
+    EINTR = 4,
+    EIO = 5,
+    E2BIG = 7,
+    ENOEXEC = 8,
+    EBADF = 9,
+    ECHILD = 10,
+    EAGAIN = 11,
+    ENOMEM = 12,
+    EACCES = 13,
+    EFAULT = 14,
+    EBUSY = 16,
+    EEXIST = 17,
+    ENODEV = 19,
+    ENOTDIR = 20,
+    EISDIR = 21,
+    EINVAL = 22,
+    EMFILE = 24,
+    EFBIG = 27,
+    ENOSPC = 28,
+    ESPIPE = 29,
+    EPIPE = 32,
+    EDEADLK = 35,
+    ENAMETOOLONG = 36,
+    ENOSYS = 38,
+}
+
+impl From<Errno> for isize {
+    fn from(errno: Errno) -> Self {
+        -(errno as isize)
+    }
+}
+
+impl From<UserTrapError> for Errno {
+    fn from(err: UserTrapError) -> Self {
+        match err {
+            UserTrapError::TaskNotFound => Errno::ESRCH,
+            UserTrapError::TrapUninitialized => Errno::EPERM,
+            UserTrapError::TrapBufferFull => Errno::EAGAIN,
+        }
+    }
+}
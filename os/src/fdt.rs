@@ -0,0 +1,135 @@
+//! Just enough of a flattened device tree reader to walk its nodes and
+//! properties, without allocating.
+
+const FDT_MAGIC: u32 = 0xd00d_feed;
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+
+/// Oldest layout with the size of the struct block in the header
+const FDT_MIN_VERSION: u32 = 17;
+
+pub struct Fdt<'a> {
+    structs: &'a [u8],
+    strings: &'a [u8],
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Token<'a> {
+    BeginNode(&'a str),
+    EndNode,
+    Prop(&'a str, &'a [u8]),
+}
+
+fn be32(bytes: &[u8], offset: usize) -> Option<u32> {
+    let bytes = bytes.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// The NUL terminated string at the start of `bytes`.
+fn cstr(bytes: &[u8]) -> Option<&str> {
+    let len = bytes.iter().position(|&b| b == 0)?;
+    core::str::from_utf8(&bytes[..len]).ok()
+}
+
+impl<'a> Fdt<'a> {
+    /// Check the header of the blob at physical address `addr`.
+    ///
+    /// # Safety
+    ///
+    /// `addr` must be 0 or point to readable memory holding at least the
+    /// header, and the blob must stay untouched for `'a`.
+    pub unsafe fn from_addr(addr: usize) -> Option<Self> {
+        if addr == 0 || addr % 4 != 0 {
+            return None;
+        }
+        let header = core::slice::from_raw_parts(addr as *const u8, 40);
+        if be32(header, 0)? != FDT_MAGIC || be32(header, 20)? < FDT_MIN_VERSION {
+            return None;
+        }
+        let total_size = be32(header, 4)? as usize;
+        let blob = core::slice::from_raw_parts(addr as *const u8, total_size);
+        let structs_offset = be32(header, 8)? as usize;
+        let strings_offset = be32(header, 12)? as usize;
+        let strings_size = be32(header, 32)? as usize;
+        let structs_size = be32(header, 36)? as usize;
+        Some(Self {
+            structs: blob.get(structs_offset..structs_offset.checked_add(structs_size)?)?,
+            strings: blob.get(strings_offset..strings_offset.checked_add(strings_size)?)?,
+        })
+    }
+
+    pub fn tokens(&self) -> Tokens<'a> {
+        Tokens {
+            structs: self.structs,
+            strings: self.strings,
+            offset: 0,
+        }
+    }
+}
+
+/// Nodes and properties in the order of the blob, where the properties of a
+/// node come before its children. Ends early on a malformed blob.
+pub struct Tokens<'a> {
+    structs: &'a [u8],
+    strings: &'a [u8],
+    offset: usize,
+}
+
+impl Tokens<'_> {
+    fn align(&mut self) {
+        self.offset = (self.offset + 3) & !3;
+    }
+}
+
+impl<'a> Iterator for Tokens<'a> {
+    type Item = Token<'a>;
+
+    fn next(&mut self) -> Option<Token<'a>> {
+        loop {
+            let token = be32(self.structs, self.offset)?;
+            self.offset += 4;
+            match token {
+                FDT_BEGIN_NODE => {
+                    let name = cstr(self.structs.get(self.offset..)?)?;
+                    self.offset += name.len() + 1;
+                    self.align();
+                    return Some(Token::BeginNode(name));
+                }
+                FDT_END_NODE => return Some(Token::EndNode),
+                FDT_PROP => {
+                    let len = be32(self.structs, self.offset)? as usize;
+                    let name_offset = be32(self.structs, self.offset + 4)? as usize;
+                    self.offset += 8;
+                    let value = self.structs.get(self.offset..self.offset + len)?;
+                    self.offset += len;
+                    self.align();
+                    let name = cstr(self.strings.get(name_offset..)?)?;
+                    return Some(Token::Prop(name, value));
+                }
+                FDT_NOP => continue,
+                // FDT_END, or a malformed blob
+                _ => return None,
+            }
+        }
+    }
+}
+
+/// The number in the first `cells` 32-bit cells of a property value.
+pub fn read_cells(value: &[u8], cells: usize) -> Option<usize> {
+    if cells == 0 || cells > 2 || value.len() < cells * 4 {
+        return None;
+    }
+    (0..cells).try_fold(0usize, |acc, i| {
+        Some(acc << 32 | be32(value, i * 4)? as usize)
+    })
+}
+
+/// The strings of a string list property such as `compatible`.
+pub fn strings(value: &[u8]) -> impl Iterator<Item = &str> {
+    value
+        .split(|&b| b == 0)
+        .filter(|s| !s.is_empty())
+        .filter_map(|s| core::str::from_utf8(s).ok())
+}
@@ -0,0 +1,128 @@
+use super::vfs::{lookup, split_path, Inode, InodeType, Stat, SEEK_CUR, SEEK_END, SEEK_SET};
+use super::File;
+use crate::errno::Errno;
+use crate::mm::UserBuffer;
+use alloc::sync::Arc;
+use spin::Mutex;
+
+bitflags! {
+    pub struct OpenFlags: u32 {
+        const RDONLY = 0;
+        const WRONLY = 1 << 0;
+        const RDWR = 1 << 1;
+        const CREATE = 1 << 9;
+        const TRUNC = 1 << 10;
+    }
+}
+
+impl OpenFlags {
+    /// Return (readable, writable)
+    pub fn read_write(&self) -> (bool, bool) {
+        if self.is_empty() {
+            (true, false)
+        } else if self.contains(Self::WRONLY) {
+            (false, true)
+        } else {
+            (true, true)
+        }
+    }
+}
+
+/// An opened inode with its own offset.
+pub struct OSInode {
+    readable: bool,
+    writable: bool,
+    inner: Mutex<OSInodeInner>,
+}
+
+struct OSInodeInner {
+    offset: usize,
+    inode: Arc<dyn Inode>,
+}
+
+impl OSInode {
+    pub fn new(readable: bool, writable: bool, inode: Arc<dyn Inode>) -> Self {
+        Self {
+            readable,
+            writable,
+            inner: Mutex::new(OSInodeInner { offset: 0, inode }),
+        }
+    }
+}
+
+pub fn open_file(path: &str, flags: OpenFlags) -> Option<Arc<OSInode>> {
+    let (readable, writable) = flags.read_write();
+    if let Some(inode) = lookup(path) {
+        if inode.kind() == InodeType::Directory && writable {
+            return None;
+        }
+        if flags.contains(OpenFlags::TRUNC) {
+            inode.clear();
+        }
+        Some(Arc::new(OSInode::new(readable, writable, inode)))
+    } else if flags.contains(OpenFlags::CREATE) {
+        let (parent, name) = split_path(path);
+        lookup(parent)
+            .and_then(|parent| parent.create(name, InodeType::File))
+            .map(|inode| Arc::new(OSInode::new(readable, writable, inode)))
+    } else {
+        None
+    }
+}
+
+impl File for OSInode {
+    fn readable(&self) -> bool {
+        self.readable
+    }
+    fn writable(&self) -> bool {
+        self.writable
+    }
+    fn read(&self, mut buf: UserBuffer) -> Result<usize, Errno> {
+        let mut inner = self.inner.lock();
+        let mut total_read_size = 0usize;
+        for slice in buf.buffers.iter_mut() {
+            let read_size = inner.inode.read_at(inner.offset, slice);
+            inner.offset += read_size;
+            total_read_size += read_size;
+            if read_size < slice.len() {
+                break;
+            }
+        }
+        Ok(total_read_size)
+    }
+    fn write(&self, buf: UserBuffer) -> Result<usize, Errno> {
+        let mut inner = self.inner.lock();
+        let mut total_write_size = 0usize;
+        for slice in buf.buffers.iter() {
+            let write_size = match inner.inode.write_at(inner.offset, slice) {
+                Ok(write_size) => write_size,
+                Err(errno) if total_write_size == 0 => return Err(errno),
+                Err(_) => break,
+            };
+            inner.offset += write_size;
+            total_write_size += write_size;
+            if write_size < slice.len() {
+                break;
+            }
+        }
+        Ok(total_write_size)
+    }
+    fn stat(&self) -> Result<Stat, Errno> {
+        Ok(self.inner.lock().inode.stat())
+    }
+    fn seek(&self, offset: isize, whence: usize) -> Result<usize, Errno> {
+        let mut inner = self.inner.lock();
+        let base = match whence {
+            SEEK_SET => 0,
+            SEEK_CUR => inner.offset as isize,
+            SEEK_END => inner.inode.stat().size as isize,
+            _ => return Err(Errno::EINVAL),
+        };
+        let new_offset = base + offset;
+        if new_offset < 0 {
+            return Err(Errno::EINVAL);
+        }
+        inner.offset = new_offset as usize;
+        Ok(inner.offset)
+    }
+}
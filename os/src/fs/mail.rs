@@ -4,6 +4,7 @@ use alloc::{
 };
 use spin::Mutex;
 
+use crate::errno::Errno;
 use crate::mm::UserBuffer;
 use crate::task::suspend_current_and_run_next;
 
@@ -48,7 +49,15 @@ impl MailBox {
 }
 
 impl File for MailBox {
-    fn read(&self, buf: UserBuffer) -> Result<usize, isize> {
+    fn readable(&self) -> bool {
+        true
+    }
+
+    fn writable(&self) -> bool {
+        false
+    }
+
+    fn read(&self, buf: UserBuffer) -> Result<usize, Errno> {
         let mut inner = self.inner.lock();
         match inner.mails.front() {
             Some(mail) => {
@@ -87,12 +96,12 @@ impl File for MailBox {
                     return Ok(read_size);
                 }
             }
-            None => Err(-1),
+            None => Err(Errno::EAGAIN),
         }
     }
 
-    fn write(&self, _buf: UserBuffer) -> Result<usize, isize> {
-        Err(-1)
+    fn write(&self, _buf: UserBuffer) -> Result<usize, Errno> {
+        Err(Errno::EBADF)
     }
 }
 
@@ -111,11 +120,19 @@ impl Socket {
 }
 
 impl File for Socket {
-    fn read(&self, _buf: UserBuffer) -> Result<usize, isize> {
-        Err(-1)
+    fn readable(&self) -> bool {
+        false
+    }
+
+    fn writable(&self) -> bool {
+        self.writable
+    }
+
+    fn read(&self, _buf: UserBuffer) -> Result<usize, Errno> {
+        Err(Errno::EBADF)
     }
 
-    fn write(&self, buf: UserBuffer) -> Result<usize, isize> {
+    fn write(&self, buf: UserBuffer) -> Result<usize, Errno> {
         debug!("socket try to write");
         assert!(self.writable);
         let mut buf_iter = buf.into_iter();
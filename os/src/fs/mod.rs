@@ -1,16 +1,31 @@
+mod inode;
 mod mail;
 mod pipe;
+mod ramfs;
 mod serial;
 mod stdio;
+mod vfs;
 
+use crate::errno::Errno;
 use crate::mm::UserBuffer;
 
 pub use mail::{MailBox, Socket};
 pub trait File: Send + Sync {
-    fn read(&self, buf: UserBuffer) -> Result<usize, isize>;
-    fn write(&self, buf: UserBuffer) -> Result<usize, isize>;
+    fn readable(&self) -> bool;
+    fn writable(&self) -> bool;
+    fn read(&self, buf: UserBuffer) -> Result<usize, Errno>;
+    fn write(&self, buf: UserBuffer) -> Result<usize, Errno>;
+    fn stat(&self) -> Result<Stat, Errno> {
+        Err(Errno::EINVAL)
+    }
+    /// Only regular files are seekable.
+    fn seek(&self, _offset: isize, _whence: usize) -> Result<usize, Errno> {
+        Err(Errno::ESPIPE)
+    }
 }
 
+pub use inode::{open_file, OSInode, OpenFlags};
 pub use pipe::{make_pipe, Pipe};
 pub use serial::Serial;
 pub use stdio::{Stdin, Stdout};
+pub use vfs::{list_root, lookup, Inode, InodeType, Stat, StatMode, ROOT_INODE};
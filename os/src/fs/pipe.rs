@@ -1,4 +1,5 @@
 use super::File;
+use crate::errno::Errno;
 use crate::mm::UserBuffer;
 use crate::task::suspend_current_and_run_next;
 use alloc::sync::{Arc, Weak};
@@ -105,7 +106,13 @@ pub fn make_pipe() -> (Arc<Pipe>, Arc<Pipe>) {
 }
 
 impl File for Pipe {
-    fn read(&self, buf: UserBuffer) -> Result<usize, isize> {
+    fn readable(&self) -> bool {
+        self.readable
+    }
+    fn writable(&self) -> bool {
+        self.writable
+    }
+    fn read(&self, buf: UserBuffer) -> Result<usize, Errno> {
         assert!(self.readable);
         let mut buf_iter = buf.into_iter();
         let mut read_size = 0usize;
@@ -133,7 +140,7 @@ impl File for Pipe {
             }
         }
     }
-    fn write(&self, buf: UserBuffer) -> Result<usize, isize> {
+    fn write(&self, buf: UserBuffer) -> Result<usize, Errno> {
         assert!(self.writable);
         let mut buf_iter = buf.into_iter();
         let mut write_size = 0usize;
@@ -0,0 +1,122 @@
+use super::vfs::{Inode, InodeType, Stat, StatMode};
+use crate::errno::Errno;
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Mutex;
+
+static NEXT_INO: AtomicUsize = AtomicUsize::new(1);
+
+/// Files live in the kernel heap, so keep them from eating all of it
+const MAX_FILE_SIZE: usize = 0x100_0000;
+
+/// An inode living entirely in kernel heap.
+pub struct RamInode {
+    ino: usize,
+    kind: InodeType,
+    inner: Mutex<RamInodeInner>,
+}
+
+struct RamInodeInner {
+    data: Vec<u8>,
+    children: BTreeMap<String, Arc<RamInode>>,
+}
+
+impl RamInode {
+    fn new(kind: InodeType) -> Self {
+        Self {
+            ino: NEXT_INO.fetch_add(1, Ordering::Relaxed),
+            kind,
+            inner: Mutex::new(RamInodeInner {
+                data: Vec::new(),
+                children: BTreeMap::new(),
+            }),
+        }
+    }
+
+    pub fn new_root() -> Arc<Self> {
+        Arc::new(Self::new(InodeType::Directory))
+    }
+}
+
+impl Inode for RamInode {
+    fn kind(&self) -> InodeType {
+        self.kind
+    }
+
+    fn stat(&self) -> Stat {
+        let inner = self.inner.lock();
+        match self.kind {
+            InodeType::File => Stat::new(self.ino, StatMode::FILE, inner.data.len()),
+            InodeType::Directory => Stat::new(self.ino, StatMode::DIR, inner.children.len()),
+        }
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        let inner = self.inner.lock();
+        if offset >= inner.data.len() {
+            return 0;
+        }
+        let end = inner.data.len().min(offset + buf.len());
+        buf[..end - offset].copy_from_slice(&inner.data[offset..end]);
+        end - offset
+    }
+
+    /// Writes past `MAX_FILE_SIZE` are cut short, or fail with EFBIG if
+    /// nothing fits.
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize, Errno> {
+        if self.kind != InodeType::File {
+            return Ok(0);
+        }
+        let end = offset.saturating_add(buf.len()).min(MAX_FILE_SIZE);
+        if offset >= end {
+            return if buf.is_empty() {
+                Ok(0)
+            } else {
+                Err(Errno::EFBIG)
+            };
+        }
+        let mut inner = self.inner.lock();
+        if end > inner.data.len() {
+            let additional = end - inner.data.len();
+            inner
+                .data
+                .try_reserve(additional)
+                .map_err(|_| Errno::ENOSPC)?;
+            inner.data.resize(end, 0);
+        }
+        inner.data[offset..end].copy_from_slice(&buf[..end - offset]);
+        Ok(end - offset)
+    }
+
+    fn clear(&self) {
+        self.inner.lock().data.clear();
+    }
+
+    fn find(&self, name: &str) -> Option<Arc<dyn Inode>> {
+        self.inner
+            .lock()
+            .children
+            .get(name)
+            .map(|inode| inode.clone() as Arc<dyn Inode>)
+    }
+
+    fn create(&self, name: &str, kind: InodeType) -> Option<Arc<dyn Inode>> {
+        if self.kind != InodeType::Directory || name.is_empty() {
+            return None;
+        }
+        let mut inner = self.inner.lock();
+        if inner.children.contains_key(name) {
+            return None;
+        }
+        let inode = Arc::new(RamInode::new(kind));
+        inner.children.insert(name.to_string(), inode.clone());
+        Some(inode)
+    }
+
+    fn ls(&self) -> Vec<String> {
+        self.inner.lock().children.keys().cloned().collect()
+    }
+}
@@ -1,13 +1,23 @@
 use embedded_hal::serial::Write;
 
 use super::File;
+use crate::errno::Errno;
 use crate::mm::UserBuffer;
-use crate::uart::{serial_getchar, BUFFERED_SERIAL};
+use crate::uart::{serial_getchar, serial_num, BUFFERED_SERIAL};
 
 pub struct Serial<const N: usize>;
 
 impl<const N: usize> File for Serial<N> {
-    fn read(&self, user_buf: UserBuffer) -> Result<usize, isize> {
+    fn readable(&self) -> bool {
+        true
+    }
+    fn writable(&self) -> bool {
+        true
+    }
+    fn read(&self, user_buf: UserBuffer) -> Result<usize, Errno> {
+        if N >= serial_num() {
+            return Err(Errno::ENODEV);
+        }
         let mut read_cnt = 0;
         let mut buf_iter = user_buf.into_iter();
         while let Some(ptr) = buf_iter.next() {
@@ -25,10 +35,13 @@ impl<const N: usize> File for Serial<N> {
         if read_cnt > 0 {
             Ok(read_cnt)
         } else {
-            Err(-1)
+            Err(Errno::EAGAIN)
         }
     }
-    fn write(&self, user_buf: UserBuffer) -> Result<usize, isize> {
+    fn write(&self, user_buf: UserBuffer) -> Result<usize, Errno> {
+        if N >= serial_num() {
+            return Err(Errno::ENODEV);
+        }
         let mut write_cnt = 0;
         let mut write_ok = true;
         let mut serial = BUFFERED_SERIAL[N].lock();
@@ -49,7 +62,7 @@ impl<const N: usize> File for Serial<N> {
         if write_cnt > 0 {
             Ok(write_cnt)
         } else {
-            Err(-1)
+            Err(Errno::EAGAIN)
         }
     }
 }
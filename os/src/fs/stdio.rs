@@ -1,6 +1,6 @@
 use super::File;
+use crate::errno::Errno;
 use crate::mm::UserBuffer;
-use crate::print;
 use crate::uart::{serial_getchar, serial_putchar};
 use core::fmt::{self, Write};
 
@@ -9,30 +9,48 @@ pub struct Stdin;
 pub struct Stdout;
 
 impl File for Stdin {
-    fn read(&self, mut user_buf: UserBuffer) -> Result<usize, isize> {
-        assert_eq!(user_buf.len(), 1);
-        // busy loop
+    fn readable(&self) -> bool {
+        true
+    }
+    fn writable(&self) -> bool {
+        false
+    }
+    fn read(&self, mut user_buf: UserBuffer) -> Result<usize, Errno> {
+        // at most one char each time
+        if user_buf.len() == 0 {
+            return Ok(0);
+        }
         if let Ok(ch) = serial_getchar(0) {
             unsafe {
                 user_buf.buffers[0].as_mut_ptr().write_volatile(ch);
             }
             Ok(1)
         } else {
-            Err(-1)
+            Err(Errno::EAGAIN)
         }
     }
-    fn write(&self, _user_buf: UserBuffer) -> Result<usize, isize> {
-        panic!("Cannot write to stdin!");
+    fn write(&self, _user_buf: UserBuffer) -> Result<usize, Errno> {
+        Err(Errno::EBADF)
     }
 }
 
 impl File for Stdout {
-    fn read(&self, _user_buf: UserBuffer) -> Result<usize, isize> {
-        panic!("Cannot read from stdout!");
+    fn readable(&self) -> bool {
+        false
     }
-    fn write(&self, user_buf: UserBuffer) -> Result<usize, isize> {
+    fn writable(&self) -> bool {
+        true
+    }
+    fn read(&self, _user_buf: UserBuffer) -> Result<usize, Errno> {
+        Err(Errno::EBADF)
+    }
+    fn write(&self, user_buf: UserBuffer) -> Result<usize, Errno> {
+        // raw bytes, user data need not be UTF-8 and a character may span
+        // two pages
         for buffer in user_buf.buffers.iter() {
-            print!("{}", core::str::from_utf8(*buffer).unwrap());
+            for &byte in buffer.iter() {
+                let _ = serial_putchar(0, byte);
+            }
         }
         Ok(user_buf.len())
     }
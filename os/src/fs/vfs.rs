@@ -0,0 +1,100 @@
+use super::ramfs::RamInode;
+use crate::errno::Errno;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use lazy_static::*;
+
+pub const SEEK_SET: usize = 0;
+pub const SEEK_CUR: usize = 1;
+pub const SEEK_END: usize = 2;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum InodeType {
+    File,
+    Directory,
+}
+
+bitflags! {
+    pub struct StatMode: u32 {
+        const NULL = 0;
+        const FIFO = 0o010000;
+        const CHR = 0o020000;
+        const DIR = 0o040000;
+        const FILE = 0o100000;
+    }
+}
+
+/// Layout shared with user space, see `user_lib::Stat`.
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct Stat {
+    pub dev: u64,
+    pub ino: u64,
+    pub mode: StatMode,
+    pub nlink: u32,
+    pub size: u64,
+    pad: [u64; 6],
+}
+
+impl Stat {
+    pub fn new(ino: usize, mode: StatMode, size: usize) -> Self {
+        Self {
+            dev: 0,
+            ino: ino as u64,
+            mode,
+            nlink: 1,
+            size: size as u64,
+            pad: [0; 6],
+        }
+    }
+}
+
+/// A node of the file system tree, independent of how it is stored.
+pub trait Inode: Send + Sync {
+    fn kind(&self) -> InodeType;
+    fn stat(&self) -> Stat;
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize;
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize, Errno>;
+    /// Drop all data of a file.
+    fn clear(&self);
+    fn find(&self, name: &str) -> Option<Arc<dyn Inode>>;
+    fn create(&self, name: &str, kind: InodeType) -> Option<Arc<dyn Inode>>;
+    fn ls(&self) -> Vec<String>;
+}
+
+lazy_static! {
+    pub static ref ROOT_INODE: Arc<dyn Inode> = RamInode::new_root();
+}
+
+/// Walk `path` from the root. There is no cwd yet, so relative paths are
+/// resolved from the root as well.
+pub fn lookup(path: &str) -> Option<Arc<dyn Inode>> {
+    path.split('/')
+        .filter(|name| !name.is_empty() && *name != ".")
+        .try_fold(ROOT_INODE.clone(), |inode, name| {
+            if inode.kind() == InodeType::Directory {
+                inode.find(name)
+            } else {
+                None
+            }
+        })
+}
+
+/// Split `path` into its parent directory and last component.
+pub fn split_path(path: &str) -> (&str, &str) {
+    let path = path.trim_end_matches('/');
+    match path.rfind('/') {
+        Some(idx) => (&path[..idx], &path[idx + 1..]),
+        None => ("", path),
+    }
+}
+
+#[allow(unused)]
+pub fn list_root() {
+    info!("/**** FILES ****");
+    for name in ROOT_INODE.ls() {
+        info!("{}", name);
+    }
+    info!("**************/")
+}
@@ -4,6 +4,7 @@
 #![feature(alloc_error_handler)]
 #![feature(map_first_last)]
 #![feature(map_try_insert)]
+#![feature(asm_const)]
 
 extern crate alloc;
 extern crate rv_plic;
@@ -13,12 +14,15 @@ extern crate bitflags;
 #[macro_use]
 extern crate log;
 
-use crate::{config::CPU_NUM, mm::init_kernel_space, sbi::send_ipi};
+use crate::mm::init_kernel_space;
 use core::arch::{asm, global_asm};
 
+mod board;
 #[macro_use]
 mod console;
 mod config;
+mod errno;
+mod fdt;
 #[macro_use]
 mod fs;
 mod lang_items;
@@ -35,7 +39,7 @@ mod trap;
 mod uart;
 mod trace;
 
-global_asm!(include_str!("entry.asm"));
+global_asm!(include_str!("entry.asm"), CPU_NUM = const config::CPU_NUM);
 global_asm!(include_str!("link_app.asm"));
 
 fn clear_bss() {
@@ -47,10 +51,14 @@ fn clear_bss() {
 }
 
 #[no_mangle]
-pub fn rust_main(hart_id: usize) -> ! {
+pub fn rust_main(hart_id: usize, dtb: usize) -> ! {
     if hart_id == 0 {
         clear_bss();
         logger::init();
+        // before the frames, which may hold the device tree
+        board::init(dtb);
+        assert!(board::hart_num() <= config::CPU_NUM);
+        sbi::init();
         mm::init();
         debug!("[kernel {}] Hello, world!", hart_id);
         mm::remap_test();
@@ -83,10 +91,12 @@ pub fn rust_main(hart_id: usize) -> ! {
             println_hart!("satp: {:#x}, sp: {:#x}", hart_id, satp, sp);
         }
 
-        for i in 1..CPU_NUM {
+        extern "C" {
+            fn _start();
+        }
+        for i in 1..board::hart_num() {
             debug!("[kernel {}] Start {}", hart_id, i);
-            let mask: usize = 1 << i;
-            send_ipi(&mask as *const _ as usize);
+            sbi::start_hart(i, _start as usize);
         }
     } else {
         let hart_id = task::hart_id();
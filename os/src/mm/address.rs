@@ -213,6 +213,9 @@ where
             || (self.l < other.r && other.r <= self.r)
             || (other.l < self.l && self.r < other.r)
     }
+    pub fn contains(&self, value: T) -> bool {
+        self.l <= value && value < self.r
+    }
 }
 impl<T> IntoIterator for SimpleRange<T>
 where
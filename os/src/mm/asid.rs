@@ -0,0 +1,96 @@
+use crate::config::CPU_NUM;
+use core::arch::asm;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use lazy_static::*;
+use riscv::asm::sfence_vma_all;
+use riscv::register::satp;
+use spin::Mutex;
+
+const ALL_HARTS: usize = (1 << CPU_NUM) - 1;
+
+/// ASID of an address space and the generation it was handed out in.
+/// When ASIDs run out a new generation starts, which takes back all of
+/// them, and a stale one is replaced before its address space runs again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Asid {
+    generation: usize,
+    id: usize,
+}
+
+impl Asid {
+    /// Of the kernel space. User spaces start with it too, but it never
+    /// belongs to the current generation, so they get their own first.
+    pub const KERNEL: Self = Self {
+        generation: 0,
+        id: 0,
+    };
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+}
+
+struct AsidAllocator {
+    /// Largest ASID of the harts, 0 if they have none and all address
+    /// spaces share ASID 0
+    max: usize,
+    next: usize,
+}
+
+static GENERATION: AtomicUsize = AtomicUsize::new(1);
+/// Harts that must drop their whole TLB before they run user code of the
+/// current generation
+static PENDING_FLUSH: AtomicUsize = AtomicUsize::new(0);
+
+lazy_static! {
+    static ref ASID_ALLOCATOR: Mutex<AsidAllocator> = Mutex::new(AsidAllocator { max: 0, next: 1 });
+}
+
+/// Find the writable ASID bits of satp, assuming all harts are alike.
+pub fn init() {
+    let old = satp::read().bits();
+    let max = unsafe {
+        asm!("csrw satp, {}", in(reg) old | 0xffff << 44);
+        let max = satp::read().bits() >> 44 & 0xffff;
+        satp::write(old);
+        sfence_vma_all();
+        max
+    };
+    ASID_ALLOCATOR.lock().max = max;
+    debug!("ASID max {}", max);
+}
+
+/// Run on `hart` before it switches to the address space of `asid`.
+/// Returns true if `asid` was stale and got replaced.
+pub fn refresh_asid(asid: &mut Asid, hart: usize) -> bool {
+    let mut replaced = false;
+    if asid.generation != GENERATION.load(Ordering::SeqCst) {
+        let mut allocator = ASID_ALLOCATOR.lock();
+        let mut generation = GENERATION.load(Ordering::SeqCst);
+        if asid.generation != generation {
+            if allocator.max == 0 {
+                *asid = Asid { generation, id: 0 };
+            } else {
+                if allocator.next > allocator.max {
+                    generation += 1;
+                    allocator.next = 1;
+                    GENERATION.store(generation, Ordering::SeqCst);
+                    // ASIDs of the last generation may still be cached
+                    PENDING_FLUSH.store(ALL_HARTS, Ordering::SeqCst);
+                }
+                *asid = Asid {
+                    generation,
+                    id: allocator.next,
+                };
+                allocator.next += 1;
+            }
+            replaced = true;
+        }
+    }
+    let bit = 1 << hart;
+    if PENDING_FLUSH.load(Ordering::SeqCst) & bit != 0 {
+        PENDING_FLUSH.fetch_and(!bit, Ordering::SeqCst);
+        unsafe { sfence_vma_all() }
+    }
+    replaced
+}
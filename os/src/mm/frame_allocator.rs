@@ -1,5 +1,7 @@
 use super::{PhysAddr, PhysPageNum};
-use crate::config::MEMORY_END;
+use crate::board::memory_end;
+use alloc::collections::BTreeMap;
+use alloc::vec;
 use alloc::vec::Vec;
 use core::fmt::{self, Debug, Formatter};
 use lazy_static::*;
@@ -35,54 +37,114 @@ impl Drop for FrameTracker {
 trait FrameAllocator {
     fn new() -> Self;
     fn alloc(&mut self) -> Option<PhysPageNum>;
+    /// `n` frames in a row, the first one aligned to `align` frames
+    fn alloc_contiguous(&mut self, n: usize, align: usize) -> Option<PhysPageNum>;
     fn dealloc(&mut self, ppn: PhysPageNum);
+    fn free_frames(&self) -> usize;
+    fn total_frames(&self) -> usize;
 }
 
-pub struct StackFrameAllocator {
-    current: usize,
-    end: usize,
-    recycled: Vec<usize>,
+/// One bit per frame, set while the frame is allocated.
+pub struct BitmapFrameAllocator {
+    base: usize,
+    total: usize,
+    free: usize,
+    bitmap: Vec<u64>,
+    /// Word to start the next single-frame search from
+    hint: usize,
 }
 
-impl StackFrameAllocator {
-    pub fn init(&mut self, l: PhysPageNum, r: PhysPageNum) {
-        self.current = l.0;
-        self.end = r.0;
-        debug!("last {} Physical Frames.", self.end - self.current);
+impl BitmapFrameAllocator {
+    /// `bitmap` must hold a zeroed bit for every frame in `[l, r)`.
+    pub fn init(&mut self, l: PhysPageNum, r: PhysPageNum, bitmap: Vec<u64>) {
+        self.base = l.0;
+        self.total = r.0 - l.0;
+        self.free = self.total;
+        self.bitmap = bitmap;
+        // bits past the end are never handed out
+        if self.total % 64 != 0 {
+            *self.bitmap.last_mut().unwrap() = !0 << (self.total % 64);
+        }
+        self.hint = 0;
+        debug!("last {} Physical Frames.", self.total);
+    }
+    fn is_used(&self, idx: usize) -> bool {
+        self.bitmap[idx / 64] & 1 << (idx % 64) != 0
+    }
+    fn set_used(&mut self, idx: usize, used: bool) {
+        if used {
+            self.bitmap[idx / 64] |= 1 << (idx % 64);
+        } else {
+            self.bitmap[idx / 64] &= !(1 << (idx % 64));
+        }
     }
 }
-impl FrameAllocator for StackFrameAllocator {
+
+impl FrameAllocator for BitmapFrameAllocator {
     fn new() -> Self {
         Self {
-            current: 0,
-            end: 0,
-            recycled: Vec::new(),
+            base: 0,
+            total: 0,
+            free: 0,
+            bitmap: Vec::new(),
+            hint: 0,
         }
     }
     fn alloc(&mut self) -> Option<PhysPageNum> {
-        if let Some(ppn) = self.recycled.pop() {
-            Some(ppn.into())
-        } else if self.current == self.end {
-            None
-        } else {
-            self.current += 1;
-            Some((self.current - 1).into())
+        let words = self.bitmap.len();
+        let word = (0..words)
+            .map(|i| (self.hint + i) % words)
+            .find(|w| self.bitmap[*w] != !0)?;
+        let idx = word * 64 + (!self.bitmap[word]).trailing_zeros() as usize;
+        self.set_used(idx, true);
+        self.free -= 1;
+        self.hint = word;
+        Some((self.base + idx).into())
+    }
+    fn alloc_contiguous(&mut self, n: usize, align: usize) -> Option<PhysPageNum> {
+        if n == 0 || !align.is_power_of_two() || n > self.free {
+            return None;
+        }
+        // align physical page numbers, not indices into the bitmap
+        let mut start = ((self.base + align - 1) & !(align - 1)) - self.base;
+        while start + n <= self.total {
+            match (start..start + n).rev().find(|idx| self.is_used(*idx)) {
+                Some(used) => {
+                    start = ((self.base + used + align) & !(align - 1)) - self.base;
+                }
+                None => {
+                    for idx in start..start + n {
+                        self.set_used(idx, true);
+                    }
+                    self.free -= n;
+                    return Some((self.base + start).into());
+                }
+            }
         }
+        None
     }
     fn dealloc(&mut self, ppn: PhysPageNum) {
         let ppn = ppn.0;
         // validity check
-        if ppn >= self.current || self.recycled.iter().any(|v| *v == ppn) {
+        if ppn < self.base || ppn >= self.base + self.total || !self.is_used(ppn - self.base) {
             panic!("Frame ppn={:#x} has not been allocated!", ppn);
         }
-        // recycle
-        self.recycled.push(ppn);
+        self.set_used(ppn - self.base, false);
+        self.free += 1;
+    }
+    fn free_frames(&self) -> usize {
+        self.free
+    }
+    fn total_frames(&self) -> usize {
+        self.total
     }
 }
 
-type FrameAllocatorImpl = StackFrameAllocator;
+type FrameAllocatorImpl = BitmapFrameAllocator;
 
 lazy_static! {
+    /// Lock order: the kernel heap, then this, since the heap grows by
+    /// frames. Nothing may allocate from the heap while holding it.
     pub static ref FRAME_ALLOCATOR: Mutex<FrameAllocatorImpl> =
         Mutex::new(FrameAllocatorImpl::new());
 }
@@ -91,20 +153,91 @@ pub fn init_frame_allocator() {
     extern "C" {
         fn ekernel();
     }
-    FRAME_ALLOCATOR.lock().init(
-        PhysAddr::from(ekernel as usize).ceil(),
-        PhysAddr::from(MEMORY_END).floor(),
-    );
+    let l = PhysAddr::from(ekernel as usize).ceil();
+    let r = PhysAddr::from(memory_end()).floor();
+    let bitmap = vec![0; (r.0 - l.0 + 63) / 64];
+    FRAME_ALLOCATOR.lock().init(l, r, bitmap);
 }
 
 pub fn frame_alloc() -> Option<FrameTracker> {
     FRAME_ALLOCATOR.lock().alloc().map(FrameTracker::new)
 }
 
+/// `n` physically contiguous frames, the first aligned to `align` frames,
+/// e.g. for DMA buffers or megapages.
+#[allow(unused)]
+pub fn frame_alloc_contiguous(n: usize, align: usize) -> Option<Vec<FrameTracker>> {
+    let start = FRAME_ALLOCATOR.lock().alloc_contiguous(n, align)?;
+    Some(
+        (start.0..start.0 + n)
+            .map(|ppn| FrameTracker::new(ppn.into()))
+            .collect(),
+    )
+}
+
+/// `n` frames aligned to `n` for the kernel heap, which owns them for good.
+pub(super) fn frame_alloc_heap(n: usize) -> Option<PhysPageNum> {
+    FRAME_ALLOCATOR.lock().alloc_contiguous(n, n)
+}
+
 fn frame_dealloc(ppn: PhysPageNum) {
+    let mut pinned = PINNED_FRAMES.lock();
+    if let Some(frame) = pinned.get_mut(&ppn.0) {
+        frame.dropped = true;
+        return;
+    }
     FRAME_ALLOCATOR.lock().dealloc(ppn);
 }
 
+struct PinnedFrame {
+    pins: usize,
+    /// Its `FrameTracker` is gone, free it with the last pin
+    dropped: bool,
+}
+
+lazy_static! {
+    /// Lock order: this, then the kernel heap and `FRAME_ALLOCATOR`.
+    static ref PINNED_FRAMES: Mutex<BTreeMap<usize, PinnedFrame>> = Mutex::new(BTreeMap::new());
+}
+
+/// Keeps a frame from being freed and handed out again while the kernel
+/// copies from or to it. Unlike an `Arc<FrameTracker>` it does not count as
+/// an owner, so copy-on-write and shared memory do not see it.
+pub struct FramePin(PhysPageNum);
+
+impl FramePin {
+    /// `ppn` must belong to a live `FrameTracker`.
+    pub fn new(ppn: PhysPageNum) -> Self {
+        PINNED_FRAMES
+            .lock()
+            .entry(ppn.0)
+            .or_insert(PinnedFrame {
+                pins: 0,
+                dropped: false,
+            })
+            .pins += 1;
+        Self(ppn)
+    }
+}
+
+impl Drop for FramePin {
+    fn drop(&mut self) {
+        let mut pinned = PINNED_FRAMES.lock();
+        let frame = pinned.get_mut(&self.0 .0).unwrap();
+        frame.pins -= 1;
+        if frame.pins == 0 && pinned.remove(&self.0 .0).unwrap().dropped {
+            FRAME_ALLOCATOR.lock().dealloc(self.0);
+        }
+    }
+}
+
+/// Free and used frame counts.
+pub fn frame_usage() -> (usize, usize) {
+    let allocator = FRAME_ALLOCATOR.lock();
+    let free = allocator.free_frames();
+    (free, allocator.total_frames() - free)
+}
+
 #[allow(unused)]
 pub fn frame_allocator_test() {
     let mut v: Vec<FrameTracker> = Vec::new();
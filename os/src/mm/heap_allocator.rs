@@ -1,12 +1,97 @@
-use crate::config::KERNEL_HEAP_SIZE;
-use buddy_system_allocator::LockedHeap;
+use super::frame_allocator::frame_alloc_heap;
+use super::PhysAddr;
+use crate::config::{KERNEL_HEAP_GROW_PAGES, KERNEL_HEAP_SIZE, PAGE_SIZE};
+use buddy_system_allocator::Heap;
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::{null_mut, NonNull};
+use spin::Mutex;
+
+/// Starts on a static area and grows by frames taken from the frame
+/// allocator, reached through the identity mapping of physical memory.
+/// Grown frames are never given back. The heap lock is taken before
+/// `FRAME_ALLOCATOR`, never after.
+struct GrowableHeap(Mutex<HeapInner>);
+
+struct HeapInner {
+    heap: Heap,
+    peak: usize,
+    failures: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct HeapStats {
+    /// Bytes managed by the heap
+    pub total: usize,
+    /// Bytes requested by live allocations
+    pub allocated: usize,
+    pub peak: usize,
+    /// Allocations that failed even after growing
+    pub failures: usize,
+}
+
+impl HeapInner {
+    /// Add frames for at least `layout`, aligned to their own size so that
+    /// the buddy allocator can carve a block that large out of them.
+    fn grow(&mut self, layout: &Layout) -> bool {
+        let need = layout.size().max(layout.align());
+        let pages = ((need + PAGE_SIZE - 1) / PAGE_SIZE).next_power_of_two();
+        let step = pages.max(KERNEL_HEAP_GROW_PAGES);
+        let (ppn, n) = match frame_alloc_heap(step) {
+            Some(ppn) => (ppn, step),
+            None => match frame_alloc_heap(pages) {
+                Some(ppn) => (ppn, pages),
+                None => return false,
+            },
+        };
+        let start = usize::from(PhysAddr::from(ppn));
+        unsafe {
+            self.heap.add_to_heap(start, start + n * PAGE_SIZE);
+        }
+        true
+    }
+}
+
+unsafe impl GlobalAlloc for GrowableHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut inner = self.0.lock();
+        let mut result = inner.heap.alloc(layout);
+        if result.is_err() && inner.grow(&layout) {
+            result = inner.heap.alloc(layout);
+        }
+        match result {
+            Ok(ptr) => {
+                inner.peak = inner.peak.max(inner.heap.stats_alloc_user());
+                ptr.as_ptr()
+            }
+            Err(_) => {
+                inner.failures += 1;
+                null_mut()
+            }
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.0
+            .lock()
+            .heap
+            .dealloc(NonNull::new_unchecked(ptr), layout);
+    }
+}
 
 #[global_allocator]
-static HEAP_ALLOCATOR: LockedHeap = LockedHeap::empty();
+static HEAP_ALLOCATOR: GrowableHeap = GrowableHeap(Mutex::new(HeapInner {
+    heap: Heap::empty(),
+    peak: 0,
+    failures: 0,
+}));
 
 #[alloc_error_handler]
 pub fn handle_alloc_error(layout: core::alloc::Layout) -> ! {
-    panic!("Heap allocation error, layout = {:?}", layout);
+    panic!(
+        "Heap allocation error, layout = {:?}, {:?}",
+        layout,
+        heap_stats()
+    );
 }
 
 static mut HEAP_SPACE: [u8; KERNEL_HEAP_SIZE] = [0; KERNEL_HEAP_SIZE];
@@ -14,11 +99,23 @@ static mut HEAP_SPACE: [u8; KERNEL_HEAP_SIZE] = [0; KERNEL_HEAP_SIZE];
 pub fn init_heap() {
     unsafe {
         HEAP_ALLOCATOR
+            .0
             .lock()
+            .heap
             .init(HEAP_SPACE.as_ptr() as usize, KERNEL_HEAP_SIZE);
     }
 }
 
+pub fn heap_stats() -> HeapStats {
+    let inner = HEAP_ALLOCATOR.0.lock();
+    HeapStats {
+        total: inner.heap.stats_total_bytes(),
+        allocated: inner.heap.stats_alloc_user(),
+        peak: inner.peak,
+        failures: inner.failures,
+    }
+}
+
 #[allow(unused)]
 pub fn heap_test() {
     use alloc::boxed::Box;
@@ -1,8 +1,16 @@
-use super::{frame_alloc, FrameTracker};
+use super::asid::{refresh_asid, Asid};
+use super::shm::{shm_release, shm_retain};
+use super::{frame_alloc, frame_usage, FramePin, FrameTracker};
 use super::{PTEFlags, PageTable, PageTableEntry};
 use super::{PhysAddr, PhysPageNum, VirtAddr, VirtPageNum};
 use super::{StepByOne, VPNRange};
-use crate::config::{MEMORY_END, PAGE_SIZE, TRACE_SIZE, TRAMPOLINE, TRAP_CONTEXT, USER_STACK_SIZE};
+use crate::board::{board, memory_end};
+use crate::config::{PAGE_SIZE, TRAMPOLINE, TRAP_CONTEXT, USER_STACK_SIZE, USER_TRAP_BUFFER};
+#[cfg(feature = "trace")]
+use crate::config::{TRACE_BASE, TRACE_SIZE};
+use crate::errno::Errno;
+use crate::sbi;
+use crate::task::hart_id;
 use alloc::collections::BTreeMap;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
@@ -12,6 +20,24 @@ use riscv::asm::sfence_vma_all;
 use riscv::register::satp;
 use spin::Mutex;
 
+/// Larger flushes drop the whole address space on the local hart
+const LOCAL_FLUSH_PAGES: usize = 16;
+
+/// Flush `asid` in range on the local hart and remotely on `harts`.
+fn flush_asid(harts: usize, start: usize, size: usize, asid: usize) {
+    if size / PAGE_SIZE <= LOCAL_FLUSH_PAGES {
+        for va in (start..start + size).step_by(PAGE_SIZE) {
+            unsafe { asm!("sfence.vma {}, {}", in(reg) va, in(reg) asid) }
+        }
+    } else {
+        unsafe { asm!("sfence.vma zero, {}", in(reg) asid) }
+    }
+    let others = harts & !(1 << hart_id());
+    if others != 0 {
+        sbi::remote_sfence_vma_asid(others, start, size, asid);
+    }
+}
+
 extern "C" {
     fn stext();
     fn etext();
@@ -33,17 +59,64 @@ lazy_static! {
 pub struct MemorySet {
     page_table: PageTable,
     areas: Vec<MapArea>,
+    /// Start of the heap, above the user stack
+    heap_bottom: usize,
+    /// Program break, the end of the heap
+    brk: usize,
+    asid: Asid,
+    /// Harts that have run this address space and may cache its
+    /// translations
+    harts: usize,
+    /// ASID replaced by a new generation, and the harts that may still
+    /// run this address space under it
+    stale_asid: usize,
+    stale_harts: usize,
 }
 
 impl MemorySet {
-    pub fn new_bare() -> Self {
-        Self {
-            page_table: PageTable::new(),
+    pub fn new_bare() -> Result<Self, Errno> {
+        Ok(Self {
+            page_table: PageTable::new()?,
             areas: Vec::new(),
-        }
+            heap_bottom: 0,
+            brk: 0,
+            asid: Asid::KERNEL,
+            harts: 0,
+            stale_asid: 0,
+            stale_harts: 0,
+        })
     }
     pub fn token(&self) -> usize {
-        self.page_table.token()
+        self.page_table.token() | self.asid.id() << 44
+    }
+    /// Record that `hart` is about to run this address space, returns the
+    /// satp value to run it with.
+    pub fn enter_user(&mut self, hart: usize) -> usize {
+        let old_asid = self.asid.id();
+        if refresh_asid(&mut self.asid, hart) && self.harts != 0 {
+            // other threads keep the old ASID until they trap
+            self.stale_asid = old_asid;
+            self.stale_harts |= self.harts;
+        }
+        self.stale_harts &= !(1 << hart);
+        self.harts |= 1 << hart;
+        self.token()
+    }
+    /// Drop translations of `[start, start + size)` from every hart that
+    /// may cache them, once mappings there are removed or downgraded. A
+    /// `size` of `usize::MAX` drops the whole address space.
+    fn flush_tlb(&self, start: VirtAddr, size: usize) {
+        let start = usize::from(start);
+        flush_asid(self.harts, start, size, self.asid.id());
+        if self.stale_harts != 0 {
+            flush_asid(self.stale_harts, start, size, self.stale_asid);
+        }
+    }
+    /// Drop cached translations of `[start_va, end_va)` on every hart,
+    /// also needed for new mappings in kernel space, since traps switch to
+    /// it without a full flush and a hart may have cached them as invalid.
+    pub fn flush_range(&self, start_va: VirtAddr, end_va: VirtAddr) {
+        self.flush_tlb(start_va, usize::from(end_va) - usize::from(start_va));
     }
     /// Assume that no conflicts.
     pub fn insert_framed_area(
@@ -51,11 +124,22 @@ impl MemorySet {
         start_va: VirtAddr,
         end_va: VirtAddr,
         permission: MapPermission,
-    ) {
+    ) -> Result<(), Errno> {
         self.push(
             MapArea::new(start_va, end_va, MapType::Framed, permission),
             None,
-        );
+        )
+    }
+    /// Frames are allocated on the first page fault.
+    pub fn insert_lazy_area(
+        &mut self,
+        start_va: VirtAddr,
+        end_va: VirtAddr,
+        permission: MapPermission,
+    ) {
+        let mut map_area = MapArea::new(start_va, end_va, MapType::Framed, permission);
+        map_area.lazy = true;
+        self.areas.push(map_area);
     }
     pub fn remove_area_with_start_vpn(&mut self, start_vpn: VirtPageNum) {
         if let Some((idx, area)) = self
@@ -65,29 +149,37 @@ impl MemorySet {
             .find(|(_, area)| area.vpn_range.get_start() == start_vpn)
         {
             area.unmap(&mut self.page_table);
-            self.areas.remove(idx);
+            let end_va: VirtAddr = area.vpn_range.get_end().into();
+            // frames go back only after no hart can reach them
+            let area = self.areas.remove(idx);
+            self.flush_tlb(
+                start_vpn.into(),
+                usize::from(end_va) - usize::from(VirtAddr::from(start_vpn)),
+            );
+            drop(area);
         }
     }
-    fn push(&mut self, mut map_area: MapArea, data: Option<&[u8]>) {
-        map_area.map(&mut self.page_table);
+    fn push(&mut self, mut map_area: MapArea, data: Option<&[u8]>) -> Result<(), Errno> {
+        map_area.map(&mut self.page_table)?;
         if let Some(data) = data {
             map_area.copy_data(&mut self.page_table, data);
         }
         self.areas.push(map_area);
+        Ok(())
     }
     /// Mention that trampoline is not collected by areas.
-    fn map_trampoline(&mut self) {
+    fn map_trampoline(&mut self) -> Result<(), Errno> {
         self.page_table.map(
             VirtAddr::from(TRAMPOLINE).into(),
             PhysAddr::from(strampoline as usize).into(),
             PTEFlags::R | PTEFlags::X,
-        );
+        )
     }
     /// Without kernel stacks.
     pub fn new_kernel() -> Self {
-        let mut memory_set = Self::new_bare();
+        let mut memory_set = Self::new_bare().unwrap();
         // map trampoline
-        memory_set.map_trampoline();
+        memory_set.map_trampoline().unwrap();
         // map kernel sections
         debug!(".text [{:#x}, {:#x})", stext as usize, etext as usize);
         debug!(".rodata [{:#x}, {:#x})", srodata as usize, erodata as usize);
@@ -97,98 +189,117 @@ impl MemorySet {
             sbss_with_stack as usize, ebss as usize
         );
         debug!("mapping .text section");
-        memory_set.push(
-            MapArea::new(
-                (stext as usize).into(),
-                (etext as usize).into(),
-                MapType::Identical,
-                MapPermission::R | MapPermission::X,
-            ),
-            None,
-        );
+        memory_set
+            .push(
+                MapArea::new(
+                    (stext as usize).into(),
+                    (etext as usize).into(),
+                    MapType::Identical,
+                    MapPermission::R | MapPermission::X,
+                ),
+                None,
+            )
+            .unwrap();
         debug!("mapping .rodata section");
-        memory_set.push(
-            MapArea::new(
-                (srodata as usize).into(),
-                (erodata as usize).into(),
-                MapType::Identical,
-                MapPermission::R,
-            ),
-            None,
-        );
+        memory_set
+            .push(
+                MapArea::new(
+                    (srodata as usize).into(),
+                    (erodata as usize).into(),
+                    MapType::Identical,
+                    MapPermission::R,
+                ),
+                None,
+            )
+            .unwrap();
         debug!("mapping .data section");
-        memory_set.push(
-            MapArea::new(
-                (sdata as usize).into(),
-                (edata as usize).into(),
-                MapType::Identical,
-                MapPermission::R | MapPermission::W,
-            ),
-            None,
-        );
+        memory_set
+            .push(
+                MapArea::new(
+                    (sdata as usize).into(),
+                    (edata as usize).into(),
+                    MapType::Identical,
+                    MapPermission::R | MapPermission::W,
+                ),
+                None,
+            )
+            .unwrap();
         debug!("mapping .bss section");
-        memory_set.push(
-            MapArea::new(
-                (sbss_with_stack as usize).into(),
-                (ebss as usize).into(),
-                MapType::Identical,
-                MapPermission::R | MapPermission::W,
-            ),
-            None,
-        );
+        memory_set
+            .push(
+                MapArea::new(
+                    (sbss_with_stack as usize).into(),
+                    (ebss as usize).into(),
+                    MapType::Identical,
+                    MapPermission::R | MapPermission::W,
+                ),
+                None,
+            )
+            .unwrap();
         debug!("mapping physical memory");
-        memory_set.push(
-            MapArea::new(
-                (ekernel as usize).into(),
-                MEMORY_END.into(),
-                MapType::Identical,
-                MapPermission::R | MapPermission::W,
-            ),
-            None,
-        );
+        memory_set
+            .push(
+                MapArea::new(
+                    (ekernel as usize).into(),
+                    memory_end().into(),
+                    MapType::Identical,
+                    MapPermission::R | MapPermission::W,
+                ),
+                None,
+            )
+            .unwrap();
         debug!("mapping plic");
-        memory_set.push(
-            MapArea::new(
-                (0xc00_0000 as usize).into(),
-                (0x1000_0000 as usize).into(),
-                MapType::Mmio,
-                MapPermission::R | MapPermission::W,
-            ),
-            None,
-        );
+        let plic = &board().plic;
+        memory_set
+            .push(
+                MapArea::new(
+                    plic.base.into(),
+                    (plic.base + plic.size).into(),
+                    MapType::Mmio,
+                    MapPermission::R | MapPermission::W,
+                ),
+                None,
+            )
+            .unwrap();
         debug!("mapping uart");
-        use crate::uart;
         #[cfg(any(feature = "board_qemu", feature = "board_lrv"))]
-        memory_set.push(
-            MapArea::new(
-                (uart::SERIAL_BASE_ADDRESS).into(),
-                (uart::SERIAL_BASE_ADDRESS + uart::SERIAL_NUM * uart::SERIAL_ADDRESS_STRIDE).into(),
-                MapType::Mmio,
-                MapPermission::R | MapPermission::W,
-            ),
-            None,
-        );
+        for serial in board().serials.iter() {
+            memory_set
+                .push(
+                    MapArea::new(
+                        serial.base.into(),
+                        (serial.base + serial.size).into(),
+                        MapType::Mmio,
+                        MapPermission::R | MapPermission::W,
+                    ),
+                    None,
+                )
+                .unwrap();
+        }
         debug!("mapping trace");
-        memory_set.push(
-            MapArea::new(
-                MEMORY_END.into(),
-                (MEMORY_END + TRACE_SIZE).into(),
-                MapType::Mmio,
-                MapPermission::R | MapPermission::W,
-            ),
-            None,
-        );
+        #[cfg(feature = "trace")]
+        memory_set
+            .push(
+                MapArea::new(
+                    TRACE_BASE.into(),
+                    (TRACE_BASE + TRACE_SIZE).into(),
+                    MapType::Mmio,
+                    MapPermission::R | MapPermission::W,
+                ),
+                None,
+            )
+            .unwrap();
         unsafe { asm!("fence.i") }
         memory_set
     }
     /// Include sections in elf and trampoline and TrapContext and user stack,
     /// also returns user_sp and entry point.
-    pub fn from_elf(elf_data: &[u8]) -> (Self, usize, usize) {
-        let mut memory_set = Self::new_bare();
+    pub fn from_elf(elf_data: &[u8]) -> Result<(Self, usize, usize), Errno> {
+        let mut memory_set = Self::new_bare()?;
         // map trampoline
-        memory_set.map_trampoline();
+        memory_set.map_trampoline()?;
         // map program headers of elf, with U flag
-        let elf = xmas_elf::ElfFile::new(elf_data).unwrap();
+        let elf = xmas_elf::ElfFile::new(elf_data).map_err(|_| Errno::ENOEXEC)?;
         let elf_header = elf.header;
         let magic = elf_header.pt1.magic;
         assert_eq!(magic, [0x7f, 0x45, 0x4c, 0x46], "invalid elf!");
@@ -215,7 +326,7 @@ impl MemorySet {
                 memory_set.push(
                     map_area,
                     Some(&elf.input[ph.offset() as usize..(ph.offset() + ph.file_size()) as usize]),
-                );
+                )?;
             }
         }
         // map user stack with U flags
@@ -232,7 +343,10 @@ impl MemorySet {
                 MapPermission::R | MapPermission::W | MapPermission::U,
             ),
             None,
-        );
+        )?;
+        // the heap starts empty above the user stack and its guard page
+        memory_set.heap_bottom = user_stack_top + PAGE_SIZE;
+        memory_set.brk = memory_set.heap_bottom;
         // map TrapContext
         memory_set.push(
             MapArea::new(
@@ -242,32 +356,46 @@ impl MemorySet {
                 MapPermission::R | MapPermission::W,
             ),
             None,
-        );
+        )?;
         // map trace
+        #[cfg(feature = "trace")]
         memory_set.push(
             MapArea::new(
-                MEMORY_END.into(),
-                (MEMORY_END + TRACE_SIZE).into(),
+                TRACE_BASE.into(),
+                (TRACE_BASE + TRACE_SIZE).into(),
                 MapType::Mmio,
                 MapPermission::R | MapPermission::W | MapPermission::U,
             ),
             None,
-        );
+        )?;
         unsafe { asm!("fence.i") }
-        (
+        Ok((
             memory_set,
             user_stack_top,
             elf.header.pt2.entry_point() as usize,
-        )
+        ))
     }
-    pub fn from_existed_user(user_space: &MemorySet) -> MemorySet {
-        let mut memory_set = Self::new_bare();
+    /// User pages are shared copy-on-write, pages only the kernel writes
+    /// (trap contexts and the user trap buffer) are copied.
+    pub fn from_existed_user(user_space: &mut MemorySet) -> Result<MemorySet, Errno> {
+        let mut memory_set = Self::new_bare()?;
+        memory_set.heap_bottom = user_space.heap_bottom;
+        memory_set.brk = user_space.brk;
         // map trampoline
-        memory_set.map_trampoline();
-        // copy data sections/trap_context/user_stack
+        memory_set.map_trampoline()?;
+        let user_trap_buffer: VirtPageNum = VirtAddr::from(USER_TRAP_BUFFER).floor();
         for area in user_space.areas.iter() {
+            if matches!(area.map_type, MapType::Framed | MapType::Shared(_))
+                && area.map_perm.contains(MapPermission::U)
+                && area.vpn_range.get_start() != user_trap_buffer
+            {
+                let new_area =
+                    area.share(&mut user_space.page_table, &mut memory_set.page_table)?;
+                memory_set.areas.push(new_area);
+                continue;
+            }
             let new_area = MapArea::from_another(area);
-            memory_set.push(new_area, None);
+            memory_set.push(new_area, None)?;
             // copy data from another space
             if area.map_type != MapType::Mmio {
                 for vpn in area.vpn_range {
@@ -279,19 +407,139 @@ impl MemorySet {
                 }
             }
         }
+        // writable pages of the parent became read-only
+        user_space.flush_tlb(VirtAddr::from(0), usize::MAX);
         unsafe { asm!("fence.i") }
-        memory_set
+        Ok(memory_set)
+    }
+    /// Map a lazy page on first touch or copy a copy-on-write page on the
+    /// first store. Fails with EFAULT if `access` is not allowed at `vpn`,
+    /// or ENOMEM if no frame is left for it.
+    pub fn handle_page_fault(
+        &mut self,
+        vpn: VirtPageNum,
+        access: MapPermission,
+    ) -> Result<(), Errno> {
+        let wanted = PTEFlags::from_bits(access.bits() as u16).unwrap() | PTEFlags::U;
+        match self.page_table.translate(vpn) {
+            // mapped meanwhile, by another hart or before a stale TLB entry
+            Some(pte) if pte.is_valid() && pte.flags().contains(wanted) => {
+                let va: usize = VirtAddr::from(vpn).into();
+                unsafe { asm!("sfence.vma {}, zero", in(reg) va) }
+                return Ok(());
+            }
+            _ => {}
+        }
+        let page_table = &mut self.page_table;
+        let area = self
+            .areas
+            .iter_mut()
+            .find(|area| {
+                area.map_type == MapType::Framed
+                    && area.map_perm.contains(MapPermission::U)
+                    && area.vpn_range.contains(vpn)
+            })
+            .ok_or(Errno::EFAULT)?;
+        if !area.map_perm.contains(access) {
+            return Err(Errno::EFAULT);
+        }
+        match page_table.translate(vpn) {
+            Some(pte) if pte.is_valid() => {
+                if pte.is_cow() && access.contains(MapPermission::W) {
+                    area.copy_on_write(page_table, vpn)?;
+                    // other threads may still read the old frame
+                    self.flush_tlb(vpn.into(), PAGE_SIZE);
+                    return Ok(());
+                }
+                // otherwise another thread resolved it first
+            }
+            _ => area.map_one(page_table, vpn)?,
+        }
+        let va: usize = VirtAddr::from(vpn).into();
+        unsafe { asm!("sfence.vma {}, zero", in(reg) va) }
+        Ok(())
     }
-    pub fn activate(&self) {
-        let satp = self.page_table.token();
+    /// Map the frames of shared memory region `id` at `start`.
+    pub fn shm_map(
+        &mut self,
+        start: usize,
+        id: usize,
+        frames: Vec<Arc<FrameTracker>>,
+        port: usize,
+    ) -> Result<isize, Errno> {
+        if port & !7 != 0 || port & 7 == 0 {
+            return Err(Errno::EINVAL);
+        }
+        let start_va: VirtAddr = VirtAddr::from(start);
+        if start_va != start_va.floor().into() {
+            return Err(Errno::EINVAL);
+        }
+        let end_va: VirtAddr = frames
+            .len()
+            .checked_mul(PAGE_SIZE)
+            .and_then(|len| start.checked_add(len))
+            .ok_or(Errno::EINVAL)?
+            .into();
+        if self.is_mapped_area(start_va, end_va) {
+            return Err(Errno::EEXIST);
+        }
+        let mut map_area = MapArea::new(
+            start_va,
+            end_va,
+            MapType::Shared(id),
+            MapPermission::from_bits((port << 1 | 0b10000) as u8).unwrap(),
+        );
+        for (vpn, frame) in map_area.vpn_range.into_iter().zip(frames) {
+            map_area.data_frames.insert(vpn, frame);
+        }
+        self.push(map_area, None)?;
+        Ok(start as isize)
+    }
+    /// Unmap the shared memory region mapped at `start`.
+    pub fn shm_unmap(&mut self, start: usize) -> Result<isize, Errno> {
+        let start_va: VirtAddr = VirtAddr::from(start);
+        if start_va != start_va.floor().into() {
+            return Err(Errno::EINVAL);
+        }
+        let idx = self
+            .areas
+            .iter()
+            .position(|area| {
+                matches!(area.map_type, MapType::Shared(_))
+                    && area.vpn_range.get_start() == start_va.floor()
+            })
+            .ok_or(Errno::EINVAL)?;
+        let mut area = self.areas.remove(idx);
+        area.unmap(&mut self.page_table);
+        let end_va: VirtAddr = area.vpn_range.get_end().into();
+        self.flush_tlb(start_va, usize::from(end_va) - start);
+        drop(area);
+        Ok(0)
+    }
+    /// Frames mapped by user areas, shared ones included.
+    pub fn resident_pages(&self) -> usize {
+        self.areas.iter().map(|area| area.data_frames.len()).sum()
+    }
+    /// Switch the current hart to this space, only used for the kernel.
+    pub fn activate(&mut self) {
+        let satp = self.token();
         unsafe {
             satp::write(satp);
             sfence_vma_all();
         }
+        self.harts |= 1 << hart_id();
     }
     pub fn translate(&self, vpn: VirtPageNum) -> Option<PageTableEntry> {
         self.page_table.translate(vpn)
     }
+    /// Pin the frame mapped at `vpn`, none for identical and MMIO pages.
+    pub fn pin_frame(&self, vpn: VirtPageNum) -> Option<FramePin> {
+        self.areas
+            .iter()
+            .find(|area| area.vpn_range.contains(vpn))
+            .and_then(|area| area.data_frames.get(&vpn))
+            .map(|frame| FramePin::new(frame.ppn))
+    }
 
     fn is_mapped_area(&self, start_va: VirtAddr, end_va: VirtAddr) -> bool {
         for area in &self.areas {
@@ -305,20 +553,25 @@ impl MemorySet {
         false
     }
 
-    pub fn mmap(&mut self, start: usize, len: usize, port: usize) -> Result<isize, isize> {
+    pub fn mmap(&mut self, start: usize, len: usize, port: usize) -> Result<isize, Errno> {
         if port & !7 != 0 || port & 7 == 0 || len > 1 << 30 {
-            Err(-1)
+            Err(Errno::EINVAL)
         } else {
             let start_va: VirtAddr = VirtAddr::from(start);
             if start_va != start_va.floor().into() {
-                return Err(-1);
+                return Err(Errno::EINVAL);
             }
             let end_va: VirtAddr = VirtAddr::from(start + len).ceil().into();
 
             if self.is_mapped_area(start_va, end_va) {
-                return Err(-1);
+                return Err(Errno::EEXIST);
             }
-            self.insert_framed_area(
+            // pages come on demand, but refuse what could never be backed
+            let (free_frames, _) = frame_usage();
+            if (usize::from(end_va) - usize::from(start_va)) / PAGE_SIZE > free_frames {
+                return Err(Errno::ENOMEM);
+            }
+            self.insert_lazy_area(
                 start_va,
                 end_va,
                 MapPermission::from_bits((port << 1 | 0b10000) as u8).unwrap(),
@@ -328,59 +581,189 @@ impl MemorySet {
         }
     }
 
-    pub fn munmap(&mut self, start: usize, len: usize) -> Result<isize, isize> {
-        let mut start_va: VirtAddr = VirtAddr::from(start);
-        if start_va != start_va.floor().into() {
-            return Err(-1);
+    /// Split areas so that [start, end) is made of whole areas, returns
+    /// their indices. Fails with EINVAL if part of the range is unmapped.
+    fn split_range(&mut self, start: VirtPageNum, end: VirtPageNum) -> Result<Vec<usize>, Errno> {
+        let range = VPNRange::new(start, end);
+        let mut overlapped: Vec<usize> = (0..self.areas.len())
+            .filter(|i| self.areas[*i].vpn_range.is_overlapped(&range))
+            .collect();
+        overlapped.sort_by_key(|i| self.areas[*i].vpn_range.get_start());
+        let mut next = start;
+        for i in &overlapped {
+            if self.areas[*i].vpn_range.get_start() > next {
+                return Err(Errno::EINVAL);
+            }
+            next = self.areas[*i].vpn_range.get_end();
         }
-        let end_va: VirtAddr = VirtAddr::from(start + len).ceil().into();
-
-        let mut to_unmap: Vec<usize> = Vec::new();
-
-        for (i, area) in self.areas.iter().enumerate() {
-            if area
-                .vpn_range
-                .is_overlapped(&VPNRange::new(start_va.into(), end_va.into()))
-            {
-                to_unmap.push(i);
+        if next < end {
+            return Err(Errno::EINVAL);
+        }
+        let mut result = Vec::new();
+        for mut i in overlapped {
+            if self.areas[i].vpn_range.get_start() < start {
+                let tail = self.areas[i].split_off(start);
+                self.areas.push(tail);
+                i = self.areas.len() - 1;
+            }
+            if self.areas[i].vpn_range.get_end() > end {
+                let tail = self.areas[i].split_off(end);
+                self.areas.push(tail);
             }
+            result.push(i);
         }
+        Ok(result)
+    }
 
-        to_unmap.sort_by_key(|i| self.areas[*i].vpn_range.get_start());
-
-        for i in &to_unmap {
-            if start_va == self.areas[*i].vpn_range.get_start().into() {
-                start_va = self.areas[*i].vpn_range.get_end().into();
-            } else {
-                return Err(-1);
-            }
+    pub fn munmap(&mut self, start: usize, len: usize) -> Result<isize, Errno> {
+        let start_va: VirtAddr = VirtAddr::from(start);
+        if start_va != start_va.floor().into() {
+            return Err(Errno::EINVAL);
+        }
+        if len == 0 {
+            return Ok(0);
         }
-        if start_va != end_va {
-            return Err(-1);
+        let end_va: VirtAddr = VirtAddr::from(start + len).ceil().into();
+        let range = VPNRange::new(start_va.floor(), end_va.floor());
+        if self.areas.iter().any(|area| {
+            area.vpn_range.is_overlapped(&range) && !area.map_perm.contains(MapPermission::U)
+        }) {
+            return Err(Errno::EINVAL);
         }
 
+        let mut to_unmap = self.split_range(range.get_start(), range.get_end())?;
         to_unmap.sort_by(|l, r| r.cmp(l));
 
+        let mut removed = Vec::new();
         for i in to_unmap {
             self.areas[i].unmap(&mut self.page_table);
-            self.areas.remove(i);
+            removed.push(self.areas.remove(i));
         }
+        self.flush_tlb(start_va, usize::from(end_va) - start);
+        drop(removed);
 
         Ok(len as isize)
     }
 
-    pub fn mmio_map(&mut self, start: usize, len: usize, port: usize) -> Result<isize, isize> {
+    pub fn program_break(&self) -> usize {
+        self.brk
+    }
+
+    /// Move the program break to `new_brk`. Heap pages are allocated on
+    /// first touch and freed when the break moves below them. Shrinking
+    /// fails with EINVAL if other mappings sit in the released range.
+    pub fn brk(&mut self, new_brk: usize) -> Result<usize, Errno> {
+        if new_brk < self.heap_bottom || new_brk - self.heap_bottom > 1 << 30 {
+            return Err(Errno::ENOMEM);
+        }
+        let bottom: VirtPageNum = VirtAddr::from(self.heap_bottom).floor();
+        let old_end: VirtPageNum = VirtAddr::from(self.brk).ceil();
+        let new_end: VirtPageNum = VirtAddr::from(new_brk).ceil();
+        let heap_perm = MapPermission::R | MapPermission::W | MapPermission::U;
+        if new_end > old_end {
+            if self.is_mapped_area(old_end.into(), new_end.into()) {
+                return Err(Errno::ENOMEM);
+            }
+            // mprotect and munmap may have split the heap, only the piece
+            // right below the break grows
+            match self.areas.iter_mut().find(|area| {
+                old_end > bottom
+                    && area.vpn_range.get_end() == old_end
+                    && area.map_type == MapType::Framed
+                    && area.lazy
+                    && area.map_perm == heap_perm
+            }) {
+                Some(area) => area.vpn_range = VPNRange::new(area.vpn_range.get_start(), new_end),
+                None => self.insert_lazy_area(old_end.into(), new_end.into(), heap_perm),
+            }
+        } else if new_end < old_end {
+            // whatever is left of the heap in [new_end, old_end), anything
+            // mapped into a hole munmap made there is not ours to drop
+            let range = VPNRange::new(new_end, old_end);
+            let is_heap = |area: &MapArea| {
+                area.map_type == MapType::Framed
+                    && area.lazy
+                    && area.map_perm.contains(MapPermission::U)
+                    && area.vpn_range.get_start() >= bottom
+                    && area.vpn_range.get_end() <= old_end
+            };
+            if self
+                .areas
+                .iter()
+                .any(|area| area.vpn_range.is_overlapped(&range) && !is_heap(area))
+            {
+                return Err(Errno::EINVAL);
+            }
+            let mut removed = Vec::new();
+            let mut i = 0;
+            while i < self.areas.len() {
+                let area_range = self.areas[i].vpn_range;
+                if !area_range.is_overlapped(&range) {
+                    i += 1;
+                    continue;
+                }
+                if area_range.get_start() < new_end {
+                    removed.push(self.areas[i].split_off(new_end));
+                    i += 1;
+                } else {
+                    removed.push(self.areas.remove(i));
+                }
+            }
+            for area in removed.iter_mut() {
+                area.unmap(&mut self.page_table);
+            }
+            let start_va: VirtAddr = new_end.into();
+            let end_va: VirtAddr = old_end.into();
+            self.flush_tlb(start_va, usize::from(end_va) - usize::from(start_va));
+            // frames go back only after no hart can reach them
+            drop(removed);
+        }
+        self.brk = new_brk;
+        Ok(new_brk)
+    }
+
+    /// Change the permission of user pages in range, splitting areas as
+    /// needed. Mapped pages get their PTE rewritten at once.
+    pub fn mprotect(&mut self, start: usize, len: usize, port: usize) -> Result<isize, Errno> {
+        if port & !7 != 0 || port & 7 == 0 {
+            return Err(Errno::EINVAL);
+        }
+        let start_va: VirtAddr = VirtAddr::from(start);
+        if start_va != start_va.floor().into() {
+            return Err(Errno::EINVAL);
+        }
+        if len == 0 {
+            return Ok(0);
+        }
+        let end_va: VirtAddr = VirtAddr::from(start + len).ceil().into();
+        let range = VPNRange::new(start_va.floor(), end_va.floor());
+        if self.areas.iter().any(|area| {
+            area.vpn_range.is_overlapped(&range) && !area.map_perm.contains(MapPermission::U)
+        }) {
+            return Err(Errno::EINVAL);
+        }
+        let map_perm = MapPermission::from_bits((port << 1 | 0b10000) as u8).unwrap();
+        for i in self.split_range(range.get_start(), range.get_end())? {
+            let area = &mut self.areas[i];
+            area.map_perm = map_perm;
+            area.remap_all(&mut self.page_table);
+        }
+        self.flush_tlb(start_va, usize::from(end_va) - start);
+        Ok(0)
+    }
+
+    pub fn mmio_map(&mut self, start: usize, len: usize, port: usize) -> Result<isize, Errno> {
         if port & !7 != 0 || port & 7 == 0 || len > 1 << 30 {
-            Err(-1)
+            Err(Errno::EINVAL)
         } else {
             let start_va: VirtAddr = VirtAddr::from(start);
             if start_va != start_va.floor().into() {
-                return Err(-1);
+                return Err(Errno::EINVAL);
             }
             let end_va: VirtAddr = VirtAddr::from(start + len).ceil().into();
 
             if self.is_mapped_area(start_va, end_va) {
-                return Err(-1);
+                return Err(Errno::EEXIST);
             }
             self.push(
                 MapArea::new(
@@ -390,64 +773,37 @@ impl MemorySet {
                     MapPermission::from_bits((port << 1 | 0b10000) as u8).unwrap(),
                 ),
                 None,
-            );
+            )?;
             Ok((usize::from(end_va) - usize::from(start_va)) as isize)
         }
     }
 
     #[allow(unused)]
-    pub fn mmio_unmap(&mut self, start: usize, len: usize) -> Result<isize, isize> {
-        let mut start_va: VirtAddr = VirtAddr::from(start);
-        if start_va != start_va.floor().into() {
-            return Err(-1);
-        }
-        let end_va: VirtAddr = VirtAddr::from(start + len).ceil().into();
-
-        let mut to_unmap: Vec<usize> = Vec::new();
-
-        for (i, area) in self.areas.iter().enumerate() {
-            if area
-                .vpn_range
-                .is_overlapped(&VPNRange::new(start_va.into(), end_va.into()))
-            {
-                to_unmap.push(i);
-            }
-        }
-
-        to_unmap.sort_by_key(|i| self.areas[*i].vpn_range.get_start());
-
-        for i in &to_unmap {
-            if start_va == self.areas[*i].vpn_range.get_start().into() {
-                start_va = self.areas[*i].vpn_range.get_end().into();
-            } else {
-                return Err(-1);
-            }
-        }
-        if start_va != end_va {
-            return Err(-1);
-        }
-
-        to_unmap.sort_by(|l, r| r.cmp(l));
-
-        for i in to_unmap {
-            self.areas[i].unmap(&mut self.page_table);
-            self.areas.remove(i);
-        }
-
-        Ok(len as isize)
+    pub fn mmio_unmap(&mut self, start: usize, len: usize) -> Result<isize, Errno> {
+        self.munmap(start, len)
     }
 
     pub fn recycle_data_pages(&mut self) {
         //*self = Self::new_bare();
-        self.areas.clear();
+        // frames are freed only once no hart can reach them, MMIO areas
+        // have none
+        let mut areas = core::mem::take(&mut self.areas);
+        for area in areas.iter_mut() {
+            if area.map_type != MapType::Mmio {
+                area.unmap(&mut self.page_table);
+            }
+        }
+        self.flush_tlb(VirtAddr::from(0), usize::MAX);
     }
 }
 
 pub struct MapArea {
     vpn_range: VPNRange,
-    data_frames: BTreeMap<VirtPageNum, FrameTracker>,
+    data_frames: BTreeMap<VirtPageNum, Arc<FrameTracker>>,
     map_type: MapType,
     map_perm: MapPermission,
+    /// Framed pages are mapped on demand
+    lazy: bool,
 }
 
 impl MapArea {
@@ -459,23 +815,38 @@ impl MapArea {
     ) -> Self {
         let start_vpn: VirtPageNum = start_va.floor();
         let end_vpn: VirtPageNum = end_va.ceil();
-        Self {
-            vpn_range: VPNRange::new(start_vpn, end_vpn),
-            data_frames: BTreeMap::new(),
-            map_type,
-            map_perm,
-        }
+        Self::with_range(VPNRange::new(start_vpn, end_vpn), map_type, map_perm, false)
     }
     pub fn from_another(another: &MapArea) -> Self {
+        Self::with_range(
+            VPNRange::new(another.vpn_range.get_start(), another.vpn_range.get_end()),
+            another.map_type,
+            another.map_perm,
+            another.lazy,
+        )
+    }
+    /// Every area is made here, so that shared regions know how many areas
+    /// map them, see the `Drop` impl.
+    fn with_range(
+        vpn_range: VPNRange,
+        map_type: MapType,
+        map_perm: MapPermission,
+        lazy: bool,
+    ) -> Self {
+        if let MapType::Shared(id) = map_type {
+            shm_retain(id);
+        }
         Self {
-            vpn_range: VPNRange::new(another.vpn_range.get_start(), another.vpn_range.get_end()),
+            vpn_range,
             data_frames: BTreeMap::new(),
-            map_type: another.map_type,
-            map_perm: another.map_perm,
+            map_type,
+            map_perm,
+            lazy,
         }
     }
-    pub fn map_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
+    pub fn map_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) -> Result<(), Errno> {
         let ppn: PhysPageNum;
+        let mut frame = None;
         match self.map_type {
             MapType::Identical => {
                 ppn = PhysPageNum(vpn.0);
@@ -483,26 +854,124 @@ impl MapArea {
             MapType::Mmio => {
                 ppn = PhysPageNum(vpn.0);
             }
+            MapType::Shared(_) => {
+                // frames of the region are put in before mapping
+                ppn = self.data_frames.get(&vpn).unwrap().ppn;
+            }
             MapType::Framed => {
-                let frame = frame_alloc().unwrap();
-                ppn = frame.ppn;
-                self.data_frames.insert(vpn, frame);
+                let new_frame = frame_alloc().ok_or(Errno::ENOMEM)?;
+                ppn = new_frame.ppn;
+                frame = Some(new_frame);
                 trace!("map_one: vpn {:?} ppn {:?}", vpn, ppn);
             }
         }
-        let pte_flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
-        page_table.map(vpn, ppn, pte_flags);
+        let pte_flags = PTEFlags::from_bits(self.map_perm.bits as u16).unwrap();
+        page_table.map(vpn, ppn, pte_flags)?;
+        // recorded only once mapped, so a failed map leaks nothing
+        if let Some(frame) = frame {
+            self.data_frames.insert(vpn, Arc::new(frame));
+        }
+        Ok(())
+    }
+    /// Map the frames of this area into `dst` too. Writable private pages
+    /// become read-only copy-on-write pages in both tables.
+    fn share(&self, src: &mut PageTable, dst: &mut PageTable) -> Result<MapArea, Errno> {
+        let mut pte_flags = PTEFlags::from_bits(self.map_perm.bits as u16).unwrap();
+        if self.map_type == MapType::Framed && pte_flags.contains(PTEFlags::W) {
+            pte_flags = (pte_flags - PTEFlags::W) | PTEFlags::COW;
+        }
+        let mut new_area = MapArea::from_another(self);
+        for (vpn, frame) in self.data_frames.iter() {
+            // on failure `dst` is dropped along with the pages mapped so far
+            dst.map(*vpn, frame.ppn, pte_flags)?;
+            src.remap(*vpn, frame.ppn, pte_flags);
+            new_area.data_frames.insert(*vpn, frame.clone());
+        }
+        Ok(new_area)
+    }
+    /// Give `vpn` a private writable frame, copying only if it is shared.
+    fn copy_on_write(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) -> Result<(), Errno> {
+        let pte_flags = PTEFlags::from_bits(self.map_perm.bits as u16).unwrap();
+        let frame = self.data_frames.get(&vpn).unwrap();
+        if Arc::strong_count(frame) == 1 {
+            page_table.remap(vpn, frame.ppn, pte_flags);
+            return Ok(());
+        }
+        let src_ppn = frame.ppn;
+        let new_frame = frame_alloc().ok_or(Errno::ENOMEM)?;
+        new_frame
+            .ppn
+            .get_bytes_array()
+            .copy_from_slice(src_ppn.get_bytes_array());
+        page_table.remap(vpn, new_frame.ppn, pte_flags);
+        self.data_frames.insert(vpn, Arc::new(new_frame));
+        Ok(())
+    }
+    /// Split off the pages from `at` on into a new area.
+    fn split_off(&mut self, at: VirtPageNum) -> MapArea {
+        let end = self.vpn_range.get_end();
+        self.vpn_range = VPNRange::new(self.vpn_range.get_start(), at);
+        let mut tail = Self::with_range(
+            VPNRange::new(at, end),
+            self.map_type,
+            self.map_perm,
+            self.lazy,
+        );
+        tail.data_frames = self.data_frames.split_off(&at);
+        tail
+    }
+    /// Rewrite the PTEs of mapped pages after `map_perm` changed. A frame
+    /// still shared by fork stays copy-on-write instead of writable.
+    fn remap_all(&mut self, page_table: &mut PageTable) {
+        let pte_flags = PTEFlags::from_bits(self.map_perm.bits as u16).unwrap();
+        let cow_flags = if pte_flags.contains(PTEFlags::W) {
+            (pte_flags - PTEFlags::W) | PTEFlags::COW
+        } else {
+            pte_flags
+        };
+        match self.map_type {
+            MapType::Framed => {
+                for (vpn, frame) in self.data_frames.iter() {
+                    let flags = if Arc::strong_count(frame) > 1 {
+                        cow_flags
+                    } else {
+                        pte_flags
+                    };
+                    page_table.remap(*vpn, frame.ppn, flags);
+                }
+            }
+            MapType::Shared(_) => {
+                for (vpn, frame) in self.data_frames.iter() {
+                    page_table.remap(*vpn, frame.ppn, pte_flags);
+                }
+            }
+            MapType::Identical | MapType::Mmio => {
+                for vpn in self.vpn_range {
+                    page_table.remap(vpn, PhysPageNum(vpn.0), pte_flags);
+                }
+            }
+        }
     }
     pub fn unmap_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
-        if let MapType::Framed = self.map_type {
-            self.data_frames.remove(&vpn);
+        if let MapType::Framed | MapType::Shared(_) = self.map_type {
+            if self.data_frames.remove(&vpn).is_none() {
+                // lazy page never touched
+                return;
+            }
         }
         page_table.unmap(vpn);
     }
-    pub fn map(&mut self, page_table: &mut PageTable) {
+    /// Undoes the pages mapped so far if it runs out of frames.
+    pub fn map(&mut self, page_table: &mut PageTable) -> Result<(), Errno> {
         for vpn in self.vpn_range {
-            self.map_one(page_table, vpn);
+            if let Err(errno) = self.map_one(page_table, vpn) {
+                for mapped in VPNRange::new(self.vpn_range.get_start(), vpn) {
+                    self.unmap_one(page_table, mapped);
+                }
+                return Err(errno);
+            }
         }
+        Ok(())
     }
     pub fn unmap(&mut self, page_table: &mut PageTable) {
         for vpn in self.vpn_range {
@@ -533,11 +1002,25 @@ impl MapArea {
     }
 }
 
+impl Drop for MapArea {
+    /// A shared region goes away with the last area mapping any part of
+    /// it, whether unmapped, exited or replaced by exec, unless its creator
+    /// is still alive.
+    fn drop(&mut self) {
+        if let MapType::Shared(id) = self.map_type {
+            self.data_frames.clear();
+            shm_release(id);
+        }
+    }
+}
+
 #[derive(Copy, Clone, PartialEq, Debug)]
 pub enum MapType {
     Identical,
     Framed,
     Mmio,
+    /// Frames of the shared memory region with this id
+    Shared(usize),
 }
 
 bitflags! {
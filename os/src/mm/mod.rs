@@ -1,24 +1,30 @@
 mod address;
+mod asid;
 mod frame_allocator;
 mod heap_allocator;
 mod memory_set;
 mod page_table;
+mod shm;
 
 pub use address::{PhysAddr, PhysPageNum, VirtAddr, VirtPageNum};
 use address::{StepByOne, VPNRange};
-pub use frame_allocator::{frame_alloc, FrameTracker};
+use frame_allocator::FramePin;
+pub use frame_allocator::{frame_alloc, frame_alloc_contiguous, frame_usage, FrameTracker};
+pub use heap_allocator::{heap_stats, HeapStats};
 pub use memory_set::remap_test;
 pub use memory_set::{MapPermission, MemorySet, KERNEL_SPACE};
 pub use page_table::{
-    translate_writable_va, translated_byte_buffer, translated_refmut, translated_str,
-    PageTableEntry, UserBuffer, UserBufferIterator,
+    copy_from_user, copy_to_user, translate_writable_va, translated_byte_buffer,
+    translated_byte_buffer_mut, translated_str, PageTableEntry, UserBuffer, UserBufferIterator,
 };
 use page_table::{PTEFlags, PageTable};
+pub use shm::{shm_create, shm_exit, shm_frames};
 
 pub fn init() {
     heap_allocator::init_heap();
     frame_allocator::init_frame_allocator();
     KERNEL_SPACE.lock().activate();
+    asid::init();
 }
 
 pub fn init_kernel_space() {
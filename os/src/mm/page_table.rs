@@ -1,11 +1,18 @@
-use super::{frame_alloc, FrameTracker, PhysAddr, PhysPageNum, StepByOne, VirtAddr, VirtPageNum};
+use super::{
+    frame_alloc, FramePin, FrameTracker, MapPermission, PhysAddr, PhysPageNum, StepByOne, VirtAddr,
+    VirtPageNum,
+};
+use crate::config::PAGE_SIZE;
+use crate::errno::Errno;
+use crate::task::current_task;
 use alloc::string::String;
 use alloc::vec;
 use alloc::vec::Vec;
 use bitflags::*;
+use core::mem::{size_of, MaybeUninit};
 
 bitflags! {
-    pub struct PTEFlags: u8 {
+    pub struct PTEFlags: u16 {
         const V = 1 << 0;
         const R = 1 << 1;
         const W = 1 << 2;
@@ -14,6 +21,8 @@ bitflags! {
         const G = 1 << 5;
         const A = 1 << 6;
         const D = 1 << 7;
+        /// RSW bit marking a read-only page shared by a copy-on-write fork
+        const COW = 1 << 8;
     }
 }
 
@@ -36,7 +45,7 @@ impl PageTableEntry {
         (self.bits >> 10 & ((1usize << 44) - 1)).into()
     }
     pub fn flags(&self) -> PTEFlags {
-        PTEFlags::from_bits(self.bits as u8).unwrap()
+        PTEFlags::from_bits_truncate((self.bits & 0x3ff) as u16)
     }
     pub fn is_valid(&self) -> bool {
         (self.flags() & PTEFlags::V) != PTEFlags::empty()
@@ -50,6 +59,9 @@ impl PageTableEntry {
     pub fn executable(&self) -> bool {
         (self.flags() & PTEFlags::X) != PTEFlags::empty()
     }
+    pub fn is_cow(&self) -> bool {
+        (self.flags() & PTEFlags::COW) != PTEFlags::empty()
+    }
 }
 
 pub struct PageTable {
@@ -57,14 +69,13 @@ pub struct PageTable {
     frames: Vec<FrameTracker>,
 }
 
-/// Assume that it won't oom when creating/mapping.
 impl PageTable {
-    pub fn new() -> Self {
-        let frame = frame_alloc().unwrap();
-        PageTable {
+    pub fn new() -> Result<Self, Errno> {
+        let frame = frame_alloc().ok_or(Errno::ENOMEM)?;
+        Ok(PageTable {
             root_ppn: frame.ppn,
             frames: vec![frame],
-        }
+        })
     }
     /// Temporarily used to get arguments from user space.
     pub fn from_token(satp: usize) -> Self {
@@ -84,7 +95,7 @@ impl PageTable {
                 break;
             }
             if !pte.is_valid() {
-                let frame = frame_alloc().unwrap();
+                let frame = frame_alloc()?;
                 *pte = PageTableEntry::new(frame.ppn, PTEFlags::V);
                 self.frames.push(frame);
             }
@@ -109,17 +120,31 @@ impl PageTable {
         }
         result
     }
-    #[allow(unused)]
-    pub fn map(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, flags: PTEFlags) {
-        let pte = self.find_pte_create(vpn).unwrap();
+    /// Fails with ENOMEM if a page table frame can not be allocated.
+    pub fn map(
+        &mut self,
+        vpn: VirtPageNum,
+        ppn: PhysPageNum,
+        flags: PTEFlags,
+    ) -> Result<(), Errno> {
+        let pte = self.find_pte_create(vpn).ok_or(Errno::ENOMEM)?;
         assert!(!pte.is_valid(), "vpn {:?} is mapped before mapping", vpn);
         #[cfg(feature = "board_lrv")]
         let flags = flags | PTEFlags::A | PTEFlags::D;
         *pte = PageTableEntry::new(ppn, flags | PTEFlags::V);
+        Ok(())
+    }
+    /// Change the frame or flags of a mapped page.
+    pub fn remap(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, flags: PTEFlags) {
+        let pte = self.find_pte_create(vpn).unwrap();
+        assert!(pte.is_valid(), "vpn {:?} is invalid before remapping", vpn);
+        #[cfg(feature = "board_lrv")]
+        let flags = flags | PTEFlags::A | PTEFlags::D;
+        *pte = PageTableEntry::new(ppn, flags | PTEFlags::V);
     }
     #[allow(unused)]
+    /// The caller flushes TLBs, see `MemorySet::flush_tlb`.
     pub fn unmap(&mut self, vpn: VirtPageNum) {
-        // TODO: Add remote TLB shootdown
         let pte = self.find_pte_create(vpn).unwrap();
         assert!(pte.is_valid(), "vpn {:?} is invalid before unmapping", vpn);
         *pte = PageTableEntry::empty();
@@ -140,44 +165,110 @@ impl PageTable {
     }
 }
 
-pub fn translate_writable_va(token: usize, va: usize) -> Result<usize, isize> {
+/// Longest string taken from user space, without the terminating null
+const USER_STR_MAX: usize = 4096;
+
+/// Sv39 addresses sign-extend bit 38. A range must stay within one half,
+/// anything else would alias other pages once split into indexes.
+fn check_user_range(start: usize, len: usize) -> Result<(), Errno> {
+    let end = start.checked_add(len).ok_or(Errno::EFAULT)?;
+    if len == 0 {
+        return Ok(());
+    }
+    let half = |va: usize| match va >> 38 {
+        0 => Some(false),
+        x if x == usize::MAX >> 38 => Some(true),
+        _ => None,
+    };
+    match (half(start), half(end - 1)) {
+        (Some(a), Some(b)) if a == b => Ok(()),
+        _ => Err(Errno::EFAULT),
+    }
+}
+
+/// The frame behind user page `vpn`, which must be user accessible and
+/// readable, also writable if `write` is set. Keep the returned pin for
+/// as long as the page is accessed.
+///
+/// The kernel accesses user memory through physical addresses, which never
+/// faults, so lazy pages of the current task are mapped and, for writes,
+/// copy-on-write pages made private before they are handed out. The pin
+/// keeps the frame from being reused if another thread unmaps it while the
+/// kernel copies.
+fn user_page(
+    page_table: &PageTable,
+    vpn: VirtPageNum,
+    write: bool,
+) -> Result<(PhysPageNum, Option<FramePin>), Errno> {
+    let check = |pte: Option<PageTableEntry>| match pte {
+        Some(pte)
+            if pte.is_valid()
+                && pte.flags().contains(PTEFlags::U | PTEFlags::R)
+                && (!write || pte.writable()) =>
+        {
+            Ok(pte.ppn())
+        }
+        _ => Err(Errno::EFAULT),
+    };
+    let task = match current_task() {
+        Some(task) => task,
+        None => return check(page_table.translate(vpn)).map(|ppn| (ppn, None)),
+    };
+    let mut memory_set = task.memory_set.lock();
+    // the ASID may change under a token taken earlier, other address
+    // spaces are not in use by anyone else
+    if PageTable::from_token(memory_set.token()).root_ppn != page_table.root_ppn {
+        return check(page_table.translate(vpn)).map(|ppn| (ppn, None));
+    }
+    let access = match memory_set.translate(vpn) {
+        // reads leave copy-on-write pages shared
+        Some(pte) if pte.is_valid() && !(write && pte.is_cow()) => None,
+        Some(pte) if pte.is_valid() => Some(MapPermission::W),
+        _ => Some(MapPermission::R),
+    };
+    if let Some(access) = access {
+        memory_set
+            .handle_page_fault(vpn, access)
+            .map_err(|_| Errno::EFAULT)?;
+    }
+    let ppn = check(memory_set.translate(vpn))?;
+    Ok((ppn, memory_set.pin_frame(vpn)))
+}
+
+/// The physical address of `va`, which nothing keeps mapped, so it is only
+/// for single accesses right away.
+pub fn translate_writable_va(token: usize, va: usize) -> Result<usize, Errno> {
+    check_user_range(va, 1)?;
     let va = VirtAddr::from(va);
-    let vpn = va.floor();
     let page_table = PageTable::from_token(token);
-    let pte = page_table.translate(vpn).unwrap();
-    if !pte.writable() || !pte.is_valid() {
-        return Err(-1);
-    }
-    let ppn = pte.ppn();
-    let mut pa: PhysAddr = ppn.into();
+    let mut pa: PhysAddr = user_page(&page_table, va.floor(), true)?.0.into();
     pa |= va.page_offset();
     Ok(usize::from(pa))
 }
 
-pub fn translated_byte_buffer(
+fn user_byte_buffer(
     token: usize,
-    ptr: *const u8,
+    ptr: usize,
     len: usize,
-) -> Result<Vec<&'static mut [u8]>, isize> {
+    write: bool,
+) -> Result<UserBuffer, Errno> {
+    check_user_range(ptr, len)?;
     let page_table = PageTable::from_token(token);
-    let mut start = ptr as usize;
+    let mut start = ptr;
     let end = start + len;
     let mut v = Vec::new();
+    let mut frames = Vec::new();
     while start < end {
         let start_va = VirtAddr::from(start);
         let mut vpn = start_va.floor();
-        let pte = page_table.translate(vpn);
-        if pte.is_none() {
-            return Err(-1);
-        }
-        let pte = pte.unwrap();
-        if !pte.readable() || !pte.is_valid() {
-            return Err(-1);
-        }
-        let ppn = pte.ppn();
+        let (ppn, frame) = user_page(&page_table, vpn, write)?;
+        frames.extend(frame);
         vpn.step();
         let mut end_va: VirtAddr = vpn.into();
-        end_va = end_va.min(VirtAddr::from(end));
+        // the last page of the address space wraps around to 0
+        if usize::from(end_va) == 0 || usize::from(end_va) > end {
+            end_va = VirtAddr::from(end);
+        }
         if end_va.page_offset() == 0 {
             v.push(&mut ppn.get_bytes_array()[start_va.page_offset()..]);
         } else {
@@ -185,45 +276,91 @@ pub fn translated_byte_buffer(
         }
         start = end_va.into();
     }
-    Ok(v)
+    Ok(UserBuffer { buffers: v, frames })
+}
+
+/// User memory the kernel reads from.
+pub fn translated_byte_buffer(
+    token: usize,
+    ptr: *const u8,
+    len: usize,
+) -> Result<UserBuffer, Errno> {
+    user_byte_buffer(token, ptr as usize, len, false)
+}
+
+/// User memory the kernel writes to.
+pub fn translated_byte_buffer_mut(
+    token: usize,
+    ptr: *mut u8,
+    len: usize,
+) -> Result<UserBuffer, Errno> {
+    user_byte_buffer(token, ptr as usize, len, true)
+}
+
+/// Read a `T` from user space, it may cross pages and need not be aligned.
+/// `T` must be valid for any bit pattern.
+pub fn copy_from_user<T: Copy>(token: usize, ptr: *const T) -> Result<T, Errno> {
+    let mut value = MaybeUninit::<T>::uninit();
+    let dst =
+        unsafe { core::slice::from_raw_parts_mut(value.as_mut_ptr() as *mut u8, size_of::<T>()) };
+    let mut copied = 0;
+    for buffer in translated_byte_buffer(token, ptr as *const u8, size_of::<T>())?
+        .buffers
+        .iter()
+    {
+        dst[copied..copied + buffer.len()].copy_from_slice(buffer);
+        copied += buffer.len();
+    }
+    Ok(unsafe { value.assume_init() })
+}
+
+pub fn copy_to_user<T: Copy>(token: usize, ptr: *mut T, value: T) -> Result<(), Errno> {
+    let src =
+        unsafe { core::slice::from_raw_parts(&value as *const T as *const u8, size_of::<T>()) };
+    let mut copied = 0;
+    for buffer in translated_byte_buffer_mut(token, ptr as *mut u8, size_of::<T>())?
+        .buffers
+        .iter_mut()
+    {
+        buffer.copy_from_slice(&src[copied..copied + buffer.len()]);
+        copied += buffer.len();
+    }
+    Ok(())
 }
 
-pub fn translated_str(token: usize, ptr: *const u8) -> String {
+/// Read a null-terminated string, ENAMETOOLONG if there is no null within
+/// `USER_STR_MAX` bytes.
+pub fn translated_str(token: usize, ptr: *const u8) -> Result<String, Errno> {
     let page_table = PageTable::from_token(token);
-    let mut string = String::new();
+    let mut bytes = Vec::new();
     let mut va = ptr as usize;
     loop {
-        let ch: u8 = *(page_table
-            .translate_va(VirtAddr::from(va))
-            .unwrap()
-            .get_mut());
-        if ch == 0 {
-            break;
-        } else {
-            string.push(ch as char);
-            va += 1;
+        check_user_range(va, 1)?;
+        let start_va = VirtAddr::from(va);
+        let (ppn, _frame) = user_page(&page_table, start_va.floor(), false)?;
+        let page = ppn.get_bytes_array();
+        for &ch in &page[start_va.page_offset()..] {
+            if ch == 0 {
+                return String::from_utf8(bytes).map_err(|_| Errno::EINVAL);
+            }
+            if bytes.len() == USER_STR_MAX {
+                return Err(Errno::ENAMETOOLONG);
+            }
+            bytes.push(ch);
         }
+        va = va
+            .checked_add(PAGE_SIZE - start_va.page_offset())
+            .ok_or(Errno::EFAULT)?;
     }
-    string
-}
-
-pub fn translated_refmut<T>(token: usize, ptr: *mut T) -> &'static mut T {
-    let page_table = PageTable::from_token(token);
-    let va = ptr as usize;
-    page_table
-        .translate_va(VirtAddr::from(va))
-        .unwrap()
-        .get_mut()
 }
 
 pub struct UserBuffer {
     pub buffers: Vec<&'static mut [u8]>,
+    /// Keep the frames behind `buffers` from being reused under the kernel
+    frames: Vec<FramePin>,
 }
 
 impl UserBuffer {
-    pub fn new(buffers: Vec<&'static mut [u8]>) -> Self {
-        Self { buffers }
-    }
     pub fn len(&self) -> usize {
         let mut total: usize = 0;
         for b in self.buffers.iter() {
@@ -239,6 +376,7 @@ impl IntoIterator for UserBuffer {
     fn into_iter(self) -> Self::IntoIter {
         UserBufferIterator {
             buffers: self.buffers,
+            _frames: self.frames,
             current_buffer: 0,
             current_idx: 0,
         }
@@ -247,6 +385,7 @@ impl IntoIterator for UserBuffer {
 
 pub struct UserBufferIterator {
     buffers: Vec<&'static mut [u8]>,
+    _frames: Vec<FramePin>,
     current_buffer: usize,
     current_idx: usize,
 }
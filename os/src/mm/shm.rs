@@ -0,0 +1,96 @@
+use super::{frame_alloc, FrameTracker};
+use crate::config::PAGE_SIZE;
+use crate::errno::Errno;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use lazy_static::*;
+use spin::Mutex;
+
+/// Shared memory regions by id. A frame is also held by every mapping of
+/// its region, so a region lives on in processes that still map it.
+struct ShmTable {
+    next_id: usize,
+    regions: BTreeMap<usize, ShmRegion>,
+}
+
+struct ShmRegion {
+    frames: Vec<Arc<FrameTracker>>,
+    /// Process that created the region, which keeps it until it exits
+    owner: Option<usize>,
+    /// Areas mapping any part of the region, in any process
+    areas: usize,
+}
+
+lazy_static! {
+    static ref SHM_TABLE: Mutex<ShmTable> = Mutex::new(ShmTable {
+        next_id: 0,
+        regions: BTreeMap::new(),
+    });
+}
+
+/// Allocate a zeroed region of at least `size` bytes owned by process
+/// `owner`, returns its id.
+pub fn shm_create(size: usize, owner: usize) -> Result<usize, Errno> {
+    if size == 0 || size > 1 << 30 {
+        return Err(Errno::EINVAL);
+    }
+    let pages = (size + PAGE_SIZE - 1) / PAGE_SIZE;
+    let mut frames = Vec::with_capacity(pages);
+    for _ in 0..pages {
+        frames.push(Arc::new(frame_alloc().ok_or(Errno::ENOMEM)?));
+    }
+    let mut table = SHM_TABLE.lock();
+    let id = table.next_id;
+    table.next_id += 1;
+    table.regions.insert(
+        id,
+        ShmRegion {
+            frames,
+            owner: Some(owner),
+            areas: 0,
+        },
+    );
+    Ok(id)
+}
+
+pub fn shm_frames(id: usize) -> Result<Vec<Arc<FrameTracker>>, Errno> {
+    SHM_TABLE
+        .lock()
+        .regions
+        .get(&id)
+        .map(|region| region.frames.clone())
+        .ok_or(Errno::EINVAL)
+}
+
+/// An area mapping region `id` was made.
+pub(super) fn shm_retain(id: usize) {
+    // gone already if its owner exited while it was being mapped, the
+    // area holds its own frames anyway
+    if let Some(region) = SHM_TABLE.lock().regions.get_mut(&id) {
+        region.areas += 1;
+    }
+}
+
+/// An area mapping region `id` was dropped, destroy the region if nothing
+/// else keeps it.
+pub(super) fn shm_release(id: usize) {
+    let mut table = SHM_TABLE.lock();
+    if let Some(region) = table.regions.get_mut(&id) {
+        region.areas -= 1;
+        if region.areas == 0 && region.owner.is_none() {
+            table.regions.remove(&id);
+        }
+    }
+}
+
+/// Process `owner` exited, destroy the regions it created that nobody maps.
+pub fn shm_exit(owner: usize) {
+    let mut table = SHM_TABLE.lock();
+    table.regions.retain(|_, region| {
+        if region.owner == Some(owner) {
+            region.owner = None;
+        }
+        region.owner.is_some() || region.areas > 0
+    });
+}
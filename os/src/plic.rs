@@ -1,49 +1,51 @@
+use crate::board::board;
 use crate::trace::{push_trace, S_EXT_INTR_ENTER, S_EXT_INTR_EXIT};
 use crate::trap::{push_trap_record, UserTrapRecord, USER_EXT_INT_MAP};
 use crate::uart;
 use rv_plic::{Priority, PLIC};
 
+/// rv_plic takes the base as a const parameter, so `init` can only check
+/// the device tree agrees
 #[cfg(any(feature = "board_qemu", feature = "board_lrv"))]
 pub const PLIC_BASE: usize = 0xc00_0000;
+/// Size of the PLIC if the device tree has none
+pub const PLIC_SIZE: usize = 0x400_0000;
 #[cfg(any(feature = "board_qemu", feature = "board_lrv"))]
 pub const PLIC_PRIORITY_BIT: usize = 3;
+/// Contexts of each hart, for M, S and U mode
+pub const MODE_PER_HART: usize = 3;
 
 pub type Plic = PLIC<{ PLIC_BASE }, { PLIC_PRIORITY_BIT }>;
 
 pub fn get_context(hart_id: usize, mode: char) -> usize {
-    const MODE_PER_HART: usize = 3;
-    hart_id * MODE_PER_HART
-        + match mode {
-            'M' => 0,
-            'S' => 1,
-            'U' => 2,
-            _ => panic!("Wrong Mode"),
-        }
-}
-
-#[cfg(feature = "board_qemu")]
-pub fn init() {
-    Plic::set_priority(12, Priority::lowest());
-    Plic::set_priority(13, Priority::lowest());
-    Plic::set_priority(14, Priority::lowest());
-    Plic::set_priority(15, Priority::lowest());
+    let mode = match mode {
+        'M' => 0,
+        'S' => 1,
+        'U' => 2,
+        _ => panic!("Wrong Mode"),
+    };
+    board().plic.contexts[hart_id][mode]
 }
 
-#[cfg(feature = "board_lrv")]
 pub fn init() {
-    Plic::set_priority(4, Priority::lowest());
-    Plic::set_priority(5, Priority::lowest());
-    Plic::set_priority(6, Priority::lowest());
-    Plic::set_priority(7, Priority::lowest());
+    let base = board().plic.base;
+    if base != PLIC_BASE {
+        panic!(
+            "PLIC at {:#x}, but the kernel is built for {:#x}",
+            base, PLIC_BASE
+        );
+    }
+    for serial in board().serials.iter() {
+        Plic::set_priority(serial.irq, Priority::lowest());
+    }
 }
 
 #[cfg(feature = "board_qemu")]
 pub fn init_hart(hart_id: usize) {
     let context = get_context(hart_id, 'S');
-    Plic::enable(context, 12);
-    Plic::enable(context, 13);
-    Plic::enable(context, 14);
-    Plic::enable(context, 15);
+    for serial in board().serials.iter() {
+        Plic::enable(context, serial.irq);
+    }
     Plic::set_threshold(context, Priority::any());
 }
 
@@ -52,10 +54,9 @@ pub fn init_hart(hart_id: usize) {
     let context = get_context(hart_id, 'S');
     Plic::clear_enable(context, 0);
     Plic::clear_enable(get_context(hart_id, 'U'), 0);
-    Plic::enable(context, 4);
-    Plic::enable(context, 5);
-    Plic::enable(context, 6);
-    Plic::enable(context, 7);
+    for serial in board().serials.iter() {
+        Plic::enable(context, serial.irq);
+    }
     Plic::set_threshold(context, Priority::any());
     Plic::set_threshold(get_context(hart_id, 'U'), Priority::any());
     Plic::set_threshold(get_context(hart_id, 'M'), Priority::never());
@@ -85,18 +86,12 @@ pub fn handle_external_interrupt(hart_id: usize) {
             // prioritize_task(*pid);
         }
         if !can_user_handle {
-            match irq {
-                #[cfg(feature = "board_qemu")]
-                12 | 13 | 14 | 15 => {
-                    uart::handle_interrupt(irq);
+            match uart::irq_to_serial_id(irq) {
+                Some(serial_id) => {
+                    uart::handle_interrupt(serial_id);
                     trace!("[PLIC] irq {:?} handled by kenel", irq);
                 }
-                #[cfg(feature = "board_lrv")]
-                4 | 5 | 6 | 7 => {
-                    uart::handle_interrupt(irq);
-                    // trace!("[PLIC] irq {:?} handled by kenel", irq);
-                }
-                _ => {
+                None => {
                     warn!("[PLIC]: irq {:?} not supported!", irq);
                 }
             }
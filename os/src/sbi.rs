@@ -9,39 +9,260 @@ const SBI_REMOTE_FENCE_I: usize = 5;
 const SBI_REMOTE_SFENCE_VMA: usize = 6;
 const SBI_REMOTE_SFENCE_VMA_ASID: usize = 7;
 const SBI_SHUTDOWN: usize = 8;
+
+const EID_BASE: usize = 0x10;
+const EID_TIME: usize = 0x54494D45;
+const EID_IPI: usize = 0x735049;
+const EID_RFENCE: usize = 0x52464E43;
+const EID_HSM: usize = 0x48534D;
+const EID_SRST: usize = 0x53525354;
+
+const BASE_GET_SPEC_VERSION: usize = 0;
+const BASE_GET_IMPL_ID: usize = 1;
+const BASE_GET_IMPL_VERSION: usize = 2;
+const BASE_PROBE_EXTENSION: usize = 3;
+
+pub const SBI_SUCCESS: isize = 0;
+pub const SBI_ERR_FAILED: isize = -1;
+pub const SBI_ERR_NOT_SUPPORTED: isize = -2;
+pub const SBI_ERR_INVALID_PARAM: isize = -3;
+pub const SBI_ERR_DENIED: isize = -4;
+pub const SBI_ERR_INVALID_ADDRESS: isize = -5;
+pub const SBI_ERR_ALREADY_AVAILABLE: isize = -6;
+
+pub const SRST_SHUTDOWN: usize = 0;
+pub const SRST_COLD_REBOOT: usize = 1;
+pub const SRST_WARM_REBOOT: usize = 2;
+
 use core::arch::asm;
+use lazy_static::*;
 
 use crate::trace::{push_trace, SEND_IPI_ENTER, SEND_IPI_EXIT};
 
 #[inline(always)]
-fn sbi_call(which: usize, arg0: usize, arg1: usize, arg2: usize) -> usize {
+fn sbi_call_legacy(which: usize, arg0: usize, arg1: usize, arg2: usize, arg3: usize) -> usize {
     let mut ret;
     unsafe {
         asm!("ecall", inout("a0") arg0 => ret, in("a1") arg1,
-             in("a2") arg2, in("a7") which)
+             in("a2") arg2, in("a3") arg3, in("a7") which)
     }
     ret
 }
 
+/// Calling convention of SBI v0.2 and later, an error code in a0 and a
+/// value in a1.
+#[inline(always)]
+fn sbi_call(
+    eid: usize,
+    fid: usize,
+    arg0: usize,
+    arg1: usize,
+    arg2: usize,
+    arg3: usize,
+    arg4: usize,
+) -> Result<usize, isize> {
+    let error: isize;
+    let value: usize;
+    unsafe {
+        asm!("ecall", inlateout("a0") arg0 => error, inlateout("a1") arg1 => value,
+             in("a2") arg2, in("a3") arg3, in("a4") arg4, in("a6") fid, in("a7") eid)
+    }
+    if error == SBI_SUCCESS {
+        Ok(value)
+    } else {
+        Err(error)
+    }
+}
+
+/// What the firmware supports, probed once through the Base extension.
+#[derive(Debug)]
+pub struct SbiInfo {
+    /// Major version in bits 24..31, minor in bits 0..24, 0 for legacy
+    /// firmware without the Base extension
+    pub spec_version: usize,
+    pub impl_id: usize,
+    pub impl_version: usize,
+    pub time: bool,
+    pub ipi: bool,
+    pub rfence: bool,
+    pub hsm: bool,
+    pub srst: bool,
+}
+
+impl SbiInfo {
+    fn probe() -> Self {
+        let spec_version = match sbi_call(EID_BASE, BASE_GET_SPEC_VERSION, 0, 0, 0, 0, 0) {
+            // SBI v0.1 has no Base extension
+            Ok(version) if version != 0 => version,
+            _ => {
+                return Self {
+                    spec_version: 0,
+                    impl_id: 0,
+                    impl_version: 0,
+                    time: false,
+                    ipi: false,
+                    rfence: false,
+                    hsm: false,
+                    srst: false,
+                }
+            }
+        };
+        let base = |fid| sbi_call(EID_BASE, fid, 0, 0, 0, 0, 0).unwrap_or(0);
+        Self {
+            spec_version,
+            impl_id: base(BASE_GET_IMPL_ID),
+            impl_version: base(BASE_GET_IMPL_VERSION),
+            time: base_probe(EID_TIME),
+            ipi: base_probe(EID_IPI),
+            rfence: base_probe(EID_RFENCE),
+            hsm: base_probe(EID_HSM),
+            srst: base_probe(EID_SRST),
+        }
+    }
+}
+
+fn base_probe(eid: usize) -> bool {
+    matches!(sbi_call(EID_BASE, BASE_PROBE_EXTENSION, eid, 0, 0, 0, 0), Ok(n) if n != 0)
+}
+
+lazy_static! {
+    pub static ref SBI_INFO: SbiInfo = SbiInfo::probe();
+}
+
+/// Probe the firmware on the boot hart.
+pub fn init() {
+    let info = &*SBI_INFO;
+    debug!(
+        "SBI v{}.{}, impl {} version {:#x}",
+        info.spec_version >> 24,
+        info.spec_version & 0xff_ffff,
+        info.impl_id,
+        info.impl_version
+    );
+    debug!(
+        "SBI extensions: TIME {} IPI {} RFENCE {} HSM {} SRST {}",
+        info.time, info.ipi, info.rfence, info.hsm, info.srst
+    );
+}
+
 pub fn set_timer(timer: usize) {
-    sbi_call(SBI_SET_TIMER, timer, 0, 0);
+    if SBI_INFO.time {
+        let _ = sbi_call(EID_TIME, 0, timer, 0, 0, 0, 0);
+    } else {
+        sbi_call_legacy(SBI_SET_TIMER, timer, 0, 0, 0);
+    }
 }
 
 pub fn console_putchar(c: usize) {
-    sbi_call(SBI_CONSOLE_PUTCHAR, c, 0, 0);
+    sbi_call_legacy(SBI_CONSOLE_PUTCHAR, c, 0, 0, 0);
 }
 
 pub fn console_getchar() -> usize {
-    sbi_call(SBI_CONSOLE_GETCHAR, 0, 0, 0)
+    sbi_call_legacy(SBI_CONSOLE_GETCHAR, 0, 0, 0, 0)
+}
+
+pub fn system_reset(reset_type: usize, reason: usize) -> isize {
+    match sbi_call(EID_SRST, 0, reset_type, reason, 0, 0, 0) {
+        Ok(_) => SBI_SUCCESS,
+        Err(error) => error,
+    }
 }
 
 pub fn shutdown() -> ! {
-    sbi_call(SBI_SHUTDOWN, 0, 0, 0);
+    if SBI_INFO.srst {
+        system_reset(SRST_SHUTDOWN, 0);
+    }
+    sbi_call_legacy(SBI_SHUTDOWN, 0, 0, 0, 0);
     panic!("It should shutdown!");
 }
 
-pub fn send_ipi(ptr: usize) {
+/// Send a software interrupt to the harts in `hart_mask`.
+pub fn send_ipi(hart_mask: usize) {
     push_trace(SEND_IPI_ENTER);
-    sbi_call(SBI_SEND_IPI, ptr, 0, 0);
+    if SBI_INFO.ipi {
+        let _ = sbi_call(EID_IPI, 0, hart_mask, 0, 0, 0, 0);
+    } else {
+        sbi_call_legacy(SBI_SEND_IPI, &hart_mask as *const _ as usize, 0, 0, 0);
+    }
     push_trace(SEND_IPI_EXIT);
 }
+
+pub fn remote_fence_i(hart_mask: usize) {
+    if SBI_INFO.rfence {
+        let _ = sbi_call(EID_RFENCE, 0, hart_mask, 0, 0, 0, 0);
+    } else {
+        sbi_call_legacy(SBI_REMOTE_FENCE_I, &hart_mask as *const _ as usize, 0, 0, 0);
+    }
+}
+
+/// Flush `[start, start + size)` from the TLBs of the harts in `hart_mask`,
+/// a `size` of `usize::MAX` flushes everything.
+pub fn remote_sfence_vma(hart_mask: usize, start: usize, size: usize) {
+    if SBI_INFO.rfence {
+        let _ = sbi_call(EID_RFENCE, 1, hart_mask, 0, start, size, 0);
+    } else {
+        let mask_ptr = &hart_mask as *const _ as usize;
+        sbi_call_legacy(SBI_REMOTE_SFENCE_VMA, mask_ptr, start, size, 0);
+    }
+}
+
+/// Like `remote_sfence_vma`, only for address space `asid`.
+pub fn remote_sfence_vma_asid(hart_mask: usize, start: usize, size: usize, asid: usize) {
+    if SBI_INFO.rfence {
+        let _ = sbi_call(EID_RFENCE, 2, hart_mask, 0, start, size, asid);
+    } else {
+        let mask_ptr = &hart_mask as *const _ as usize;
+        sbi_call_legacy(SBI_REMOTE_SFENCE_VMA_ASID, mask_ptr, start, size, asid);
+    }
+}
+
+/// Start `hartid` at `start_addr` in supervisor mode, with the hart id in
+/// a0 and `opaque` in a1.
+pub fn hart_start(hartid: usize, start_addr: usize, opaque: usize) -> Result<(), isize> {
+    sbi_call(EID_HSM, 0, hartid, start_addr, opaque, 0, 0).map(|_| ())
+}
+
+/// Stop the calling hart, only returns on failure.
+pub fn hart_stop() -> isize {
+    match sbi_call(EID_HSM, 1, 0, 0, 0, 0, 0) {
+        Ok(_) => SBI_SUCCESS,
+        Err(error) => error,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HartStatus {
+    Started,
+    Stopped,
+    StartPending,
+    StopPending,
+    Suspended,
+    SuspendPending,
+    ResumePending,
+}
+
+pub fn hart_get_status(hartid: usize) -> Result<HartStatus, isize> {
+    match sbi_call(EID_HSM, 2, hartid, 0, 0, 0, 0)? {
+        0 => Ok(HartStatus::Started),
+        1 => Ok(HartStatus::Stopped),
+        2 => Ok(HartStatus::StartPending),
+        3 => Ok(HartStatus::StopPending),
+        4 => Ok(HartStatus::Suspended),
+        5 => Ok(HartStatus::SuspendPending),
+        6 => Ok(HartStatus::ResumePending),
+        _ => Err(SBI_ERR_FAILED),
+    }
+}
+
+/// Bring up a secondary hart at `start_addr`. Legacy firmware holds
+/// secondary harts until they get an IPI instead, which is also tried if
+/// HSM does not report the hart as stopped.
+pub fn start_hart(hartid: usize, start_addr: usize) {
+    if SBI_INFO.hsm && hart_get_status(hartid) == Ok(HartStatus::Stopped) {
+        match hart_start(hartid, start_addr, 0) {
+            Ok(()) => return,
+            Err(error) => warn!("hart_start {} failed: {}", hartid, error),
+        }
+    }
+    send_ipi(1 << hartid);
+}
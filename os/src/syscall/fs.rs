@@ -1,13 +1,15 @@
 use core::cmp::min;
+use core::mem::size_of;
 
-use crate::fs::{make_pipe, File};
+use crate::errno::Errno;
+use crate::fs::{make_pipe, open_file, File, OpenFlags, Stat};
 use crate::task::{current_task, current_user_token};
 use crate::trace::{
     push_trace, TRACE_SYSCALL_READ_FIND_FD, TRACE_SYSCALL_READ_RES, TRACE_SYSCALL_WRITE_FIND_FD,
     TRACE_SYSCALL_WRITE_RES,
 };
 use crate::{
-    mm::{translated_byte_buffer, translated_refmut, UserBuffer},
+    mm::{copy_to_user, translated_byte_buffer, translated_byte_buffer_mut, translated_str},
     task::find_task,
 };
 
@@ -17,27 +19,30 @@ pub fn sys_write(fd: usize, buf: *const u8, len: usize) -> isize {
     }
     let token = current_user_token();
     let task = current_task().unwrap();
-    let inner = task.acquire_inner_lock();
-    if fd >= inner.fd_table.len() {
-        return -1;
+    let fd_table = task.fd_table.lock();
+    if fd >= fd_table.len() {
+        return Errno::EBADF.into();
     }
-    if let Some(file) = &inner.fd_table[fd] {
+    if let Some(file) = &fd_table[fd] {
         push_trace(TRACE_SYSCALL_WRITE_FIND_FD + fd);
+        if !file.writable() {
+            return Errno::EBADF.into();
+        }
         let file = file.clone();
-        // release Task lock manually to avoid deadlock
-        drop(inner);
+        // release fd table lock manually to avoid deadlock
+        drop(fd_table);
         if let Ok(buffers) = translated_byte_buffer(token, buf, len) {
-            let res = match file.write(UserBuffer::new(buffers)) {
+            let res = match file.write(buffers) {
                 Ok(write_len) => write_len as isize,
-                Err(_) => -2,
+                Err(errno) => errno.into(),
             };
             push_trace((TRACE_SYSCALL_WRITE_RES as isize + res) as usize);
             res
         } else {
-            -3
+            Errno::EFAULT.into()
         }
     } else {
-        -4
+        Errno::EBADF.into()
     }
 }
 
@@ -47,55 +52,136 @@ pub fn sys_read(fd: usize, buf: *const u8, len: usize) -> isize {
     }
     let token = current_user_token();
     let task = current_task().unwrap();
-    let inner = task.acquire_inner_lock();
-    if fd >= inner.fd_table.len() {
-        return -1;
+    let fd_table = task.fd_table.lock();
+    if fd >= fd_table.len() {
+        return Errno::EBADF.into();
     }
-    if let Some(file) = &inner.fd_table[fd] {
+    if let Some(file) = &fd_table[fd] {
         push_trace(TRACE_SYSCALL_READ_FIND_FD + fd);
+        if !file.readable() {
+            return Errno::EBADF.into();
+        }
         let file = file.clone();
-        // release Task lock manually to avoid deadlock
-        drop(inner);
-        if let Ok(buffers) = translated_byte_buffer(token, buf, len) {
-            let res = match file.read(UserBuffer::new(buffers)) {
+        // release fd table lock manually to avoid deadlock
+        drop(fd_table);
+        if let Ok(buffers) = translated_byte_buffer_mut(token, buf as *mut u8, len) {
+            let res = match file.read(buffers) {
                 Ok(read_len) => read_len as isize,
-                Err(_) => -2,
+                Err(errno) => errno.into(),
             };
             push_trace((TRACE_SYSCALL_READ_RES as isize + res) as usize);
             res
         } else {
-            -3
+            Errno::EFAULT.into()
         }
     } else {
-        -4
+        Errno::EBADF.into()
+    }
+}
+
+pub fn sys_open(path: *const u8, flags: u32) -> isize {
+    let task = current_task().unwrap();
+    let token = current_user_token();
+    let path = match translated_str(token, path) {
+        Ok(path) => path,
+        Err(errno) => return errno.into(),
+    };
+    if let Some(inode) = open_file(path.as_str(), OpenFlags::from_bits_truncate(flags)) {
+        let mut fd_table = task.fd_table.lock();
+        let fd = fd_table.alloc_fd();
+        fd_table[fd] = Some(inode);
+        fd as isize
+    } else {
+        Errno::ENOENT.into()
     }
 }
 
 pub fn sys_close(fd: usize) -> isize {
     let task = current_task().unwrap();
-    let mut inner = task.acquire_inner_lock();
-    if fd >= inner.fd_table.len() {
-        return -1;
+    let mut fd_table = task.fd_table.lock();
+    if fd >= fd_table.len() {
+        return Errno::EBADF.into();
     }
-    if inner.fd_table[fd].is_none() {
-        return -1;
+    if fd_table[fd].is_none() {
+        return Errno::EBADF.into();
     }
-    inner.fd_table[fd].take();
+    fd_table[fd].take();
     0
 }
 
 pub fn sys_pipe(pipe: *mut usize) -> isize {
     let task = current_task().unwrap();
     let token = current_user_token();
-    let mut inner = task.acquire_inner_lock();
+    // fail before allocating fds the caller would never learn about
+    if let Err(errno) = translated_byte_buffer_mut(token, pipe as *mut u8, 2 * size_of::<usize>()) {
+        return errno.into();
+    }
+    let mut fd_table = task.fd_table.lock();
     let (pipe_read, pipe_write) = make_pipe();
-    let read_fd = inner.alloc_fd();
-    inner.fd_table[read_fd] = Some(pipe_read);
-    let write_fd = inner.alloc_fd();
-    inner.fd_table[write_fd] = Some(pipe_write);
-    *translated_refmut(token, pipe) = read_fd;
-    *translated_refmut(token, unsafe { pipe.add(1) }) = write_fd;
-    0
+    let read_fd = fd_table.alloc_fd();
+    fd_table[read_fd] = Some(pipe_read);
+    let write_fd = fd_table.alloc_fd();
+    fd_table[write_fd] = Some(pipe_write);
+    drop(fd_table);
+    match copy_to_user(token, pipe as *mut [usize; 2], [read_fd, write_fd]) {
+        Ok(()) => 0,
+        Err(errno) => errno.into(),
+    }
+}
+
+pub fn sys_dup(fd: usize) -> isize {
+    let task = current_task().unwrap();
+    let mut fd_table = task.fd_table.lock();
+    if fd >= fd_table.len() {
+        return Errno::EBADF.into();
+    }
+    if fd_table[fd].is_none() {
+        return Errno::EBADF.into();
+    }
+    let new_fd = fd_table.alloc_fd();
+    fd_table[new_fd] = Some(fd_table[fd].as_ref().unwrap().clone());
+    new_fd as isize
+}
+
+pub fn sys_lseek(fd: usize, offset: isize, whence: usize) -> isize {
+    let task = current_task().unwrap();
+    let fd_table = task.fd_table.lock();
+    if fd >= fd_table.len() {
+        return Errno::EBADF.into();
+    }
+    if let Some(file) = &fd_table[fd] {
+        let file = file.clone();
+        drop(fd_table);
+        match file.seek(offset, whence) {
+            Ok(offset) => offset as isize,
+            Err(errno) => errno.into(),
+        }
+    } else {
+        Errno::EBADF.into()
+    }
+}
+
+pub fn sys_fstat(fd: usize, st: *mut u8) -> isize {
+    let token = current_user_token();
+    let task = current_task().unwrap();
+    let fd_table = task.fd_table.lock();
+    if fd >= fd_table.len() {
+        return Errno::EBADF.into();
+    }
+    if let Some(file) = &fd_table[fd] {
+        let file = file.clone();
+        drop(fd_table);
+        let stat = match file.stat() {
+            Ok(stat) => stat,
+            Err(errno) => return errno.into(),
+        };
+        match copy_to_user(token, st as *mut Stat, stat) {
+            Ok(()) => 0,
+            Err(errno) => errno.into(),
+        }
+    } else {
+        Errno::EBADF.into()
+    }
 }
 
 pub fn sys_mailwrite(pid: usize, buf: *mut u8, len: usize) -> isize {
@@ -103,23 +189,23 @@ pub fn sys_mailwrite(pid: usize, buf: *mut u8, len: usize) -> isize {
     if let Some(receive_task) = find_task(pid) {
         debug!("find task");
         if receive_task.acquire_inner_lock().is_mailbox_full() {
-            return -1;
+            return Errno::EAGAIN.into();
         } else if len == 0 {
             return 0;
         }
 
         if let Ok(buffers) = translated_byte_buffer(token, buf, min(len, 256)) {
             let socket = receive_task.create_socket();
-            match socket.write(UserBuffer::new(buffers)) {
+            match socket.write(buffers) {
                 Ok(write_len) => write_len as isize,
-                Err(_) => -1,
+                Err(errno) => errno.into(),
             }
         } else {
-            -1
+            Errno::EFAULT.into()
         }
     } else {
         debug!("not find task");
-        -1
+        Errno::ESRCH.into()
     }
 }
 
@@ -131,20 +217,20 @@ pub fn sys_mailread(buf: *mut u8, len: usize) -> isize {
         task.acquire_inner_lock().is_mailbox_empty()
     );
     if task.acquire_inner_lock().is_mailbox_empty() {
-        return -1;
+        return Errno::EAGAIN.into();
     } else if len == 0 {
         return 0;
     }
     let mail_box = task.acquire_inner_lock().mail_box.clone();
-    if let Ok(buffers) = translated_byte_buffer(token, buf, min(len, 256)) {
-        match mail_box.read(UserBuffer::new(buffers)) {
+    if let Ok(buffers) = translated_byte_buffer_mut(token, buf, min(len, 256)) {
+        match mail_box.read(buffers) {
             Ok(read_len) => {
                 debug!("mail read {} len", read_len);
                 read_len as isize
             }
-            Err(_) => -1,
+            Err(errno) => errno.into(),
         }
     } else {
-        -1
+        Errno::EFAULT.into()
     }
 }
@@ -1,15 +1,21 @@
-use crate::config::{CPU_NUM, MEMORY_END};
+use crate::board::hart_num;
+use crate::errno::Errno;
 use crate::loader::get_app_data_by_name;
 use crate::mm;
 use crate::plic::{get_context, Plic};
 use crate::task::{
-    add_task, current_task, current_user_token, exit_current_and_run_next, hart_id, mmap, munmap,
-    set_current_priority, suspend_current_and_run_next, WAIT_LOCK,
+    add_task, block_current_and_run_next, current_task, current_user_token,
+    exit_current_and_run_next, find_task, futex_wait, futex_wake, hart_id, mmap, mprotect,
+    munmap, set_current_priority, suspend_current_and_run_next, wake_task, SignalAction,
+    TaskControlBlock, FUTEX_WAIT, FUTEX_WAKE, INITPROC, MAX_SIG, WAIT_LOCK,
 };
-use crate::timer::get_time;
+use crate::timer::{get_time, sleep_until, TimeSpec, NSEC_PER_SEC};
 use crate::trap::{push_trap_record, UserTrapRecord};
+use alloc::string::String;
+use alloc::sync::Arc;
 use alloc::vec::Vec;
 use core::mem::size_of;
+use riscv::register::time;
 
 pub fn sys_exit(exit_code: i32) -> ! {
     exit_current_and_run_next(exit_code);
@@ -25,7 +31,57 @@ pub fn sys_yield() -> isize {
 pub fn sys_set_priority(prio: isize) -> isize {
     match set_current_priority(prio) {
         Ok(prio) => prio,
-        Err(err) => err,
+        Err(errno) => errno.into(),
+    }
+}
+
+/// `pid` 0 is the calling task.
+fn find_task_or_current(pid: usize) -> Option<Arc<TaskControlBlock>> {
+    if pid == 0 {
+        current_task()
+    } else {
+        find_task(pid)
+    }
+}
+
+pub fn sys_sched_setaffinity(pid: usize, size: usize, mask: *const usize) -> isize {
+    if size < size_of::<usize>() {
+        return Errno::EINVAL.into();
+    }
+    let token = current_user_token();
+    let mask = match mm::copy_from_user(token, mask) {
+        Ok(mask) => mask,
+        Err(errno) => return errno.into(),
+    };
+    let task = match find_task_or_current(pid) {
+        Some(task) => task,
+        None => return Errno::ESRCH.into(),
+    };
+    if let Err(errno) = task.set_cpu_mask(mask) {
+        return errno.into();
+    }
+    debug!("pid {} affinity {:#x}", task.getpid(), task.cpu_mask());
+    // migrate now if the current hart is no longer allowed, queued tasks
+    // are moved when fetched
+    let is_current = Arc::ptr_eq(&task, &current_task().unwrap());
+    drop(task);
+    if is_current && !current_task().unwrap().can_run_on(hart_id()) {
+        suspend_current_and_run_next();
+    }
+    0
+}
+
+pub fn sys_sched_getaffinity(pid: usize, size: usize, mask: *mut usize) -> isize {
+    if size < size_of::<usize>() {
+        return Errno::EINVAL.into();
+    }
+    let token = current_user_token();
+    match find_task_or_current(pid) {
+        Some(task) => match mm::copy_to_user(token, mask, task.cpu_mask()) {
+            Ok(()) => size_of::<usize>() as isize,
+            Err(errno) => errno.into(),
+        },
+        None => Errno::ESRCH.into(),
     }
 }
 
@@ -33,32 +89,109 @@ pub fn sys_get_time(time: usize, tz: usize) -> isize {
     let token = current_user_token();
     let mut pas: Vec<*mut usize> = Vec::new();
     match mm::translate_writable_va(token, time) {
-        Err(_) => return -1,
+        Err(errno) => return errno.into(),
         Ok(pa) => pas.push(pa as *mut usize),
     }
     match mm::translate_writable_va(token, time + size_of::<usize>()) {
-        Err(_) => return -1,
+        Err(errno) => return errno.into(),
         Ok(pa) => pas.push(pa as *mut usize),
     }
     get_time(pas, tz)
 }
 
 pub fn sys_mmap(start: usize, len: usize, port: usize) -> isize {
-    mmap(start, len, port).unwrap_or(-1)
+    mmap(start, len, port).unwrap_or_else(|errno| errno.into())
 }
 
 pub fn sys_munmap(start: usize, len: usize) -> isize {
-    munmap(start, len).unwrap_or(-1)
+    munmap(start, len).unwrap_or_else(|errno| errno.into())
+}
+
+/// Set the program break to `addr`, or just return it if `addr` is 0.
+/// Returns the new break.
+pub fn sys_brk(addr: usize) -> isize {
+    let task = current_task().unwrap();
+    let mut memory_set = task.memory_set.lock();
+    if addr == 0 {
+        return memory_set.program_break() as isize;
+    }
+    memory_set
+        .brk(addr)
+        .map(|brk| brk as isize)
+        .unwrap_or_else(|errno| errno.into())
+}
+
+/// Move the program break by `increment` bytes, returns the old break.
+pub fn sys_sbrk(increment: isize) -> isize {
+    let task = current_task().unwrap();
+    let mut memory_set = task.memory_set.lock();
+    let old_brk = memory_set.program_break();
+    match (old_brk as isize).checked_add(increment) {
+        Some(new_brk) if new_brk >= 0 => match memory_set.brk(new_brk as usize) {
+            Ok(_) => old_brk as isize,
+            Err(errno) => errno.into(),
+        },
+        _ => Errno::ENOMEM.into(),
+    }
+}
+
+pub fn sys_mprotect(start: usize, len: usize, prot: usize) -> isize {
+    mprotect(start, len, prot).unwrap_or_else(|errno| errno.into())
 }
 
 pub fn sys_getpid() -> isize {
-    current_task().unwrap().pid.0 as isize
+    current_task().unwrap().thread_group.tgid as isize
+}
+
+pub fn sys_gettid() -> isize {
+    current_task().unwrap().getpid() as isize
+}
+
+/// Start a thread at `entry` with `arg` in a0. The thread must call
+/// `sys_exit` itself instead of returning from `entry`.
+pub fn sys_thread_create(entry: usize, arg: usize) -> isize {
+    let task = current_task().unwrap();
+    match task.create_thread(entry, arg) {
+        Ok(thread) => {
+            let tid = thread.getpid();
+            add_task(thread);
+            debug!("new thread {} in process {}", tid, task.thread_group.tgid);
+            tid as isize
+        }
+        Err(errno) => errno.into(),
+    }
+}
+
+/// Block until thread `tid` of the same process exits and return `tid`.
+pub fn sys_waittid(tid: usize, exit_code_ptr: *mut i32) -> isize {
+    let task = current_task().unwrap();
+    if tid == task.getpid() {
+        return Errno::EDEADLK.into();
+    }
+    match task.thread_group.join(tid) {
+        Ok(exit_code) => {
+            if !exit_code_ptr.is_null() {
+                if let Err(errno) = mm::copy_to_user(current_user_token(), exit_code_ptr, exit_code)
+                {
+                    return errno.into();
+                }
+            }
+            tid as isize
+        }
+        Err(errno) => errno.into(),
+    }
 }
 
 pub fn sys_fork() -> isize {
     debug!("Fork start");
     let current_task = current_task().unwrap();
-    let new_task = current_task.fork();
+    let new_task = match current_task.fork() {
+        Ok(new_task) => new_task,
+        Err(errno) => {
+            warn!("fork failed!");
+            return errno.into();
+        }
+    };
     let new_pid = new_task.pid.0;
     // modify trap context of new_task, because it returns immediately after switching
     let trap_cx = new_task.acquire_inner_lock().get_trap_cx();
@@ -71,85 +204,151 @@ pub fn sys_fork() -> isize {
     new_pid as isize
 }
 
-pub fn sys_exec(path: *const u8) -> isize {
+/// Most arguments taken by exec and spawn
+const MAX_ARGS: usize = 64;
+
+/// Read a null-terminated array of C strings from user space. A null `args`
+/// means no argument except the program name itself.
+fn translated_args(token: usize, path: &str, mut args: *const usize) -> Result<Vec<String>, Errno> {
+    let mut args_vec: Vec<String> = Vec::new();
+    if args.is_null() {
+        args_vec.push(String::from(path));
+        return Ok(args_vec);
+    }
+    loop {
+        let arg_str_ptr = mm::copy_from_user(token, args)?;
+        if arg_str_ptr == 0 {
+            break;
+        }
+        if args_vec.len() == MAX_ARGS {
+            return Err(Errno::E2BIG);
+        }
+        args_vec.push(mm::translated_str(token, arg_str_ptr as *const u8)?);
+        args = args.wrapping_add(1);
+    }
+    Ok(args_vec)
+}
+
+/// Path and arguments of exec and spawn.
+fn translated_path_args(
+    token: usize,
+    path: *const u8,
+    args: *const usize,
+) -> Result<(String, Vec<String>), Errno> {
+    let path = mm::translated_str(token, path)?;
+    let args = translated_args(token, path.as_str(), args)?;
+    Ok((path, args))
+}
+
+pub fn sys_exec(path: *const u8, args: *const usize) -> isize {
     let token = current_user_token();
-    let path = mm::translated_str(token, path);
-    debug!("EXEC {}", &path);
+    let (path, args) = match translated_path_args(token, path, args) {
+        Ok(path_args) => path_args,
+        Err(errno) => return errno.into(),
+    };
+    debug!("EXEC {} {:?}", &path, &args);
     if let Some(data) = get_app_data_by_name(path.as_str()) {
         let task = current_task().unwrap();
-        task.exec(data);
-        0
+        match task.exec(data, args) {
+            Ok(()) => 0,
+            Err(errno) => errno.into(),
+        }
     } else {
         warn!("exec failed!");
-        -1
+        Errno::ENOENT.into()
     }
 }
 
-/// If there is not a child process whose pid is same as given, return -1.
-/// Else if there is a child process but it is still running, return -2.
+/// If there is not a child process whose pid is same as given, return -ECHILD.
+/// Else block until such a child exits.
 pub fn sys_waitpid(pid: isize, exit_code_ptr: *mut i32) -> isize {
     trace!("sys_waitpid {}", pid);
     let task = current_task().unwrap();
-    // find a child process
-
-    let _ = WAIT_LOCK.lock();
-    // ---- hold current PCB lock
-    let mut inner = task.acquire_inner_lock();
-    if inner
-        .children
-        .iter()
-        .find(|p| pid == -1 || pid as usize == p.getpid())
-        .is_none()
-    {
-        return -1;
+    loop {
+        if task.is_interrupted() {
+            return Errno::EINTR.into();
+        }
+        // find a child process
+        let wl = WAIT_LOCK.lock();
+        // ---- hold current PCB lock
+        let mut inner = task.acquire_inner_lock();
+        if inner
+            .children
+            .iter()
+            .find(|p| pid == -1 || pid as usize == p.getpid())
+            .is_none()
+        {
+            return Errno::ECHILD.into();
+            // ---- release current PCB lock
+        }
+        let pair = inner.children.iter().enumerate().find(|(_, p)| {
+            // ++++ temporarily hold child PCB lock
+            p.acquire_inner_lock().is_zombie() && (pid == -1 || pid as usize == p.getpid())
+            // ++++ release child PCB lock
+        });
+        if let Some((idx, _)) = pair {
+            let child = inner.children.remove(idx);
+            // confirm that child will be deallocated after removing from children list
+            // assert_eq!(Arc::strong_count(&child), 1);
+            let found_pid = child.getpid();
+            // ++++ temporarily hold child lock
+            let exit_code = child.acquire_inner_lock().exit_code;
+            // ++++ release child PCB lock
+            drop(inner);
+            drop(wl);
+            // the child is reaped either way, as on Linux
+            if !exit_code_ptr.is_null() {
+                if let Err(errno) =
+                    mm::copy_to_user(task.get_user_token(), exit_code_ptr, exit_code)
+                {
+                    return errno.into();
+                }
+            }
+            return found_pid as isize;
+        }
+        drop(inner);
         // ---- release current PCB lock
+        // children exit under WAIT_LOCK, so no wakeup is lost here
+        task.wait_child.prepare_to_wait();
+        drop(wl);
+        block_current_and_run_next();
     }
-    let pair = inner.children.iter().enumerate().find(|(_, p)| {
-        // ++++ temporarily hold child PCB lock
-        p.acquire_inner_lock().is_zombie() && (pid == -1 || pid as usize == p.getpid())
-        // ++++ release child PCB lock
-    });
-    if let Some((idx, _)) = pair {
-        let child = inner.children.remove(idx);
-        // confirm that child will be deallocated after removing from children list
-        // assert_eq!(Arc::strong_count(&child), 1);
-        let found_pid = child.getpid();
-        // ++++ temporarily hold child lock
-        let exit_code = child.acquire_inner_lock().exit_code;
-        // ++++ release child PCB lock
-        *mm::translated_refmut(inner.memory_set.token(), exit_code_ptr) = exit_code;
-        found_pid as isize
-    } else {
-        -2
-    }
-    // ---- release current PCB lock automatically
 }
 
-pub fn sys_spawn(file: *const u8) -> isize {
+pub fn sys_spawn(path: *const u8, args: *const usize) -> isize {
     trace!("SPAWN start");
+    let token = current_user_token();
+    let (path, args) = match translated_path_args(token, path, args) {
+        Ok(path_args) => path_args,
+        Err(errno) => return errno.into(),
+    };
     let current_task = current_task().unwrap();
-    match current_task.spawn(file) {
+    match current_task.spawn(path.as_str(), args) {
         Ok(new_task) => {
             let new_pid = new_task.pid.0;
             add_task(new_task);
             debug!("new_task via spawn {:?}", new_pid);
             new_pid as isize
         }
-        Err(_) => {
+        Err(errno) => {
             warn!("spawn failed!");
-            -1
+            errno.into()
         }
     }
 }
 
 pub fn sys_flush_trace() -> isize {
-    const FLUSH_SIZE: usize = 0x400_0000; // 2M
-    let pid = current_task().unwrap().pid.0;
-    let offset = FLUSH_SIZE * (pid as usize & 3);
-    let start = MEMORY_END + offset;
-    (start..(start + FLUSH_SIZE)).for_each(|a| unsafe {
-        let _ = (a as *mut u8).read_volatile();
-    });
+    // the trace buffer is only kept clear of the frames with the trace feature
+    #[cfg(feature = "trace")]
+    {
+        const FLUSH_SIZE: usize = 0x400_0000; // 2M
+        let pid = current_task().unwrap().pid.0;
+        let offset = FLUSH_SIZE * (pid as usize & 3);
+        let start = crate::config::TRACE_BASE + offset;
+        (start..(start + FLUSH_SIZE)).for_each(|a| unsafe {
+            let _ = (a as *mut u8).read_volatile();
+        });
+    }
     0
 }
 
@@ -159,50 +358,182 @@ pub fn sys_void() -> isize {
 
 pub fn sys_init_user_trap() -> isize {
     trace!("init user trap!");
-    match current_task()
-        .unwrap()
+    let task = current_task().unwrap();
+    let result = task
         .acquire_inner_lock()
-        .init_user_trap()
-    {
+        .init_user_trap(&mut task.memory_set.lock());
+    match result {
         Ok(addr) => {
             trace!("init ok, addr: {:#x}", addr);
             addr
         }
-        Err(errno) => errno,
+        Err(errno) => errno.into(),
     }
 }
 
 pub fn sys_send_msg(pid: usize, msg: usize) -> isize {
-    if push_trap_record(
+    match push_trap_record(
         pid,
         UserTrapRecord {
             cause: pid << 4,
             message: msg,
         },
-    )
-    .is_ok()
+    ) {
+        Ok(()) => 0,
+        Err(err) => Errno::from(err).into(),
+    }
+}
+
+/// Signal 0 only checks that `pid` exists.
+pub fn sys_kill(pid: usize, signo: usize) -> isize {
+    if signo > MAX_SIG {
+        return Errno::EINVAL.into();
+    }
+    let task = match find_task(pid) {
+        Some(task) => task,
+        None => return Errno::ESRCH.into(),
+    };
+    if Arc::ptr_eq(&task, &INITPROC) {
+        return Errno::EPERM.into();
+    }
+    if signo != 0
+        && task
+            .acquire_inner_lock()
+            .signals
+            .raise(signo, &task.sig_actions.lock())
     {
-        0
+        debug!("signal {} to pid {}", signo, pid);
+        // interrupt a blocking syscall, the signal is handled on its way out
+        wake_task(task);
+    }
+    0
+}
+
+pub fn sys_sigaction(
+    signo: usize,
+    action: *const SignalAction,
+    old_action: *mut SignalAction,
+) -> isize {
+    let token = current_user_token();
+    let action = if action.is_null() {
+        None
+    } else {
+        match mm::copy_from_user(token, action) {
+            Ok(action) => Some(action),
+            Err(errno) => return errno.into(),
+        }
+    };
+    let task = current_task().unwrap();
+    let mut inner = task.acquire_inner_lock();
+    let mut actions = task.sig_actions.lock();
+    let result = actions.set_action(signo, action);
+    // pending signals that are now ignored are dropped, siblings drop
+    // theirs on delivery
+    if result.is_ok() && actions.ignores(signo) {
+        inner.signals.pending &= !(1 << signo);
+    }
+    drop(actions);
+    drop(inner);
+    match result {
+        Ok(old) => {
+            if old_action.is_null() {
+                return 0;
+            }
+            match mm::copy_to_user(token, old_action, old) {
+                Ok(()) => 0,
+                Err(errno) => errno.into(),
+            }
+        }
+        Err(errno) => errno.into(),
+    }
+}
+
+/// `how` is one of SIG_BLOCK, SIG_UNBLOCK and SIG_SETMASK. A null `set`
+/// only reads the mask.
+pub fn sys_sigprocmask(how: usize, set: *const u32, old_set: *mut u32) -> isize {
+    let token = current_user_token();
+    let set = if set.is_null() {
+        None
     } else {
-        -1
+        match mm::copy_from_user(token, set) {
+            Ok(set) => Some(set),
+            Err(errno) => return errno.into(),
+        }
+    };
+    let task = current_task().unwrap();
+    let result = task.acquire_inner_lock().signals.set_mask(how, set);
+    match result {
+        Ok(old) => {
+            if old_set.is_null() {
+                return 0;
+            }
+            match mm::copy_to_user(token, old_set, old) {
+                Ok(()) => 0,
+                Err(errno) => errno.into(),
+            }
+        }
+        Err(errno) => errno.into(),
     }
 }
 
 pub fn sys_set_timer(time_us: usize) -> isize {
     let pid = current_task().unwrap().pid.0;
-    use crate::config::CLOCK_FREQ;
-    use crate::timer::{set_virtual_timer, USEC_PER_SEC};
-    let time = time_us * CLOCK_FREQ / USEC_PER_SEC;
-    set_virtual_timer(time, pid);
+    use crate::board::clock_freq;
+    use crate::timer::{set_virtual_timer, TimerEvent, USEC_PER_SEC};
+    let time = time_us * clock_freq() / USEC_PER_SEC;
+    set_virtual_timer(time, TimerEvent::UserTimer(pid));
     0
 }
 
+pub fn sys_futex(addr: usize, op: usize, val: usize) -> isize {
+    let result = match op {
+        FUTEX_WAIT => futex_wait(addr, val as u32),
+        FUTEX_WAKE => futex_wake(addr, val),
+        _ => Err(Errno::EINVAL),
+    };
+    result.unwrap_or_else(|errno| errno.into())
+}
+
+/// The unslept time is stored in `rem` if the sleep is interrupted.
+pub fn sys_nanosleep(req: *const TimeSpec, rem: *mut TimeSpec) -> isize {
+    use crate::board::clock_freq;
+    let token = current_user_token();
+    let req = match mm::copy_from_user(token, req) {
+        Ok(req) => req,
+        Err(errno) => return errno.into(),
+    };
+    if req.nsec >= NSEC_PER_SEC {
+        return Errno::EINVAL.into();
+    }
+    let start = time::read();
+    // a huge request is cut to ages from now, short of usize::MAX so
+    // that timer keys can still be bumped past it
+    let end = start
+        .saturating_add(req.sec.saturating_mul(clock_freq()))
+        .saturating_add(req.nsec.saturating_mul(clock_freq()) / NSEC_PER_SEC)
+        .min(usize::MAX >> 1);
+    if sleep_until(end) {
+        return 0;
+    }
+    if !rem.is_null() {
+        let left = end.saturating_sub(time::read());
+        let left = TimeSpec {
+            sec: left / clock_freq(),
+            nsec: left % clock_freq() * NSEC_PER_SEC / clock_freq(),
+        };
+        if let Err(errno) = mm::copy_to_user(token, rem, left) {
+            return errno.into();
+        }
+    }
+    Errno::EINTR.into()
+}
+
 pub fn sys_claim_ext_int(device_id: usize) -> isize {
     let device_id = device_id as u16;
     let current_task = current_task().unwrap();
     let mut inner = current_task.acquire_inner_lock();
     if !inner.is_user_trap_enabled() {
-        return -1;
+        return Errno::EPERM.into();
     }
     use crate::plic;
     use crate::trap::USER_EXT_INT_MAP;
@@ -218,48 +549,39 @@ pub fn sys_claim_ext_int(device_id: usize) -> isize {
                 );
                 map.insert(device_id, pid);
                 info.devices.push((device_id, false));
-                for hart_id in 0..CPU_NUM {
+                for hart_id in 0..hart_num() {
                     let claim_addr = Plic::context_address(plic::get_context(hart_id, 'U'));
-                    if inner
+                    if current_task
                         .memory_set
+                        .lock()
                         .mmio_map(claim_addr, crate::config::PAGE_SIZE, 0b11)
                         .is_err()
                     {
                         warn!("[syscall claim] map plic claim reg failed!");
-                        return -6;
+                        return Errno::ENOMEM.into();
                     }
                 }
             }
             use crate::uart;
-            match device_id {
-                #[cfg(feature = "board_qemu")]
-                13 | 14 | 15 => {
-                    let base_address = uart::get_base_addr_from_irq(device_id);
-                    match inner
-                        .memory_set
-                        .mmio_map(base_address, uart::SERIAL_ADDRESS_STRIDE, 0x3)
-                    {
-                        Ok(_) => base_address as isize,
-                        Err(_) => -2,
-                    }
-                }
-                #[cfg(feature = "board_lrv")]
-                5 | 6 | 7 => {
-                    let base_address = uart::get_base_addr_from_irq(device_id);
-                    match inner
+            // serial 0 is the console of the kernel
+            match uart::irq_to_serial_id(device_id) {
+                Some(serial_id) if serial_id > 0 => {
+                    let base_address = uart::get_base_addr(serial_id);
+                    match current_task
                         .memory_set
+                        .lock()
                         .mmio_map(base_address, uart::SERIAL_ADDRESS_STRIDE, 0x3)
                     {
                         Ok(_) => base_address as isize,
-                        Err(_) => -2,
+                        Err(errno) => errno.into(),
                     }
                 }
-                _ => -4,
+                _ => Errno::ENODEV.into(),
             }
         }
         None => {
             warn!("[syscall claim] user trap info is None!");
-            -5
+            Errno::EPERM.into()
         }
     }
 }
@@ -271,7 +593,7 @@ pub fn sys_set_ext_int_enable(device_id: usize, enable: usize) -> isize {
     let current_task = current_task().unwrap();
     let mut inner = current_task.acquire_inner_lock();
     if !inner.is_user_trap_enabled() {
-        return -1;
+        return Errno::EPERM.into();
     }
     use crate::trap::USER_EXT_INT_MAP;
     let user_trap_info = &mut inner.user_trap_info;
@@ -284,7 +606,7 @@ pub fn sys_set_ext_int_enable(device_id: usize, enable: usize) -> isize {
                             *en = is_enable;
                             if is_enable {
                                 Plic::enable(get_context(hart_id(), 'U'), device_id);
-                                for hart in 0..CPU_NUM {
+                                for hart in 0..hart_num() {
                                     Plic::disable(get_context(hart, 'S'), device_id);
                                 }
                             } else {
@@ -300,16 +622,16 @@ pub fn sys_set_ext_int_enable(device_id: usize, enable: usize) -> isize {
                         device_id,
                         current_task.getpid()
                     );
-                    return -1;
+                    return Errno::EPERM.into();
                 }
             } else {
                 warn!("[sys set ext] device not claimed!");
-                return -2;
+                return Errno::ENODEV.into();
             }
         }
         None => {
             warn!("[syscall claim] user trap info is None!");
-            -5
+            Errno::EPERM.into()
         }
     }
 }
@@ -0,0 +1,38 @@
+use crate::errno::Errno;
+use crate::mm::{shm_create, shm_frames};
+use crate::task::current_task;
+
+fn shm_map(id: usize, addr: usize, prot: usize) -> Result<isize, Errno> {
+    let frames = shm_frames(id)?;
+    current_task()
+        .unwrap()
+        .memory_set
+        .lock()
+        .shm_map(addr, id, frames, prot)
+}
+
+/// Returns the id of a new region of `size` bytes. It is kept until the
+/// process exits, and after that for as long as anyone maps it.
+pub fn sys_shm_create(size: usize) -> isize {
+    let owner = current_task().unwrap().thread_group.tgid;
+    shm_create(size, owner)
+        .map(|id| id as isize)
+        .unwrap_or_else(|errno| errno.into())
+}
+
+/// Map region `id` at `addr` with `prot` bits as in mmap, returns `addr`.
+/// The mapping is kept across fork.
+pub fn sys_shm_map(id: usize, addr: usize, prot: usize) -> isize {
+    shm_map(id, addr, prot).unwrap_or_else(|errno| errno.into())
+}
+
+/// The region is destroyed when the last mapping of it is gone, however
+/// it was removed.
+pub fn sys_shm_unmap(addr: usize) -> isize {
+    current_task()
+        .unwrap()
+        .memory_set
+        .lock()
+        .shm_unmap(addr)
+        .unwrap_or_else(|errno| errno.into())
+}
@@ -0,0 +1,136 @@
+use crate::errno::Errno;
+use crate::task::{current_task, Resource};
+
+fn mutex_lock(id: usize) -> Result<isize, Errno> {
+    let task = current_task().unwrap();
+    let tid = task.getpid();
+    let res = Resource::Mutex(id);
+    let mut sync_table = task.sync_table.lock();
+    let mutex = sync_table.mutex(id)?;
+    sync_table.request(tid, res)?;
+    while !mutex.try_lock(tid) {
+        drop(sync_table);
+        let result = mutex.wait();
+        sync_table = task.sync_table.lock();
+        if let Err(errno) = result {
+            sync_table.finish_request(tid, res, false);
+            return Err(errno);
+        }
+    }
+    sync_table.finish_request(tid, res, true);
+    Ok(0)
+}
+
+fn mutex_unlock(id: usize) -> Result<isize, Errno> {
+    let task = current_task().unwrap();
+    let tid = task.getpid();
+    let mut sync_table = task.sync_table.lock();
+    sync_table.mutex(id)?.unlock(tid)?;
+    sync_table.release(tid, Resource::Mutex(id));
+    Ok(0)
+}
+
+fn semaphore_up(id: usize) -> Result<isize, Errno> {
+    let task = current_task().unwrap();
+    let mut sync_table = task.sync_table.lock();
+    sync_table.semaphore(id)?.up();
+    sync_table.release(task.getpid(), Resource::Semaphore(id));
+    Ok(0)
+}
+
+fn semaphore_down(id: usize) -> Result<isize, Errno> {
+    let task = current_task().unwrap();
+    let tid = task.getpid();
+    let res = Resource::Semaphore(id);
+    let mut sync_table = task.sync_table.lock();
+    let semaphore = sync_table.semaphore(id)?;
+    sync_table.request(tid, res)?;
+    while !semaphore.try_down() {
+        drop(sync_table);
+        let result = semaphore.wait();
+        sync_table = task.sync_table.lock();
+        if let Err(errno) = result {
+            sync_table.finish_request(tid, res, false);
+            return Err(errno);
+        }
+    }
+    sync_table.finish_request(tid, res, true);
+    Ok(0)
+}
+
+fn condvar_signal(id: usize) -> Result<isize, Errno> {
+    let task = current_task().unwrap();
+    let condvar = task.sync_table.lock().condvar(id)?;
+    condvar.signal();
+    Ok(0)
+}
+
+/// On EINTR the mutex is no longer held.
+fn condvar_wait(condvar_id: usize, mutex_id: usize) -> Result<isize, Errno> {
+    let task = current_task().unwrap();
+    let tid = task.getpid();
+    let mut sync_table = task.sync_table.lock();
+    let condvar = sync_table.condvar(condvar_id)?;
+    let mutex = sync_table.mutex(mutex_id)?;
+    condvar.unlock_and_queue(&mutex, tid)?;
+    sync_table.release(tid, Resource::Mutex(mutex_id));
+    drop(sync_table);
+    condvar.wait()?;
+    mutex_lock(mutex_id)
+}
+
+pub fn sys_mutex_create(blocking: bool) -> isize {
+    let task = current_task().unwrap();
+    let id = task.sync_table.lock().add_mutex(blocking);
+    id as isize
+}
+
+pub fn sys_mutex_lock(id: usize) -> isize {
+    mutex_lock(id).unwrap_or_else(|errno| errno.into())
+}
+
+/// Fails with EPERM unless the caller holds the mutex.
+pub fn sys_mutex_unlock(id: usize) -> isize {
+    mutex_unlock(id).unwrap_or_else(|errno| errno.into())
+}
+
+pub fn sys_semaphore_create(count: usize) -> isize {
+    let task = current_task().unwrap();
+    let id = task.sync_table.lock().add_semaphore(count);
+    id as isize
+}
+
+pub fn sys_semaphore_up(id: usize) -> isize {
+    semaphore_up(id).unwrap_or_else(|errno| errno.into())
+}
+
+pub fn sys_semaphore_down(id: usize) -> isize {
+    semaphore_down(id).unwrap_or_else(|errno| errno.into())
+}
+
+pub fn sys_condvar_create() -> isize {
+    let task = current_task().unwrap();
+    let id = task.sync_table.lock().add_condvar();
+    id as isize
+}
+
+pub fn sys_condvar_signal(id: usize) -> isize {
+    condvar_signal(id).unwrap_or_else(|errno| errno.into())
+}
+
+pub fn sys_condvar_wait(condvar_id: usize, mutex_id: usize) -> isize {
+    condvar_wait(condvar_id, mutex_id).unwrap_or_else(|errno| errno.into())
+}
+
+/// With detection enabled, lock and down fail with EDEADLK instead of
+/// blocking into a possible deadlock.
+pub fn sys_enable_deadlock_detect(enabled: usize) -> isize {
+    let task = current_task().unwrap();
+    match enabled {
+        0 | 1 => {
+            task.sync_table.lock().set_deadlock_detect(enabled == 1);
+            0
+        }
+        _ => Errno::EINVAL.into(),
+    }
+}
@@ -0,0 +1,79 @@
+use super::{
+    block_current_and_run_next, current_task, current_user_token, wake_task, WaitQueue,
+};
+use crate::errno::Errno;
+use crate::mm::translate_writable_va;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicU32, Ordering};
+use lazy_static::*;
+use spin::Mutex;
+
+pub const FUTEX_WAIT: usize = 0;
+pub const FUTEX_WAKE: usize = 1;
+
+lazy_static! {
+    /// Waiters by physical address of the futex word, so that threads and
+    /// processes sharing the page meet in the same queue.
+    static ref FUTEX_QUEUES: Mutex<BTreeMap<usize, Arc<WaitQueue>>> = Mutex::new(BTreeMap::new());
+}
+
+fn futex_pa(addr: usize) -> Result<usize, Errno> {
+    if addr % core::mem::size_of::<u32>() != 0 {
+        return Err(Errno::EINVAL);
+    }
+    // also makes a copy-on-write page private, so the key stays stable
+    translate_writable_va(current_user_token(), addr)
+}
+
+/// Block while the word at `addr` equals `val`.
+pub fn futex_wait(addr: usize, val: u32) -> Result<isize, Errno> {
+    let pa = futex_pa(addr)?;
+    let task = current_task().unwrap();
+    let mut queues = FUTEX_QUEUES.lock();
+    // compared under the lock `futex_wake` takes, so no wakeup is lost
+    let word = unsafe { &*(pa as *const AtomicU32) };
+    if word.load(Ordering::SeqCst) != val {
+        return Err(Errno::EAGAIN);
+    }
+    let queue = queues
+        .entry(pa)
+        .or_insert_with(|| Arc::new(WaitQueue::new()))
+        .clone();
+    queue.prepare_to_wait();
+    drop(queues);
+    // a signal raised before `Blocked` was set found nothing to wake
+    if task.is_interrupted() {
+        wake_task(task.clone());
+    }
+    block_current_and_run_next();
+
+    let mut queues = FUTEX_QUEUES.lock();
+    // still queued means we were not woken by `futex_wake`
+    let cancelled = queue.remove(&task);
+    // `futex_wake` may have dropped our queue and another waiter made a new one
+    if queue.is_empty() && queues.get(&pa).map_or(false, |q| Arc::ptr_eq(q, &queue)) {
+        queues.remove(&pa);
+    }
+    if cancelled && task.is_interrupted() {
+        Err(Errno::EINTR)
+    } else {
+        Ok(0)
+    }
+}
+
+/// Wake at most `count` tasks waiting on `addr`, returns how many.
+pub fn futex_wake(addr: usize, count: usize) -> Result<isize, Errno> {
+    let pa = futex_pa(addr)?;
+    let mut queues = FUTEX_QUEUES.lock();
+    let mut woken = 0;
+    if let Some(queue) = queues.get(&pa) {
+        while woken < count && queue.wake_one() {
+            woken += 1;
+        }
+        if queue.is_empty() {
+            queues.remove(&pa);
+        }
+    }
+    Ok(woken as isize)
+}
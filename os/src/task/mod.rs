@@ -1,26 +1,38 @@
 mod context;
-mod manager;
+mod futex;
 mod pid;
 mod pool;
 mod processor;
+mod sched;
+mod signal;
+mod sync;
 mod switch;
 mod task;
+mod thread;
+mod wait_queue;
 
 use crate::loader::get_app_data_by_name;
+use crate::mm::shm_exit;
 use alloc::sync::Arc;
 use lazy_static::*;
 
+use signal::SIGKILL;
 use spin::Mutex;
 use switch::__switch2;
 
 pub use context::TaskContext;
+pub use futex::{futex_wait, futex_wake, FUTEX_WAIT, FUTEX_WAKE};
 pub use pid::{find_task, pid_alloc, KernelStack, PidHandle};
-pub use pool::{add_task, fetch_task, prioritize_task};
+pub use pool::{add_task, fetch_task, prioritize_task, wake_task};
 pub use processor::{
-    current_task, current_trap_cx, current_user_token, hart_id, mmap, munmap, run_tasks, schedule,
-    set_current_priority, take_current_task,
+    current_task, current_trap_cx, current_user_token, hart_id, mmap, mprotect, munmap, run_tasks,
+    schedule, set_current_priority, take_current_task,
 };
+pub use sched::SchedEntity;
+pub use signal::{handle_signals, SignalAction, MAX_SIG};
+pub use sync::Resource;
 pub use task::{TaskControlBlock, TaskStatus};
+pub use wait_queue::WaitQueue;
 
 lazy_static! {
     pub static ref WAIT_LOCK: Mutex<()> = Mutex::new(());
@@ -38,14 +50,44 @@ pub fn suspend_current_and_run_next() {
     schedule(task_cx_ptr);
 }
 
-pub fn exit_current_and_run_next(exit_code: i32) {
-    // ++++++ hold initproc PCB lock here
-    let mut initproc_inner = INITPROC.acquire_inner_lock();
+/// Account a kernel tick to the current task. Returns true if its time
+/// slice is used up.
+pub fn tick_current() -> bool {
+    use sched::{Scheduler, SchedulerImpl};
+    if let Some(task) = current_task() {
+        let mut task_inner = task.acquire_inner_lock();
+        task_inner.sched.ticks += 1;
+        task_inner.sched.ticks >= SchedulerImpl::time_slice(&task_inner.sched)
+    } else {
+        true
+    }
+}
 
+/// Give up the hart after the current task is marked `Blocked`, e.g. by
+/// `WaitQueue::prepare_to_wait`. Returns once the task is woken.
+pub fn block_current_and_run_next() {
+    let task = current_task().unwrap();
+    let mut task_inner = task.acquire_inner_lock();
+    let task_cx_ptr = task_inner.get_task_cx_ptr();
+    drop(task_inner);
+    drop(task);
+
+    schedule(task_cx_ptr);
+}
+
+pub fn exit_current_and_run_next(exit_code: i32) {
     // take from Processor
     let task = take_current_task().unwrap();
-    // **** hold current PCB lock
+    let thread_idx = task.thread_idx();
+    if thread_idx == 0 {
+        // the whole process goes with its leader
+        task.thread_group.kill_others(0, SIGKILL);
+    }
+    // WAIT_LOCK before any PCB lock, same as sys_waitpid
     let wl = WAIT_LOCK.lock();
+    // ++++++ hold initproc PCB lock here
+    let mut initproc_inner = INITPROC.acquire_inner_lock();
+    // **** hold current PCB lock
     let mut inner = task.acquire_inner_lock();
     info!(
         "pid: {} exited with code {}, time intr: {}, cycle count: {}",
@@ -67,18 +109,42 @@ pub fn exit_current_and_run_next(exit_code: i32) {
     inner.exit_code = exit_code;
     // do not move to its parent but under initproc
 
+    let mut has_zombie_child = false;
     for child in inner.children.iter() {
-        child.acquire_inner_lock().parent = Some(Arc::downgrade(&INITPROC));
+        let mut child_inner = child.acquire_inner_lock();
+        child_inner.parent = Some(Arc::downgrade(&INITPROC));
+        has_zombie_child |= child_inner.is_zombie();
+        drop(child_inner);
         initproc_inner.children.push(child.clone());
     }
     drop(initproc_inner);
     // ++++++ release parent PCB lock here
 
     inner.children.clear();
-    // deallocate user space
-    inner.memory_set.recycle_data_pages();
+    // siblings killed above may still be running on other harts, so user
+    // space goes with the last thread rather than the leader
+    if task.thread_group.leave() {
+        // deallocate user space
+        task.memory_set.lock().recycle_data_pages();
+        shm_exit(task.thread_group.tgid);
+    } else if thread_idx != 0 {
+        thread::unmap_thread(&mut task.memory_set.lock(), thread_idx);
+    }
+    let parent = inner.parent.as_ref().and_then(|parent| parent.upgrade());
     drop(inner);
     // **** release current PCB lock
+
+    if thread_idx != 0 {
+        task.thread_group.exit(thread_idx, task.getpid(), exit_code);
+    }
+
+    // wake waiters with no PCB lock held, still under WAIT_LOCK
+    if let Some(parent) = parent {
+        parent.wait_child.wake_all();
+    }
+    if has_zombie_child {
+        INITPROC.wait_child.wake_all();
+    }
     // drop task manually to maintain rc correctly
     drop(task);
     drop(wl);
@@ -93,6 +159,36 @@ pub fn exit_current_and_run_next(exit_code: i32) {
     // }
 }
 
+/// Kill the live process with the most resident pages, so that a task out
+/// of memory can retry. Returns false if there is no victim.
+#[cfg(feature = "oom_killer")]
+pub fn oom_kill() -> bool {
+    let victim = pid::all_tasks()
+        .into_iter()
+        .filter(|task| task.thread_idx() == 0 && !Arc::ptr_eq(task, &INITPROC))
+        .filter(|task| !task.acquire_inner_lock().is_zombie())
+        .map(|task| {
+            let pages = task.memory_set.lock().resident_pages();
+            (pages, task)
+        })
+        .filter(|(pages, _)| *pages > 0)
+        .max_by_key(|(pages, _)| *pages);
+    let (pages, victim) = match victim {
+        Some(victim) => victim,
+        None => return false,
+    };
+    if victim.is_killed() {
+        // still on its way out, let it run to free its pages
+        suspend_current_and_run_next();
+        return true;
+    }
+    warn!("[oom] killing pid {} with {} pages", victim.getpid(), pages);
+    victim.kill(SIGKILL);
+    victim.thread_group.kill_others(0, SIGKILL);
+    wake_task(victim);
+    true
+}
+
 lazy_static! {
     pub static ref INITPROC: Arc<TaskControlBlock> =
         TaskControlBlock::new(get_app_data_by_name("initproc").unwrap());
@@ -1,4 +1,5 @@
-use crate::config::{KERNEL_STACK_SIZE, PAGE_SIZE, TRAMPOLINE};
+use crate::config::{KERNEL_STACK_SIZE, MAX_KERNEL_STACKS, PAGE_SIZE, TRAMPOLINE};
+use crate::errno::Errno;
 use crate::mm::{MapPermission, VirtAddr, KERNEL_SPACE};
 use alloc::collections::BTreeMap;
 use alloc::sync::{Arc, Weak};
@@ -8,29 +9,62 @@ use spin::Mutex;
 
 use super::task::TaskControlBlock;
 
-struct PidAllocator {
+/// Hands out released ids again before new ones.
+struct RecycleAllocator {
     current: usize,
     recycled: Vec<usize>,
-    task_table: BTreeMap<usize, Weak<TaskControlBlock>>,
 }
 
-impl PidAllocator {
+impl RecycleAllocator {
     pub fn new() -> Self {
-        PidAllocator {
+        RecycleAllocator {
             current: 0,
             recycled: Vec::new(),
-            task_table: BTreeMap::new(),
         }
     }
-    pub fn alloc(&mut self) -> PidHandle {
-        let pid = match self.recycled.pop() {
-            Some(pid) => pid,
+    pub fn alloc(&mut self) -> usize {
+        match self.recycled.pop() {
+            Some(id) => id,
             None => {
                 self.current += 1;
                 self.current - 1
             }
-        };
-        PidHandle(pid)
+        }
+    }
+    pub fn dealloc(&mut self, id: usize) {
+        assert!(id < self.current);
+        self.recycled.push(id);
+    }
+}
+
+/// Low bits of a pid select a slot, the rest is the generation of that
+/// slot, so a recycled slot never gives out a pid still held somewhere.
+const PID_SLOT_BITS: usize = 16;
+const PID_SLOT_MASK: usize = (1 << PID_SLOT_BITS) - 1;
+/// Small enough for `pid << 4` in trap record causes
+const PID_GENERATION_BITS: usize = 32;
+
+struct PidAllocator {
+    slots: RecycleAllocator,
+    generations: Vec<usize>,
+    task_table: BTreeMap<usize, Weak<TaskControlBlock>>,
+}
+
+impl PidAllocator {
+    pub fn new() -> Self {
+        PidAllocator {
+            slots: RecycleAllocator::new(),
+            generations: Vec::new(),
+            task_table: BTreeMap::new(),
+        }
+    }
+    pub fn alloc(&mut self) -> PidHandle {
+        let slot = self.slots.alloc();
+        assert!(slot <= PID_SLOT_MASK, "out of pids");
+        if slot == self.generations.len() {
+            self.generations.push(0);
+        }
+        PidHandle(self.generations[slot] << PID_SLOT_BITS | slot)
     }
     pub fn add_task(&mut self, pid: usize, task: Arc<TaskControlBlock>) -> Result<(), usize> {
         match self.task_table.try_insert(pid, Arc::downgrade(&task)) {
@@ -39,23 +73,23 @@ impl PidAllocator {
         }
     }
     pub fn dealloc(&mut self, pid: usize) {
-        assert!(pid < self.current);
-        // assert!(
-        //     self.recycled.iter().find(|ppid| **ppid == pid).is_none(),
-        //     "pid {} has been deallocated!",
-        //     pid
-        // );
-        assert!(
-            self.task_table.remove(&pid).is_some(),
+        let slot = pid & PID_SLOT_MASK;
+        assert_eq!(
+            self.generations[slot],
+            pid >> PID_SLOT_BITS,
             "pid {} has been deallocated!",
             pid
         );
-        // self.recycled.push(pid);
+        // not in the table if task creation failed halfway
+        self.task_table.remove(&pid);
+        self.generations[slot] = (self.generations[slot] + 1) % (1 << PID_GENERATION_BITS);
+        self.slots.dealloc(slot);
     }
 }
 
 lazy_static! {
     static ref PID_ALLOCATOR: Mutex<PidAllocator> = Mutex::new(PidAllocator::new());
+    static ref KSTACK_ALLOCATOR: Mutex<RecycleAllocator> = Mutex::new(RecycleAllocator::new());
 }
 
 #[derive(PartialEq, Eq, PartialOrd, Ord, Debug)]
@@ -97,28 +131,52 @@ pub fn find_task(pid: usize) -> Option<Arc<TaskControlBlock>> {
         })
 }
 
-/// Return (bottom, top) of a kernel stack in kernel space.
-pub fn kernel_stack_position(app_id: usize) -> (usize, usize) {
-    let top = TRAMPOLINE - app_id * (KERNEL_STACK_SIZE + PAGE_SIZE);
+/// Tasks that are still referenced, zombies included.
+#[cfg(feature = "oom_killer")]
+pub fn all_tasks() -> Vec<Arc<TaskControlBlock>> {
+    PID_ALLOCATOR
+        .lock()
+        .task_table
+        .values()
+        .filter_map(|weak| weak.upgrade())
+        .collect()
+}
+
+/// Return (bottom, top) of a kernel stack in kernel space. Slots are one
+/// unmapped guard page apart.
+pub fn kernel_stack_position(slot: usize) -> (usize, usize) {
+    let top = TRAMPOLINE - slot * (KERNEL_STACK_SIZE + PAGE_SIZE);
     let bottom = top - KERNEL_STACK_SIZE;
     (bottom, top)
 }
 
 #[derive(Debug)]
 pub struct KernelStack {
-    pid: usize,
+    slot: usize,
 }
 
 impl KernelStack {
-    pub fn new(pid_handle: &PidHandle) -> Self {
-        let pid = pid_handle.0;
-        let (kernel_stack_bottom, kernel_stack_top) = kernel_stack_position(pid);
-        KERNEL_SPACE.lock().insert_framed_area(
+    /// Fails with EAGAIN once every slot is taken.
+    pub fn new() -> Result<Self, Errno> {
+        let slot = KSTACK_ALLOCATOR.lock().alloc();
+        if slot >= MAX_KERNEL_STACKS {
+            KSTACK_ALLOCATOR.lock().dealloc(slot);
+            return Err(Errno::EAGAIN);
+        }
+        let (kernel_stack_bottom, kernel_stack_top) = kernel_stack_position(slot);
+        let mut kernel_space = KERNEL_SPACE.lock();
+        if let Err(errno) = kernel_space.insert_framed_area(
             kernel_stack_bottom.into(),
             kernel_stack_top.into(),
             MapPermission::R | MapPermission::W,
-        );
-        KernelStack { pid: pid_handle.0 }
+        ) {
+            drop(kernel_space);
+            KSTACK_ALLOCATOR.lock().dealloc(slot);
+            return Err(errno);
+        }
+        // the task may run on any hart
+        kernel_space.flush_range(kernel_stack_bottom.into(), kernel_stack_top.into());
+        Ok(KernelStack { slot })
     }
     pub fn push_on_top<T>(&self, value: T) -> *mut T
     where
@@ -132,17 +190,20 @@ impl KernelStack {
         ptr_mut
     }
     pub fn get_top(&self) -> usize {
-        let (_, kernel_stack_top) = kernel_stack_position(self.pid);
+        let (_, kernel_stack_top) = kernel_stack_position(self.slot);
         kernel_stack_top
     }
 }
 
 impl Drop for KernelStack {
     fn drop(&mut self) {
-        let (kernel_stack_bottom, _) = kernel_stack_position(self.pid);
+        let (kernel_stack_bottom, _) = kernel_stack_position(self.slot);
         let kernel_stack_bottom_va: VirtAddr = kernel_stack_bottom.into();
+        // shoots the stack down on every hart in kernel space before the
+        // slot can be mapped to new frames
         KERNEL_SPACE
             .lock()
             .remove_area_with_start_vpn(kernel_stack_bottom_va.into());
+        KSTACK_ALLOCATOR.lock().dealloc(self.slot);
     }
 }
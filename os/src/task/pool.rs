@@ -2,67 +2,121 @@ use alloc::{collections::BTreeSet, sync::Arc};
 use lazy_static::*;
 use spin::Mutex;
 
-use super::{manager::TaskManager, task::TaskControlBlock};
+use super::sched::{Scheduler, SchedulerImpl};
+use super::{hart_id, task::TaskControlBlock, TaskStatus};
+use crate::config::CPU_NUM;
 
+/// One ready queue per hart. Lock order: `sleeping_tasks`, then a ready
+/// queue, then a task.
 pub struct TaskPool {
-    pub scheduler: TaskManager,
-    pub sleeping_tasks: BTreeSet<Arc<TaskControlBlock>>,
+    schedulers: [Mutex<SchedulerImpl>; CPU_NUM],
+    pub sleeping_tasks: Mutex<BTreeSet<Arc<TaskControlBlock>>>,
 }
 
 lazy_static! {
-    pub static ref TASK_POOL: Mutex<TaskPool> = Mutex::new(TaskPool::new());
+    pub static ref TASK_POOL: TaskPool = TaskPool::new();
 }
 
 impl TaskPool {
     pub fn new() -> Self {
         Self {
-            scheduler: TaskManager::new(),
-            sleeping_tasks: BTreeSet::new(),
+            schedulers: array_init::array_init(|_| Mutex::new(SchedulerImpl::new())),
+            sleeping_tasks: Mutex::new(BTreeSet::new()),
         }
     }
 
-    pub fn add(&mut self, task: Arc<TaskControlBlock>) {
-        self.scheduler.add(task);
+    /// Queue on the current hart if allowed, else on the first allowed hart.
+    pub fn add(&self, task: Arc<TaskControlBlock>) {
+        let hart = hart_id();
+        let target = if task.can_run_on(hart) {
+            hart
+        } else {
+            task.cpu_mask().trailing_zeros() as usize
+        };
+        self.schedulers[target].lock().add(task);
     }
 
     #[allow(unused)]
-    pub fn remove(&mut self, task: Arc<TaskControlBlock>) {
-        self.scheduler.remove(&task);
+    pub fn remove(&self, task: Arc<TaskControlBlock>) {
+        for scheduler in self.schedulers.iter() {
+            scheduler.lock().remove(&task);
+        }
     }
 
-    #[allow(unused)]
-    pub fn wake(&mut self, task: Arc<TaskControlBlock>) {
-        self.sleeping_tasks.remove(&task);
-        self.scheduler.add(task);
+    /// Move a sleeping task back to a ready queue. Returns false if the
+    /// task has not been put to sleep yet, i.e. it is still on its hart.
+    pub fn wake(&self, task: Arc<TaskControlBlock>) -> bool {
+        let mut sleeping_tasks = self.sleeping_tasks.lock();
+        let mut task_inner = task.acquire_inner_lock();
+        if task_inner.task_status != TaskStatus::Blocked {
+            return false;
+        }
+        // if it is still running, `suspend_current` sees Ready and requeues it
+        task_inner.task_status = TaskStatus::Ready;
+        drop(task_inner);
+        if sleeping_tasks.remove(&task) {
+            drop(sleeping_tasks);
+            self.add(task);
+            true
+        } else {
+            false
+        }
     }
 
-    #[allow(unused)]
-    pub fn sleep(&mut self, task: Arc<TaskControlBlock>) {
-        self.scheduler.remove(&task);
-        self.sleeping_tasks.insert(task);
+    pub fn fetch(&self, hart: usize) -> Option<Arc<TaskControlBlock>> {
+        loop {
+            let task = self.schedulers[hart].lock().fetch();
+            match task {
+                // affinity changed while it was queued here
+                Some(task) if !task.can_run_on(hart) => self.add(task),
+                Some(task) => return Some(task),
+                None => return self.steal(hart),
+            }
+        }
     }
 
-    pub fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
-        self.scheduler.fetch()
+    /// Take a task from another hart when `hart` is idle. Busy queues are
+    /// skipped rather than waited for, so two thieves never deadlock.
+    fn steal(&self, hart: usize) -> Option<Arc<TaskControlBlock>> {
+        let mut thief = self.schedulers[hart].lock();
+        let stolen = (1..CPU_NUM)
+            .map(|offset| (hart + offset) % CPU_NUM)
+            .any(|victim| {
+                self.schedulers[victim]
+                    .try_lock()
+                    .map_or(false, |mut scheduler| scheduler.steal(&mut thief, hart))
+            });
+        if stolen {
+            thief.fetch()
+        } else {
+            None
+        }
     }
 
     #[allow(unused)]
-    pub fn prioritize(&mut self, pid: usize) {
-        self.scheduler.prioritize(pid);
+    pub fn prioritize(&self, pid: usize) {
+        for scheduler in self.schedulers.iter() {
+            scheduler.lock().prioritize(pid);
+        }
     }
 }
 
 pub fn add_task(task: Arc<TaskControlBlock>) {
     // let token = task.acquire_inner_lock().memory_set.token();
     // trace!("task pid: {}, satp: {:#x} added to pool", task.pid.0, token);
-    TASK_POOL.lock().add(task);
+    TASK_POOL.add(task);
 }
 
 pub fn fetch_task() -> Option<Arc<TaskControlBlock>> {
-    TASK_POOL.lock().fetch()
+    TASK_POOL.fetch(hart_id())
+}
+
+/// Wake a task blocked on a `WaitQueue`.
+pub fn wake_task(task: Arc<TaskControlBlock>) {
+    TASK_POOL.wake(task);
 }
 
 #[allow(unused)]
 pub fn prioritize_task(pid: usize) {
-    TASK_POOL.lock().prioritize(pid);
+    TASK_POOL.prioritize(pid);
 }
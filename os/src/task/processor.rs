@@ -1,9 +1,10 @@
 use super::TaskContext;
 use super::TaskControlBlock;
 use super::__switch2;
-use super::add_task;
+use super::pool::TASK_POOL;
 use super::{fetch_task, TaskStatus};
 use crate::config::CPU_NUM;
+use crate::errno::Errno;
 use crate::trace::SCHEDULE;
 use crate::trace::{push_trace, RUN_NEXT, SUSPEND_CURRENT};
 use crate::trap::TrapContext;
@@ -76,6 +77,7 @@ impl Processor {
         let mut task_inner = task.acquire_inner_lock();
         let next_task_cx_ptr = task_inner.get_task_cx_ptr();
         task_inner.task_status = TaskStatus::Running(hart_id());
+        task_inner.sched.ticks = 0;
         if let Some(trap_info) = &task_inner.user_trap_info {
             trap_info.enable_user_ext_int();
         }
@@ -98,11 +100,16 @@ impl Processor {
     fn suspend_current(&self) {
         trace!("[suspend current]");
         if let Some(task) = take_current_task() {
+            // sleeping set first, same as `wake_task`
+            let mut sleeping_tasks = TASK_POOL.sleeping_tasks.lock();
             // ---- hold current PCB lock
             // push_trace(SUSPEND_CURRENT + task.getpid());
             let mut task_inner = task.acquire_inner_lock();
-            // Change status to Ready
-            task_inner.task_status = TaskStatus::Ready;
+            let blocked = task_inner.task_status == TaskStatus::Blocked;
+            if !blocked {
+                // Change status to Ready
+                task_inner.task_status = TaskStatus::Ready;
+            }
             if let Some(trap_info) = &task_inner.user_trap_info {
                 trap_info.disable_user_ext_int();
             }
@@ -110,8 +117,13 @@ impl Processor {
             drop(task_inner);
             // ---- release current PCB lock
 
-            // push back to ready queue.
-            add_task(task);
+            if blocked {
+                sleeping_tasks.insert(task);
+            } else {
+                drop(sleeping_tasks);
+                // push back to ready queue.
+                TASK_POOL.add(task);
+            }
         }
     }
 
@@ -173,7 +185,7 @@ pub fn current_tasks() -> Vec<Option<Arc<TaskControlBlock>>> {
 
 pub fn current_user_token() -> usize {
     let task = current_task().unwrap();
-    let token = task.acquire_inner_lock().get_user_token();
+    let token = task.get_user_token();
     token
 }
 
@@ -199,29 +211,35 @@ pub fn schedule(switched_task_cx_ptr: *mut TaskContext) {
     }
 }
 
-pub fn set_current_priority(priority: isize) -> Result<isize, isize> {
+pub fn set_current_priority(priority: isize) -> Result<isize, Errno> {
     if let Some(current) = current_task() {
         let mut current = current.acquire_inner_lock();
         current.set_priority(priority)
     } else {
-        Err(-1)
+        Err(Errno::ESRCH)
     }
 }
 
-pub fn mmap(start: usize, len: usize, port: usize) -> Result<isize, isize> {
+pub fn mmap(start: usize, len: usize, port: usize) -> Result<isize, Errno> {
     if let Some(current) = current_task() {
-        let mut current = current.acquire_inner_lock();
         current.mmap(start, len, port)
     } else {
-        Err(-1)
+        Err(Errno::ESRCH)
     }
 }
 
-pub fn munmap(start: usize, len: usize) -> Result<isize, isize> {
+pub fn munmap(start: usize, len: usize) -> Result<isize, Errno> {
     if let Some(current) = current_task() {
-        let mut current = current.acquire_inner_lock();
         current.munmap(start, len)
     } else {
-        Err(-1)
+        Err(Errno::ESRCH)
+    }
+}
+
+pub fn mprotect(start: usize, len: usize, port: usize) -> Result<isize, Errno> {
+    if let Some(current) = current_task() {
+        current.mprotect(start, len, port)
+    } else {
+        Err(Errno::ESRCH)
     }
 }
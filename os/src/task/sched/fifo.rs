@@ -1,23 +1,25 @@
-use super::TaskControlBlock;
+use super::{Scheduler, TaskControlBlock};
 use alloc::collections::VecDeque;
 use alloc::sync::Arc;
 
-pub struct TaskManager {
+/// A simple FIFO scheduler.
+pub struct FifoScheduler {
     ready_queue: VecDeque<Arc<TaskControlBlock>>,
 }
 
-/// A simple FIFO scheduler.
-impl TaskManager {
+impl FifoScheduler {
     pub fn new() -> Self {
         Self {
             ready_queue: VecDeque::new(),
         }
     }
-    pub fn add(&mut self, task: Arc<TaskControlBlock>) {
+}
+
+impl Scheduler for FifoScheduler {
+    fn add(&mut self, task: Arc<TaskControlBlock>) {
         self.ready_queue.push_back(task);
     }
-    #[allow(unused)]
-    pub fn remove(&mut self, task: &Arc<TaskControlBlock>) {
+    fn remove(&mut self, task: &Arc<TaskControlBlock>) {
         for (idx, task_item) in self.ready_queue.iter().enumerate() {
             if *task_item == *task {
                 self.ready_queue.remove(idx);
@@ -25,13 +27,26 @@ impl TaskManager {
             }
         }
     }
-    pub fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
-        // May need to concern affinity
+    fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
         self.ready_queue.pop_front()
     }
 
-    #[allow(unused)]
-    pub fn prioritize(&mut self, pid: usize) {
+    fn steal(&mut self, thief: &mut Self, hart: usize) -> bool {
+        // the back waits longest before it would run here
+        let idx = self
+            .ready_queue
+            .iter()
+            .rposition(|task| task.can_run_on(hart));
+        match idx.and_then(|idx| self.ready_queue.remove(idx)) {
+            Some(task) => {
+                thief.ready_queue.push_back(task);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn prioritize(&mut self, pid: usize) {
         let q = &mut self.ready_queue;
         if q.is_empty() || q.len() == 1 {
             return;
@@ -54,23 +69,3 @@ impl TaskManager {
         }
     }
 }
-
-// lazy_static! {
-//     pub static ref TASK_MANAGER: Mutex<TaskManager> = Mutex::new(TaskManager::new());
-// }
-
-// pub fn add_task(task: Arc<TaskControlBlock>) {
-//     TASK_MANAGER.lock().add(task);
-// }
-
-// pub fn fetch_task() -> Option<Arc<TaskControlBlock>> {
-//     TASK_MANAGER.lock().fetch()
-// }
-
-// pub fn find_task(pid: usize) -> Option<Arc<TaskControlBlock>> {
-//     let current = current_task().unwrap();
-//     if current.pid == pid {
-//         return Some(current);
-//     }
-//     TASK_MANAGER.lock().find(pid)
-// }
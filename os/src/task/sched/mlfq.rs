@@ -0,0 +1,103 @@
+use super::{SchedEntity, Scheduler, TaskControlBlock};
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+
+const LEVEL_NUM: usize = 4;
+/// Move every task back to the top level after this many fetches.
+const BOOST_INTERVAL: usize = 1000;
+
+/// Multi-level feedback queue. Level `i` runs a task for `2^i` ticks, a
+/// task that uses up its allotment at one level moves down one level.
+pub struct MlfqScheduler {
+    ready_queues: [VecDeque<Arc<TaskControlBlock>>; LEVEL_NUM],
+    fetch_count: usize,
+}
+
+impl MlfqScheduler {
+    pub fn new() -> Self {
+        Self {
+            ready_queues: Default::default(),
+            fetch_count: 0,
+        }
+    }
+
+    fn boost(&mut self) {
+        for level in 1..LEVEL_NUM {
+            while let Some(task) = self.ready_queues[level].pop_front() {
+                let mut inner = task.acquire_inner_lock();
+                inner.sched.level = 0;
+                inner.sched.used = 0;
+                drop(inner);
+                self.ready_queues[0].push_back(task);
+            }
+        }
+    }
+}
+
+impl Scheduler for MlfqScheduler {
+    fn add(&mut self, task: Arc<TaskControlBlock>) {
+        let mut inner = task.acquire_inner_lock();
+        let sched = &mut inner.sched;
+        sched.used += sched.ticks;
+        sched.ticks = 0;
+        if sched.used >= 1 << sched.level {
+            sched.used = 0;
+            if sched.level + 1 < LEVEL_NUM {
+                sched.level += 1;
+            }
+        }
+        let level = sched.level;
+        drop(inner);
+        self.ready_queues[level].push_back(task);
+    }
+
+    fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
+        self.fetch_count += 1;
+        if self.fetch_count >= BOOST_INTERVAL {
+            self.fetch_count = 0;
+            self.boost();
+        }
+        self.ready_queues
+            .iter_mut()
+            .find(|queue| !queue.is_empty())
+            .and_then(|queue| queue.pop_front())
+    }
+
+    fn steal(&mut self, thief: &mut Self, hart: usize) -> bool {
+        // lowest level first, the local hart runs the upper levels soon anyway
+        for (level, queue) in self.ready_queues.iter_mut().enumerate().rev() {
+            if let Some(idx) = queue.iter().rposition(|task| task.can_run_on(hart)) {
+                let task = queue.remove(idx).unwrap();
+                thief.ready_queues[level].push_back(task);
+                return true;
+            }
+        }
+        false
+    }
+
+    fn remove(&mut self, task: &Arc<TaskControlBlock>) {
+        for queue in self.ready_queues.iter_mut() {
+            if let Some(idx) = queue.iter().position(|t| t == task) {
+                queue.remove(idx);
+                return;
+            }
+        }
+    }
+
+    fn prioritize(&mut self, pid: usize) {
+        for level in 0..LEVEL_NUM {
+            let queue = &mut self.ready_queues[level];
+            if let Some(idx) = queue.iter().position(|t| t.pid.0 == pid) {
+                let task = queue.remove(idx).unwrap();
+                task.acquire_inner_lock().sched.level = 0;
+                self.ready_queues[0].push_front(task);
+                debug!("[mlfq] Prioritized task {}", pid);
+                return;
+            }
+        }
+    }
+
+    fn time_slice(entity: &SchedEntity) -> usize {
+        (1usize << entity.level).saturating_sub(entity.used)
+    }
+}
@@ -0,0 +1,52 @@
+// only one of them is built in, see `SchedulerImpl`
+#[cfg(not(any(feature = "sched_stride", feature = "sched_mlfq")))]
+mod fifo;
+#[cfg(feature = "sched_mlfq")]
+mod mlfq;
+#[cfg(feature = "sched_stride")]
+mod stride;
+
+#[cfg(all(feature = "sched_stride", feature = "sched_mlfq"))]
+compile_error!("features sched_stride and sched_mlfq are mutually exclusive");
+
+use super::TaskControlBlock;
+use alloc::sync::Arc;
+
+/// Ready queue policy behind `TaskPool`.
+pub trait Scheduler {
+    fn add(&mut self, task: Arc<TaskControlBlock>);
+    fn fetch(&mut self) -> Option<Arc<TaskControlBlock>>;
+    fn remove(&mut self, task: &Arc<TaskControlBlock>);
+    /// Move a task allowed on `hart` over to `thief`, the queue of that
+    /// idle hart. Returns false if there is none.
+    fn steal(&mut self, thief: &mut Self, hart: usize) -> bool;
+    /// Let `pid` run as soon as possible, e.g. when it has a pending user interrupt.
+    fn prioritize(&mut self, pid: usize);
+    /// Kernel ticks the task may run before it is preempted.
+    fn time_slice(_entity: &SchedEntity) -> usize {
+        1
+    }
+}
+
+/// Per task bookkeeping of the schedulers, see `TaskControlBlockInner::sched`.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct SchedEntity {
+    /// stride: virtual time consumed
+    #[cfg(feature = "sched_stride")]
+    pub pass: usize,
+    /// mlfq: current queue level
+    #[cfg(feature = "sched_mlfq")]
+    pub level: usize,
+    /// mlfq: ticks used at the current level
+    #[cfg(feature = "sched_mlfq")]
+    pub used: usize,
+    /// ticks since the task was last picked
+    pub ticks: usize,
+}
+
+#[cfg(not(any(feature = "sched_stride", feature = "sched_mlfq")))]
+pub type SchedulerImpl = fifo::FifoScheduler;
+#[cfg(feature = "sched_stride")]
+pub type SchedulerImpl = stride::StrideScheduler;
+#[cfg(feature = "sched_mlfq")]
+pub type SchedulerImpl = mlfq::MlfqScheduler;
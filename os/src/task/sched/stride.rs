@@ -0,0 +1,116 @@
+use super::{Scheduler, TaskControlBlock};
+use alloc::collections::BinaryHeap;
+use alloc::sync::Arc;
+use core::cmp::Ordering;
+
+/// Passes wrap around, any two live passes differ by at most `BIG_STRIDE`.
+const BIG_STRIDE: usize = 1 << 20;
+
+struct StrideEntry {
+    pass: usize,
+    task: Arc<TaskControlBlock>,
+}
+
+impl PartialEq for StrideEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.pass == other.pass
+    }
+}
+
+impl Eq for StrideEntry {}
+
+impl PartialOrd for StrideEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for StrideEntry {
+    /// Reversed so that `BinaryHeap` pops the smallest pass first.
+    fn cmp(&self, other: &Self) -> Ordering {
+        0.cmp(&(self.pass.wrapping_sub(other.pass) as isize))
+    }
+}
+
+/// Stride scheduling, the stride of a task is `BIG_STRIDE / priority`.
+pub struct StrideScheduler {
+    ready_queue: BinaryHeap<StrideEntry>,
+    /// pass of the last fetched task, newcomers start from here
+    min_pass: usize,
+}
+
+impl StrideScheduler {
+    pub fn new() -> Self {
+        Self {
+            ready_queue: BinaryHeap::new(),
+            min_pass: 0,
+        }
+    }
+}
+
+impl Scheduler for StrideScheduler {
+    fn add(&mut self, task: Arc<TaskControlBlock>) {
+        let mut inner = task.acquire_inner_lock();
+        // do not let new or long sleeping tasks monopolize the harts
+        if (inner.sched.pass.wrapping_sub(self.min_pass) as isize) < 0 {
+            inner.sched.pass = self.min_pass;
+        }
+        let pass = inner.sched.pass;
+        drop(inner);
+        self.ready_queue.push(StrideEntry { pass, task });
+    }
+
+    fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
+        let StrideEntry { pass, task } = self.ready_queue.pop()?;
+        self.min_pass = pass;
+        let mut inner = task.acquire_inner_lock();
+        inner.sched.pass = pass.wrapping_add(BIG_STRIDE / inner.priority as usize);
+        drop(inner);
+        Some(task)
+    }
+
+    fn steal(&mut self, thief: &mut Self, hart: usize) -> bool {
+        let mut entries = core::mem::take(&mut self.ready_queue).into_vec();
+        // the smallest pass, as `fetch` would pick
+        let idx = entries
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| entry.task.can_run_on(hart))
+            .max_by(|(_, l), (_, r)| l.cmp(r))
+            .map(|(idx, _)| idx);
+        let stolen = idx.map(|idx| entries.swap_remove(idx));
+        self.ready_queue = entries.into();
+        match stolen {
+            Some(StrideEntry { pass, task }) => {
+                // passes of two queues are unrelated, keep the lag behind
+                // this one instead
+                let pass = thief
+                    .min_pass
+                    .wrapping_add(pass.wrapping_sub(self.min_pass));
+                task.acquire_inner_lock().sched.pass = pass;
+                thief.ready_queue.push(StrideEntry { pass, task });
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn remove(&mut self, task: &Arc<TaskControlBlock>) {
+        let mut entries = core::mem::take(&mut self.ready_queue).into_vec();
+        entries.retain(|entry| entry.task != *task);
+        self.ready_queue = entries.into();
+    }
+
+    fn prioritize(&mut self, pid: usize) {
+        let min_pass = self.min_pass;
+        let mut entries = core::mem::take(&mut self.ready_queue).into_vec();
+        for entry in entries.iter_mut() {
+            if entry.task.pid.0 == pid {
+                entry.pass = min_pass;
+                entry.task.acquire_inner_lock().sched.pass = min_pass;
+                debug!("[stride] Prioritized task {}", pid);
+            }
+        }
+        self.ready_queue = entries.into();
+    }
+}
@@ -0,0 +1,201 @@
+use super::{current_task, exit_current_and_run_next};
+use crate::errno::Errno;
+use crate::trap::UserTrapRecord;
+
+pub const MAX_SIG: usize = 31;
+
+pub const SIGKILL: usize = 9;
+pub const SIGCHLD: usize = 17;
+pub const SIGCONT: usize = 18;
+pub const SIGSTOP: usize = 19;
+pub const SIGTSTP: usize = 20;
+pub const SIGTTIN: usize = 21;
+pub const SIGTTOU: usize = 22;
+pub const SIGURG: usize = 23;
+pub const SIGWINCH: usize = 28;
+
+pub const SIG_DFL: usize = 0;
+pub const SIG_IGN: usize = 1;
+
+pub const SIG_BLOCK: usize = 0;
+pub const SIG_UNBLOCK: usize = 1;
+pub const SIG_SETMASK: usize = 2;
+
+/// `UserTrapRecord::cause` of a signal is `signo << 4 | SIGNAL_TRAP_CAUSE`,
+/// and its message is the handler address.
+pub const SIGNAL_TRAP_CAUSE: usize = 0xF;
+
+/// SIGKILL and SIGSTOP can not be caught, ignored or blocked.
+const UNCATCHABLE: u32 = 1 << SIGKILL | 1 << SIGSTOP;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct SignalAction {
+    /// `SIG_DFL`, `SIG_IGN` or a user function taking the signal number
+    pub handler: usize,
+}
+
+impl Default for SignalAction {
+    fn default() -> Self {
+        Self { handler: SIG_DFL }
+    }
+}
+
+/// Job control is not supported, so stop signals are ignored as well.
+fn ignored_by_default(signo: usize) -> bool {
+    matches!(
+        signo,
+        SIGCHLD | SIGCONT | SIGSTOP | SIGTSTP | SIGTTIN | SIGTTOU | SIGURG | SIGWINCH
+    )
+}
+
+/// Signal actions of a process, shared by its threads.
+#[derive(Debug, Clone)]
+pub struct SignalActions {
+    actions: [SignalAction; MAX_SIG + 1],
+}
+
+impl SignalActions {
+    pub fn new() -> Self {
+        Self {
+            actions: [SignalAction::default(); MAX_SIG + 1],
+        }
+    }
+
+    /// Handlers do not survive exec, ignored signals stay ignored.
+    pub fn reset_handlers(&mut self) {
+        for action in self.actions.iter_mut() {
+            if action.handler != SIG_IGN {
+                action.handler = SIG_DFL;
+            }
+        }
+    }
+
+    pub fn handler(&self, signo: usize) -> usize {
+        self.actions[signo].handler
+    }
+
+    /// Whether `signo` is discarded instead of made pending.
+    pub fn ignores(&self, signo: usize) -> bool {
+        let handler = self.actions[signo].handler;
+        handler == SIG_IGN || (handler == SIG_DFL && ignored_by_default(signo))
+    }
+
+    pub fn set_action(
+        &mut self,
+        signo: usize,
+        action: Option<SignalAction>,
+    ) -> Result<SignalAction, Errno> {
+        if signo == 0 || signo > MAX_SIG || UNCATCHABLE & 1 << signo != 0 {
+            return Err(Errno::EINVAL);
+        }
+        let old_action = self.actions[signo];
+        if let Some(action) = action {
+            self.actions[signo] = action;
+        }
+        Ok(old_action)
+    }
+}
+
+/// Per thread part of the signal state.
+#[derive(Debug, Clone)]
+pub struct SignalState {
+    pub pending: u32,
+    pub mask: u32,
+}
+
+impl SignalState {
+    pub fn new() -> Self {
+        Self {
+            pending: 0,
+            mask: 0,
+        }
+    }
+
+    /// The mask is inherited, pending signals are not.
+    pub fn fork(&self) -> Self {
+        Self {
+            pending: 0,
+            mask: self.mask,
+        }
+    }
+
+    /// Make `signo` pending unless `actions` ignore it, returns whether the
+    /// signal was kept.
+    pub fn raise(&mut self, signo: usize, actions: &SignalActions) -> bool {
+        if actions.ignores(signo) {
+            return false;
+        }
+        self.pending |= 1 << signo;
+        true
+    }
+
+    pub fn has_deliverable(&self) -> bool {
+        self.pending & !self.mask != 0
+    }
+
+    fn take_deliverable(&mut self) -> Option<usize> {
+        let deliverable = self.pending & !self.mask;
+        if deliverable == 0 {
+            return None;
+        }
+        let signo = deliverable.trailing_zeros() as usize;
+        self.pending &= !(1 << signo);
+        Some(signo)
+    }
+
+    /// Update the blocked set like sigprocmask, returns the old one.
+    pub fn set_mask(&mut self, how: usize, set: Option<u32>) -> Result<u32, Errno> {
+        let old_mask = self.mask;
+        if let Some(set) = set {
+            let set = set & !UNCATCHABLE;
+            self.mask = match how {
+                SIG_BLOCK => self.mask | set,
+                SIG_UNBLOCK => self.mask & !set,
+                SIG_SETMASK => set,
+                _ => return Err(Errno::EINVAL),
+            };
+        }
+        Ok(old_mask)
+    }
+}
+
+/// Act on pending unblocked signals of the current task before it returns
+/// to user mode. Handlers run through the user trap queue; without one the
+/// default action is taken, which terminates the whole process for most
+/// signals.
+pub fn handle_signals() {
+    let task = current_task().unwrap();
+    let mut guard = task.acquire_inner_lock();
+    let inner = &mut *guard;
+    while let Some(signo) = inner.signals.take_deliverable() {
+        let handler = task.sig_actions.lock().handler(signo);
+        if handler == SIG_IGN {
+            continue;
+        }
+        if handler != SIG_DFL {
+            if let Some(trap_info) = &mut inner.user_trap_info {
+                let record = UserTrapRecord {
+                    cause: signo << 4 | SIGNAL_TRAP_CAUSE,
+                    message: handler,
+                };
+                if trap_info.push_trap_record(record).is_err() {
+                    // retry after user mode drains the queue
+                    inner.signals.pending |= 1 << signo;
+                    break;
+                }
+                continue;
+            }
+        }
+        if ignored_by_default(signo) {
+            continue;
+        }
+        debug!("pid {} terminated by signal {}", task.getpid(), signo);
+        drop(guard);
+        // siblings exit with the signal too, so the leader reports it
+        task.thread_group.kill_others(task.thread_idx(), signo);
+        drop(task);
+        exit_current_and_run_next(-(signo as i32));
+        return;
+    }
+}
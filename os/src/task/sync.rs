@@ -0,0 +1,308 @@
+use super::{
+    block_current_and_run_next, current_task, suspend_current_and_run_next, wake_task, WaitQueue,
+};
+use crate::errno::Errno;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt::{self, Debug, Formatter};
+use spin::Mutex;
+
+/// Block on `queue` after `prepare_to_wait`. Returns Err if the task was
+/// interrupted, in which case it is no longer queued.
+fn block_on(queue: &WaitQueue) -> Result<(), Errno> {
+    let task = current_task().unwrap();
+    // a signal raised before `Blocked` was set found nothing to wake
+    if task.is_interrupted() {
+        wake_task(task.clone());
+    }
+    block_current_and_run_next();
+    if task.is_interrupted() {
+        // pass on a wakeup meant for us
+        if !queue.remove(&task) {
+            queue.wake_one();
+        }
+        return Err(Errno::EINTR);
+    }
+    Ok(())
+}
+
+/// Acquisition is split in `try_*` and `wait` so the caller can record a
+/// successful one in the same critical section that made it.
+pub struct KernelMutex {
+    /// Yield instead of sleeping while contended
+    spin: bool,
+    /// Thread id of the holder
+    owner: Mutex<Option<usize>>,
+    wait_queue: WaitQueue,
+}
+
+impl KernelMutex {
+    pub fn new(blocking: bool) -> Self {
+        Self {
+            spin: !blocking,
+            owner: Mutex::new(None),
+            wait_queue: WaitQueue::new(),
+        }
+    }
+
+    /// Take the mutex for `tid`, or get ready to `wait` for it.
+    pub fn try_lock(&self, tid: usize) -> bool {
+        let mut owner = self.owner.lock();
+        if owner.is_none() {
+            *owner = Some(tid);
+            return true;
+        }
+        if !self.spin {
+            self.wait_queue.prepare_to_wait();
+        }
+        false
+    }
+
+    /// Wait after a failed `try_lock`, then try again.
+    pub fn wait(&self) -> Result<(), Errno> {
+        if self.spin {
+            if current_task().unwrap().is_interrupted() {
+                return Err(Errno::EINTR);
+            }
+            suspend_current_and_run_next();
+            Ok(())
+        } else {
+            block_on(&self.wait_queue)
+        }
+    }
+
+    /// Fails with EPERM unless `tid` holds the mutex.
+    pub fn unlock(&self, tid: usize) -> Result<(), Errno> {
+        let mut owner = self.owner.lock();
+        if *owner != Some(tid) {
+            return Err(Errno::EPERM);
+        }
+        *owner = None;
+        drop(owner);
+        self.wait_queue.wake_one();
+        Ok(())
+    }
+}
+
+pub struct Semaphore {
+    count: Mutex<usize>,
+    wait_queue: WaitQueue,
+}
+
+impl Semaphore {
+    pub fn new(count: usize) -> Self {
+        Self {
+            count: Mutex::new(count),
+            wait_queue: WaitQueue::new(),
+        }
+    }
+
+    pub fn up(&self) {
+        *self.count.lock() += 1;
+        self.wait_queue.wake_one();
+    }
+
+    /// Take one unit, or get ready to `wait` for it.
+    pub fn try_down(&self) -> bool {
+        let mut count = self.count.lock();
+        if *count > 0 {
+            *count -= 1;
+            return true;
+        }
+        self.wait_queue.prepare_to_wait();
+        false
+    }
+
+    /// Wait after a failed `try_down`, then try again.
+    pub fn wait(&self) -> Result<(), Errno> {
+        block_on(&self.wait_queue)
+    }
+}
+
+pub struct Condvar {
+    wait_queue: WaitQueue,
+}
+
+impl Condvar {
+    pub fn new() -> Self {
+        Self {
+            wait_queue: WaitQueue::new(),
+        }
+    }
+
+    pub fn signal(&self) {
+        self.wait_queue.wake_one();
+    }
+
+    /// Queue the caller and release `mutex`, held by `tid`. Block with
+    /// `wait` next, and lock the mutex again afterwards.
+    pub fn unlock_and_queue(&self, mutex: &KernelMutex, tid: usize) -> Result<(), Errno> {
+        if *mutex.owner.lock() != Some(tid) {
+            return Err(Errno::EPERM);
+        }
+        // queued before unlocking, so a signal right after is not lost
+        self.wait_queue.prepare_to_wait();
+        mutex.unlock(tid)
+    }
+
+    /// Block until signalled, Err if interrupted.
+    pub fn wait(&self) -> Result<(), Errno> {
+        block_on(&self.wait_queue)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Resource {
+    Mutex(usize),
+    Semaphore(usize),
+}
+
+/// Resource allocation state for the banker's algorithm, by thread id.
+#[derive(Debug, Default)]
+struct Banker {
+    available: BTreeMap<Resource, usize>,
+    allocation: BTreeMap<(usize, Resource), usize>,
+    /// Requested but not granted yet
+    need: BTreeMap<(usize, Resource), usize>,
+}
+
+fn increase(map: &mut BTreeMap<(usize, Resource), usize>, key: (usize, Resource)) {
+    *map.entry(key).or_insert(0) += 1;
+}
+
+fn decrease(map: &mut BTreeMap<(usize, Resource), usize>, key: (usize, Resource)) {
+    if let Some(n) = map.get_mut(&key) {
+        *n -= 1;
+        if *n == 0 {
+            map.remove(&key);
+        }
+    }
+}
+
+impl Banker {
+    /// Whether all threads can finish in some order, each getting what it
+    /// requested and then releasing what it holds.
+    fn is_safe(&self) -> bool {
+        let mut work = self.available.clone();
+        let mut unfinished: BTreeSet<usize> = self
+            .allocation
+            .keys()
+            .chain(self.need.keys())
+            .map(|(tid, _)| *tid)
+            .collect();
+        loop {
+            let runnable = unfinished.iter().copied().find(|tid| {
+                self.need
+                    .iter()
+                    .filter(|((t, _), _)| t == tid)
+                    .all(|((_, res), n)| work.get(res).copied().unwrap_or(0) >= *n)
+            });
+            match runnable {
+                Some(tid) => {
+                    for ((_, res), n) in self.allocation.iter().filter(|((t, _), _)| *t == tid) {
+                        *work.entry(*res).or_insert(0) += n;
+                    }
+                    unfinished.remove(&tid);
+                }
+                None => return unfinished.is_empty(),
+            }
+        }
+    }
+}
+
+/// Kernel sync objects of a process, shared by its threads. Ids index
+/// the per-kind lists.
+#[derive(Default)]
+pub struct SyncTable {
+    mutexes: Vec<Arc<KernelMutex>>,
+    semaphores: Vec<Arc<Semaphore>>,
+    condvars: Vec<Arc<Condvar>>,
+    deadlock_detect: bool,
+    banker: Banker,
+}
+
+impl SyncTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_mutex(&mut self, blocking: bool) -> usize {
+        self.mutexes.push(Arc::new(KernelMutex::new(blocking)));
+        let id = self.mutexes.len() - 1;
+        self.banker.available.insert(Resource::Mutex(id), 1);
+        id
+    }
+
+    pub fn add_semaphore(&mut self, count: usize) -> usize {
+        self.semaphores.push(Arc::new(Semaphore::new(count)));
+        let id = self.semaphores.len() - 1;
+        self.banker.available.insert(Resource::Semaphore(id), count);
+        id
+    }
+
+    pub fn add_condvar(&mut self) -> usize {
+        self.condvars.push(Arc::new(Condvar::new()));
+        self.condvars.len() - 1
+    }
+
+    pub fn mutex(&self, id: usize) -> Result<Arc<KernelMutex>, Errno> {
+        self.mutexes.get(id).cloned().ok_or(Errno::EINVAL)
+    }
+
+    pub fn semaphore(&self, id: usize) -> Result<Arc<Semaphore>, Errno> {
+        self.semaphores.get(id).cloned().ok_or(Errno::EINVAL)
+    }
+
+    pub fn condvar(&self, id: usize) -> Result<Arc<Condvar>, Errno> {
+        self.condvars.get(id).cloned().ok_or(Errno::EINVAL)
+    }
+
+    pub fn set_deadlock_detect(&mut self, enabled: bool) {
+        self.deadlock_detect = enabled;
+    }
+
+    /// Record that `tid` is about to wait for `res`. With deadlock
+    /// detection on, EDEADLK is returned if that leaves an unsafe state.
+    pub fn request(&mut self, tid: usize, res: Resource) -> Result<(), Errno> {
+        increase(&mut self.banker.need, (tid, res));
+        if self.deadlock_detect && !self.banker.is_safe() {
+            decrease(&mut self.banker.need, (tid, res));
+            return Err(Errno::EDEADLK);
+        }
+        Ok(())
+    }
+
+    /// Settle a `request`, either granted or given up. A grant must be
+    /// recorded under the same lock as the acquisition, or `is_safe` may
+    /// see a held resource as available.
+    pub fn finish_request(&mut self, tid: usize, res: Resource, granted: bool) {
+        decrease(&mut self.banker.need, (tid, res));
+        if granted {
+            increase(&mut self.banker.allocation, (tid, res));
+            if let Some(n) = self.banker.available.get_mut(&res) {
+                *n = n.saturating_sub(1);
+            }
+        }
+    }
+
+    pub fn release(&mut self, tid: usize, res: Resource) {
+        decrease(&mut self.banker.allocation, (tid, res));
+        match res {
+            Resource::Mutex(_) => *self.banker.available.entry(res).or_insert(0) = 1,
+            // may be up'ed by a thread that never took it
+            Resource::Semaphore(_) => *self.banker.available.entry(res).or_insert(0) += 1,
+        }
+    }
+}
+
+impl Debug for SyncTable {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_fmt(format_args!(
+            "SyncTable({} mutexes, {} semaphores, {} condvars)",
+            self.mutexes.len(),
+            self.semaphores.len(),
+            self.condvars.len()
+        ))
+    }
+}
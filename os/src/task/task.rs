@@ -1,25 +1,56 @@
+use super::signal::{SignalActions, SignalState};
+use super::sync::SyncTable;
+use super::thread::{map_thread, thread_stack_top, trap_cx_va, unmap_thread, ThreadGroup};
+use super::SchedEntity;
 use super::TaskContext;
+use super::WaitQueue;
 use super::{pid_alloc, KernelStack, PidHandle};
+use crate::board::hart_num;
+use crate::errno::Errno;
 use crate::fs::{File, MailBox, Serial, Socket, Stdin, Stdout};
-use crate::mm::{translate_writable_va, MemorySet, PhysAddr, PhysPageNum, VirtAddr, KERNEL_SPACE};
+use crate::mm::{
+    copy_to_user, translated_byte_buffer_mut, MapPermission, MemorySet, PhysAddr, PhysPageNum,
+    VirtAddr, KERNEL_SPACE,
+};
 use crate::task::pid::add_task_2_map;
 use crate::trap::{trap_handler, TrapContext, UserTrapInfo, UserTrapQueue};
 use crate::{
-    config::{PAGE_SIZE, TRAP_CONTEXT, USER_TRAP_BUFFER},
+    config::{CPU_NUM, PAGE_SIZE, TRAP_CONTEXT, USER_STACK_SIZE, USER_TRAP_BUFFER},
     loader::get_app_data_by_name,
-    mm::translated_str,
 };
+use alloc::string::String;
 use alloc::sync::{Arc, Weak};
 use alloc::vec;
 use alloc::vec::Vec;
 use core::fmt::{self, Debug, Formatter};
+use core::mem::size_of;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicUsize, Ordering};
 use spin::{Mutex, MutexGuard};
 
+const ALL_HARTS: usize = (1 << CPU_NUM) - 1;
+
 #[derive(Debug)]
 pub struct TaskControlBlock {
     // immutable
     pub pid: PidHandle,
     pub kernel_stack: KernelStack,
+    /// Parent blocked in waitpid
+    pub wait_child: WaitQueue,
+    /// Harts allowed to run this task, one bit per hart
+    cpu_mask: AtomicUsize,
+    pub thread_group: Arc<ThreadGroup>,
+    /// Index in the thread group, 0 for the leader
+    thread_idx: usize,
+    /// Signal to exit with at the next trap, 0 if not killed
+    killed: AtomicUsize,
+    pub fd_table: Arc<Mutex<FdTable>>,
+    /// Locked before `inner`
+    pub sync_table: Arc<Mutex<SyncTable>>,
+    /// Shared by threads, locked after `inner`
+    pub sig_actions: Arc<Mutex<SignalActions>>,
+    /// Shared by threads, locked after `inner`
+    pub memory_set: Arc<Mutex<MemorySet>>,
     // mutable
     inner: Mutex<TaskControlBlockInner>,
 }
@@ -32,11 +63,11 @@ pub struct TaskControlBlockInner {
     pub user_trap_info: Option<UserTrapInfo>,
     pub task_status: TaskStatus,
     pub priority: isize,
-    pub memory_set: MemorySet,
+    pub sched: SchedEntity,
+    pub signals: SignalState,
     pub parent: Option<Weak<TaskControlBlock>>,
     pub children: Vec<Arc<TaskControlBlock>>,
     pub exit_code: i32,
-    pub fd_table: Vec<Option<Arc<dyn File + Send + Sync>>>,
     pub mail_box: Arc<MailBox>,
     pub time_intr_count: usize,
     pub total_cpu_cycle_count: usize,
@@ -46,8 +77,8 @@ pub struct TaskControlBlockInner {
 impl Debug for TaskControlBlockInner {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         f.write_fmt(format_args!(
-            "TCBInner: {{\r\n  trap cx addr: {:?} , base_size: {:#x} \r\n  task_cx_ptr: {:#x} , token: {:#x} \r\n}}",
-            PhysAddr::from(self.trap_cx_ppn), self.base_size, self.task_cx_ptr, self.memory_set.token()
+            "TCBInner: {{\r\n  trap cx addr: {:?} , base_size: {:#x} \r\n  task_cx_ptr: {:#x} \r\n}}",
+            PhysAddr::from(self.trap_cx_ppn), self.base_size, self.task_cx_ptr
         ))
     }
 }
@@ -64,9 +95,6 @@ impl TaskControlBlockInner {
     pub fn get_trap_cx(&self) -> &'static mut TrapContext {
         self.trap_cx_ppn.get_mut()
     }
-    pub fn get_user_token(&self) -> usize {
-        self.memory_set.token()
-    }
     fn get_status(&self) -> TaskStatus {
         self.task_status
     }
@@ -74,31 +102,14 @@ impl TaskControlBlockInner {
         self.get_status() == TaskStatus::Zombie
     }
 
-    pub fn set_priority(&mut self, priority: isize) -> Result<isize, isize> {
+    pub fn set_priority(&mut self, priority: isize) -> Result<isize, Errno> {
         if priority < 2 {
-            return Err(-1);
+            return Err(Errno::EINVAL);
         }
         self.priority = priority;
         Ok(priority)
     }
 
-    pub fn mmap(&mut self, start: usize, len: usize, port: usize) -> Result<isize, isize> {
-        self.memory_set.mmap(start, len, port)
-    }
-
-    pub fn munmap(&mut self, start: usize, len: usize) -> Result<isize, isize> {
-        self.memory_set.munmap(start, len)
-    }
-
-    pub fn alloc_fd(&mut self) -> usize {
-        if let Some(fd) = (0..self.fd_table.len()).find(|fd| self.fd_table[*fd].is_none()) {
-            fd
-        } else {
-            self.fd_table.push(None);
-            self.fd_table.len() - 1
-        }
-    }
-
     pub fn is_mailbox_full(&self) -> bool {
         self.mail_box.is_full()
     }
@@ -111,15 +122,27 @@ impl TaskControlBlockInner {
         self.get_trap_cx().sstatus.uie()
     }
 
-    pub fn init_user_trap(&mut self) -> Result<isize, isize> {
+    pub fn init_user_trap(&mut self, memory_set: &mut MemorySet) -> Result<isize, Errno> {
         use riscv::register::sstatus;
         if self.user_trap_info.is_none() {
             // R | W
-            if self.mmap(USER_TRAP_BUFFER, PAGE_SIZE, 0b11).is_ok() {
-                let phys_addr =
-                    translate_writable_va(self.get_user_token(), USER_TRAP_BUFFER).unwrap();
+            if let Err(err) = memory_set.mmap(USER_TRAP_BUFFER, PAGE_SIZE, 0b11) {
+                warn!("[init user trap] mmap failed!");
+                Err(err)
+            } else {
+                // the kernel writes the buffer by its physical address
+                if let Err(err) = memory_set
+                    .handle_page_fault(VirtAddr::from(USER_TRAP_BUFFER).floor(), MapPermission::W)
+                {
+                    memory_set.munmap(USER_TRAP_BUFFER, PAGE_SIZE).unwrap();
+                    return Err(err);
+                }
+                // the lock is held here, so not through translate_writable_va
+                let pte = memory_set
+                    .translate(VirtAddr::from(USER_TRAP_BUFFER).floor())
+                    .unwrap();
                 self.user_trap_info = Some(UserTrapInfo {
-                    user_trap_buffer_ppn: PhysPageNum::from(PhysAddr::from(phys_addr)),
+                    user_trap_buffer_ppn: pte.ppn(),
                     devices: Vec::new(),
                 });
                 let trap_queue = self.user_trap_info.as_mut().unwrap().get_trap_queue_mut();
@@ -127,14 +150,12 @@ impl TaskControlBlockInner {
                 unsafe {
                     sstatus::set_uie();
                 }
-                return Ok(USER_TRAP_BUFFER as isize);
-            } else {
-                warn!("[init user trap] mmap failed!");
+                Ok(USER_TRAP_BUFFER as isize)
             }
         } else {
             warn!("[init user trap] self user trap info is not None!");
+            Err(Errno::EEXIST)
         }
-        Err(-1)
     }
 
     pub fn restore_user_trap_info(&mut self) {
@@ -166,22 +187,32 @@ impl TaskControlBlock {
     }
     pub fn new(elf_data: &[u8]) -> Arc<TaskControlBlock> {
         // memory_set with elf program headers/trampoline/trap context/user stack
-        let (memory_set, user_sp, entry_point) = MemorySet::from_elf(elf_data);
+        let (memory_set, user_sp, entry_point) = MemorySet::from_elf(elf_data).unwrap();
         let trap_cx_ppn = memory_set
             .translate(VirtAddr::from(TRAP_CONTEXT).into())
             .unwrap()
             .ppn();
         // alloc a pid and a kernel stack in kernel space
         let pid_handle = pid_alloc();
-        let kernel_stack = KernelStack::new(&pid_handle);
+        let kernel_stack = KernelStack::new().unwrap();
         let kernel_stack_top = kernel_stack.get_top();
         // push a task context which goes to trap_return to the top of kernel stack
         let task_cx = TaskContext::goto_trap_return(kernel_stack_top, pid_handle.0);
         let task_cx_ptr = kernel_stack.push_on_top(task_cx.clone());
         trace!("new task cx ptr: {:#x?}", task_cx_ptr as usize);
+        let thread_group = Arc::new(ThreadGroup::new(pid_handle.0));
         let task_control_block = Arc::new(TaskControlBlock {
             pid: pid_handle,
             kernel_stack,
+            wait_child: WaitQueue::new(),
+            cpu_mask: AtomicUsize::new(ALL_HARTS),
+            thread_group,
+            thread_idx: 0,
+            killed: AtomicUsize::new(0),
+            fd_table: Arc::new(Mutex::new(FdTable::new())),
+            sync_table: Arc::new(Mutex::new(SyncTable::new())),
+            sig_actions: Arc::new(Mutex::new(SignalActions::new())),
+            memory_set: Arc::new(Mutex::new(memory_set)),
             inner: Mutex::new(TaskControlBlockInner {
                 trap_cx_ppn,
                 base_size: user_sp,
@@ -189,23 +220,12 @@ impl TaskControlBlock {
                 task_cx_ptr: task_cx_ptr as usize,
                 user_trap_info: None,
                 task_status: TaskStatus::Ready,
-                memory_set,
                 parent: None,
                 children: Vec::new(),
                 exit_code: 0,
                 priority: 16,
-                fd_table: vec![
-                    // 0 -> stdin
-                    Some(Arc::new(Stdin)),
-                    // 1 -> stdout
-                    Some(Arc::new(Stdout)),
-                    // 2 -> stderr
-                    Some(Arc::new(Stdout)),
-                    // 3 -> serial 3
-                    Some(Arc::new(Serial::<2>)),
-                    // 4 -> serial 4
-                    Some(Arc::new(Serial::<3>)),
-                ],
+                sched: SchedEntity::default(),
+                signals: SignalState::new(),
                 mail_box: Arc::new(MailBox::new()),
                 time_intr_count: 0,
                 total_cpu_cycle_count: 0,
@@ -213,11 +233,16 @@ impl TaskControlBlock {
             }),
         });
         add_task_2_map(task_control_block.getpid(), task_control_block.clone());
+        task_control_block
+            .thread_group
+            .attach(0, &task_control_block);
         // prepare TrapContext in user space
         let trap_cx = task_control_block.acquire_inner_lock().get_trap_cx();
         *trap_cx = TrapContext::app_init_context(
             entry_point,
             user_sp,
+            0,
+            0,
             KERNEL_SPACE.lock().token(),
             kernel_stack_top,
             trap_handler as usize,
@@ -225,19 +250,29 @@ impl TaskControlBlock {
         task_control_block
     }
 
-    pub fn exec(&self, elf_data: &[u8]) {
+    /// Only a process without other threads may exec, since they would
+    /// keep running on the old address space.
+    pub fn exec(&self, elf_data: &[u8], args: Vec<String>) -> Result<(), Errno> {
+        if self.thread_idx != 0 || self.thread_group.live() > 1 {
+            return Err(Errno::EBUSY);
+        }
         // memory_set with elf program headers/trampoline/trap context/user stack
-        let (memory_set, user_sp, entry_point) = MemorySet::from_elf(elf_data);
+        let (memory_set, user_sp, entry_point) = MemorySet::from_elf(elf_data)?;
         let trap_cx_ppn = memory_set
             .translate(VirtAddr::from(TRAP_CONTEXT).into())
             .unwrap()
             .ppn();
+        let (user_sp, argv_base) = push_args(memory_set.token(), user_sp, &args)?;
 
+        // waiters queue themselves with the sync table locked, so it is
+        // not taken under the PCB lock
+        *self.sync_table.lock() = SyncTable::new();
         // **** hold current PCB lock
         let mut inner = self.acquire_inner_lock();
         inner.user_trap_info = None;
+        self.sig_actions.lock().reset_handlers();
         // substitute memory_set
-        inner.memory_set = memory_set;
+        *self.memory_set.lock() = memory_set;
         // update trap_cx ppn
         inner.trap_cx_ppn = trap_cx_ppn;
         // initialize trap_cx
@@ -245,39 +280,51 @@ impl TaskControlBlock {
         *trap_cx = TrapContext::app_init_context(
             entry_point,
             user_sp,
+            args.len(),
+            argv_base,
             KERNEL_SPACE.lock().token(),
             self.kernel_stack.get_top(),
             trap_handler as usize,
         );
+        Ok(())
         // **** release current PCB lock
     }
 
-    pub fn fork(self: &Arc<TaskControlBlock>) -> Arc<TaskControlBlock> {
+    /// The child only has a copy of the calling thread, which becomes its
+    /// leader.
+    pub fn fork(self: &Arc<TaskControlBlock>) -> Result<Arc<TaskControlBlock>, Errno> {
         // ---- hold parent PCB lock
         let mut parent_inner = self.acquire_inner_lock();
         // copy user space(include trap context)
-        let memory_set = MemorySet::from_existed_user(&parent_inner.memory_set);
+        let mut memory_set = MemorySet::from_existed_user(&mut self.memory_set.lock())?;
+        for idx in self.thread_group.other_idxs(self.thread_idx) {
+            unmap_thread(&mut memory_set, idx);
+        }
         let trap_cx_ppn = memory_set
             .translate(VirtAddr::from(TRAP_CONTEXT).into())
             .unwrap()
             .ppn();
+        if self.thread_idx != 0 {
+            trap_cx_ppn
+                .get_bytes_array()
+                .copy_from_slice(parent_inner.trap_cx_ppn.get_bytes_array());
+            memory_set.remove_area_with_start_vpn(VirtAddr::from(self.trap_cx_va()).into());
+        }
         // alloc a pid and a kernel stack in kernel space
         let pid_handle = pid_alloc();
-        let kernel_stack = KernelStack::new(&pid_handle);
+        let kernel_stack = KernelStack::new()?;
         let kernel_stack_top = kernel_stack.get_top();
         // push a goto_trap_return task_cx on the top of kernel stack
         let task_cx = TaskContext::goto_trap_return(kernel_stack_top, pid_handle.0);
         let task_cx_ptr = kernel_stack.push_on_top(task_cx.clone());
         debug!("forked task cx ptr: {:#x?}", task_cx_ptr as usize);
-        // copy fd table
-        let mut new_fd_table: Vec<Option<Arc<dyn File + Send + Sync>>> = Vec::new();
-        for fd in parent_inner.fd_table.iter() {
-            if let Some(file) = fd {
-                new_fd_table.push(Some(file.clone()));
-            } else {
-                new_fd_table.push(None);
-            }
+        let thread_group = Arc::new(ThreadGroup::new(pid_handle.0));
+        if self.thread_idx != 0 {
+            // the child still runs on the stack of this thread
+            thread_group.reserve(self.thread_idx);
         }
+        // copy fd table
+        let fd_table = self.fd_table.lock().clone();
         let mut user_trap_info: Option<UserTrapInfo> = None;
         if let Some(mut trap_info) = parent_inner.user_trap_info.clone() {
             debug!("[fork] copy parent trap info");
@@ -290,6 +337,15 @@ impl TaskControlBlock {
         let task_control_block = Arc::new(TaskControlBlock {
             pid: pid_handle,
             kernel_stack,
+            wait_child: WaitQueue::new(),
+            cpu_mask: AtomicUsize::new(self.cpu_mask()),
+            thread_group,
+            thread_idx: 0,
+            killed: AtomicUsize::new(0),
+            fd_table: Arc::new(Mutex::new(fd_table)),
+            sync_table: Arc::new(Mutex::new(SyncTable::new())),
+            sig_actions: Arc::new(Mutex::new(self.sig_actions.lock().clone())),
+            memory_set: Arc::new(Mutex::new(memory_set)),
             inner: Mutex::new(TaskControlBlockInner {
                 trap_cx_ppn,
                 base_size: parent_inner.base_size,
@@ -297,12 +353,12 @@ impl TaskControlBlock {
                 task_cx_ptr: task_cx_ptr as usize,
                 user_trap_info,
                 task_status: TaskStatus::Ready,
-                memory_set,
                 parent: Some(Arc::downgrade(self)),
                 children: Vec::new(),
                 exit_code: 0,
                 priority: 16,
-                fd_table: new_fd_table,
+                sched: SchedEntity::default(),
+                signals: parent_inner.signals.fork(),
                 mail_box: Arc::new(MailBox::new()),
                 time_intr_count: 0,
                 total_cpu_cycle_count: 0,
@@ -310,6 +366,9 @@ impl TaskControlBlock {
             }),
         });
         add_task_2_map(task_control_block.getpid(), task_control_block.clone());
+        task_control_block
+            .thread_group
+            .attach(0, &task_control_block);
         // add child
         parent_inner.children.push(task_control_block.clone());
         // modify kernel_sp in trap_cx
@@ -318,62 +377,211 @@ impl TaskControlBlock {
         // **** release child PCB lock
         trap_cx.kernel_sp = kernel_stack_top;
         // return
-        task_control_block
+        Ok(task_control_block)
         // ---- release parent PCB lock
     }
+
+    /// Start a thread at `entry` with `arg` in a0, sharing the address
+    /// space and fd table of this task.
+    pub fn create_thread(
+        self: &Arc<TaskControlBlock>,
+        entry: usize,
+        arg: usize,
+    ) -> Result<Arc<TaskControlBlock>, Errno> {
+        let idx = self.thread_group.alloc_idx()?;
+        let inner = self.acquire_inner_lock();
+        let base_size = inner.base_size;
+        let priority = inner.priority;
+        let signals = inner.signals.fork();
+        drop(inner);
+        let trap_cx_ppn = {
+            let mut memory_set = self.memory_set.lock();
+            if let Err(errno) = map_thread(&mut memory_set, idx) {
+                drop(memory_set);
+                self.thread_group.free_idx(idx);
+                return Err(errno);
+            }
+            memory_set
+                .translate(VirtAddr::from(trap_cx_va(idx)).into())
+                .unwrap()
+                .ppn()
+        };
+        let pid_handle = pid_alloc();
+        let kernel_stack = match KernelStack::new() {
+            Ok(kernel_stack) => kernel_stack,
+            Err(errno) => {
+                unmap_thread(&mut self.memory_set.lock(), idx);
+                self.thread_group.free_idx(idx);
+                return Err(errno);
+            }
+        };
+        let kernel_stack_top = kernel_stack.get_top();
+        let task_cx = TaskContext::goto_trap_return(kernel_stack_top, pid_handle.0);
+        let task_cx_ptr = kernel_stack.push_on_top(task_cx.clone());
+        trace!("thread task cx ptr: {:#x?}", task_cx_ptr as usize);
+        let task_control_block = Arc::new(TaskControlBlock {
+            pid: pid_handle,
+            kernel_stack,
+            wait_child: WaitQueue::new(),
+            cpu_mask: AtomicUsize::new(self.cpu_mask()),
+            thread_group: self.thread_group.clone(),
+            thread_idx: idx,
+            killed: AtomicUsize::new(0),
+            fd_table: self.fd_table.clone(),
+            sync_table: self.sync_table.clone(),
+            sig_actions: self.sig_actions.clone(),
+            memory_set: self.memory_set.clone(),
+            inner: Mutex::new(TaskControlBlockInner {
+                trap_cx_ppn,
+                base_size,
+                task_cx,
+                task_cx_ptr: task_cx_ptr as usize,
+                user_trap_info: None,
+                task_status: TaskStatus::Ready,
+                parent: None,
+                children: Vec::new(),
+                exit_code: 0,
+                priority,
+                sched: SchedEntity::default(),
+                signals,
+                mail_box: Arc::new(MailBox::new()),
+                time_intr_count: 0,
+                total_cpu_cycle_count: 0,
+                last_cpu_cycle: 0,
+            }),
+        });
+        add_task_2_map(task_control_block.getpid(), task_control_block.clone());
+        self.thread_group.attach(idx, &task_control_block);
+        let trap_cx = task_control_block.acquire_inner_lock().get_trap_cx();
+        *trap_cx = TrapContext::app_init_context(
+            entry,
+            thread_stack_top(idx),
+            arg,
+            0,
+            KERNEL_SPACE.lock().token(),
+            kernel_stack_top,
+            trap_handler as usize,
+        );
+        Ok(task_control_block)
+    }
+
     pub fn getpid(&self) -> usize {
         self.pid.0
     }
 
+    pub fn get_user_token(&self) -> usize {
+        self.memory_set.lock().token()
+    }
+
+    pub fn mmap(&self, start: usize, len: usize, port: usize) -> Result<isize, Errno> {
+        self.memory_set.lock().mmap(start, len, port)
+    }
+
+    pub fn munmap(&self, start: usize, len: usize) -> Result<isize, Errno> {
+        self.memory_set.lock().munmap(start, len)
+    }
+
+    pub fn mprotect(&self, start: usize, len: usize, port: usize) -> Result<isize, Errno> {
+        self.memory_set.lock().mprotect(start, len, port)
+    }
+
+    pub fn thread_idx(&self) -> usize {
+        self.thread_idx
+    }
+
+    pub fn trap_cx_va(&self) -> usize {
+        trap_cx_va(self.thread_idx)
+    }
+
+    /// Make the task exit with `signo` at its next trap, the first kill
+    /// wins.
+    pub fn kill(&self, signo: usize) {
+        let _ = self
+            .killed
+            .compare_exchange(0, signo, Ordering::Relaxed, Ordering::Relaxed);
+    }
+
+    pub fn is_killed(&self) -> bool {
+        self.kill_signal() != 0
+    }
+
+    /// Signal the task was killed with, 0 if none.
+    pub fn kill_signal(&self) -> usize {
+        self.killed.load(Ordering::Relaxed)
+    }
+
+    /// A blocking syscall should give up with EINTR.
+    pub fn is_interrupted(&self) -> bool {
+        self.is_killed() || self.acquire_inner_lock().signals.has_deliverable()
+    }
+
+    pub fn cpu_mask(&self) -> usize {
+        self.cpu_mask.load(Ordering::Relaxed)
+    }
+
+    pub fn set_cpu_mask(&self, mask: usize) -> Result<(), Errno> {
+        // harts past the ones found never run anything
+        let mask = mask & ((1 << hart_num()) - 1);
+        if mask == 0 {
+            return Err(Errno::EINVAL);
+        }
+        self.cpu_mask.store(mask, Ordering::Relaxed);
+        Ok(())
+    }
+
+    pub fn can_run_on(&self, hart: usize) -> bool {
+        self.cpu_mask() & (1 << hart) != 0
+    }
+
     pub fn spawn(
         self: &Arc<TaskControlBlock>,
-        file: *const u8,
-    ) -> Result<Arc<TaskControlBlock>, isize> {
+        path: &str,
+        args: Vec<String>,
+    ) -> Result<Arc<TaskControlBlock>, Errno> {
         let mut parent_inner = self.acquire_inner_lock();
-        let parent_token = parent_inner.get_user_token();
-        let f = translated_str(parent_token, file);
-        debug!("SPAWN exec {:?}", &f);
+        debug!("SPAWN exec {:?} {:?}", path, &args);
 
-        if let Some(elf_data) = get_app_data_by_name(f.as_str()) {
-            let (memory_set, user_sp, entry_point) = MemorySet::from_elf(elf_data);
+        if let Some(elf_data) = get_app_data_by_name(path) {
+            let (memory_set, user_sp, entry_point) = MemorySet::from_elf(elf_data)?;
             let trap_cx_ppn = memory_set
                 .translate(VirtAddr::from(TRAP_CONTEXT).into())
                 .unwrap()
                 .ppn();
+            let base_size = user_sp;
+            let (user_sp, argv_base) = push_args(memory_set.token(), user_sp, &args)?;
             let pid_handle = pid_alloc();
-            let kernel_stack = KernelStack::new(&pid_handle);
+            let kernel_stack = KernelStack::new()?;
             let kernel_stack_top = kernel_stack.get_top();
             let task_cx = TaskContext::goto_trap_return(kernel_stack_top, pid_handle.0);
             let task_cx_ptr = kernel_stack.push_on_top(task_cx.clone());
             trace!("spawned task cx ptr: {:#x?}", task_cx_ptr as usize);
+            let thread_group = Arc::new(ThreadGroup::new(pid_handle.0));
 
             let task_control_block = Arc::new(TaskControlBlock {
                 pid: pid_handle,
                 kernel_stack,
+                wait_child: WaitQueue::new(),
+                cpu_mask: AtomicUsize::new(self.cpu_mask()),
+                thread_group,
+                thread_idx: 0,
+                killed: AtomicUsize::new(0),
+                fd_table: Arc::new(Mutex::new(FdTable::new())),
+                sync_table: Arc::new(Mutex::new(SyncTable::new())),
+                sig_actions: Arc::new(Mutex::new(SignalActions::new())),
+                memory_set: Arc::new(Mutex::new(memory_set)),
                 inner: Mutex::new(TaskControlBlockInner {
                     trap_cx_ppn,
-                    base_size: user_sp,
+                    base_size,
                     task_cx,
                     task_cx_ptr: task_cx_ptr as usize,
                     user_trap_info: None,
                     task_status: TaskStatus::Ready,
-                    memory_set,
                     parent: Some(Arc::downgrade(self)),
                     children: Vec::new(),
                     exit_code: 0,
                     priority: 16,
-                    fd_table: vec![
-                        // 0 -> stdin
-                        Some(Arc::new(Stdin)),
-                        // 1 -> stdout
-                        Some(Arc::new(Stdout)),
-                        // 2 -> stderr
-                        Some(Arc::new(Stdout)),
-                        // 3 -> serial 2
-                        Some(Arc::new(Serial::<2>)),
-                        // 4 -> serial 3
-                        Some(Arc::new(Serial::<3>)),
-                    ],
+                    sched: SchedEntity::default(),
+                    signals: SignalState::new(),
                     mail_box: Arc::new(MailBox::new()),
                     time_intr_count: 0,
                     total_cpu_cycle_count: 0,
@@ -381,18 +589,23 @@ impl TaskControlBlock {
                 }),
             });
             add_task_2_map(task_control_block.getpid(), task_control_block.clone());
+            task_control_block
+                .thread_group
+                .attach(0, &task_control_block);
             parent_inner.children.push(task_control_block.clone());
             let trap_cx = task_control_block.acquire_inner_lock().get_trap_cx();
             *trap_cx = TrapContext::app_init_context(
                 entry_point,
                 user_sp,
+                args.len(),
+                argv_base,
                 KERNEL_SPACE.lock().token(),
                 kernel_stack_top,
                 trap_handler as usize,
             );
             return Ok(task_control_block);
         }
-        Err(-1)
+        Err(Errno::ENOENT)
     }
 
     pub fn create_socket(&self) -> Arc<Socket> {
@@ -400,6 +613,83 @@ impl TaskControlBlock {
     }
 }
 
+/// Open files of a process, shared by its threads.
+#[derive(Clone)]
+pub struct FdTable(Vec<Option<Arc<dyn File + Send + Sync>>>);
+
+impl FdTable {
+    pub fn new() -> Self {
+        Self(vec![
+            // 0 -> stdin
+            Some(Arc::new(Stdin)),
+            // 1 -> stdout
+            Some(Arc::new(Stdout)),
+            // 2 -> stderr
+            Some(Arc::new(Stdout)),
+            // 3 -> serial 2
+            Some(Arc::new(Serial::<2>)),
+            // 4 -> serial 3
+            Some(Arc::new(Serial::<3>)),
+        ])
+    }
+
+    pub fn alloc_fd(&mut self) -> usize {
+        if let Some(fd) = (0..self.len()).find(|fd| self[*fd].is_none()) {
+            fd
+        } else {
+            self.push(None);
+            self.len() - 1
+        }
+    }
+}
+
+impl Deref for FdTable {
+    type Target = Vec<Option<Arc<dyn File + Send + Sync>>>;
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for FdTable {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl Debug for FdTable {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_fmt(format_args!("FdTable({} fds)", self.len()))
+    }
+}
+
+/// Copy `args` and a null-terminated pointer array onto the user stack of
+/// the address space `token`. Returns the new stack pointer and argv base,
+/// or E2BIG if they do not fit in the stack.
+fn push_args(token: usize, mut user_sp: usize, args: &[String]) -> Result<(usize, usize), Errno> {
+    let size: usize = args.iter().map(|arg| arg.len() + 1).sum();
+    if size + (args.len() + 1) * size_of::<usize>() > USER_STACK_SIZE {
+        return Err(Errno::E2BIG);
+    }
+    let too_big = |_| Errno::E2BIG;
+    user_sp -= (args.len() + 1) * size_of::<usize>();
+    let argv_base = user_sp;
+    let argv = argv_base as *mut usize;
+    copy_to_user(token, argv.wrapping_add(args.len()), 0).map_err(too_big)?;
+    for (i, arg) in args.iter().enumerate() {
+        user_sp -= arg.len() + 1;
+        copy_to_user(token, argv.wrapping_add(i), user_sp).map_err(too_big)?;
+        let buffer = translated_byte_buffer_mut(token, user_sp as *mut u8, arg.len() + 1)
+            .map_err(too_big)?;
+        let bytes = arg.as_bytes().iter().chain(core::iter::once(&0u8));
+        for (dst, src) in buffer.into_iter().zip(bytes) {
+            unsafe { *dst = *src };
+        }
+    }
+    // the psABI wants a 16-byte aligned sp
+    user_sp -= user_sp % 16;
+    Ok((user_sp, argv_base))
+}
+
 impl PartialEq for TaskControlBlock {
     fn eq(&self, other: &Self) -> bool {
         self.pid == other.pid
@@ -424,5 +714,7 @@ impl Ord for TaskControlBlock {
 pub enum TaskStatus {
     Ready,
     Running(usize),
+    /// Waiting on a `WaitQueue`, parked in `TaskPool::sleeping_tasks`
+    Blocked,
     Zombie,
 }
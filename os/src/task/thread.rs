@@ -0,0 +1,199 @@
+use super::{block_current_and_run_next, current_task, wake_task, TaskControlBlock, WaitQueue};
+use crate::config::{
+    MAX_THREADS, PAGE_SIZE, THREAD_STACK_TOP, TRAP_CONTEXT, USER_STACK_SIZE, USER_TRAP_BUFFER,
+};
+use crate::errno::Errno;
+use crate::mm::{MapPermission, MemorySet, VirtAddr};
+use alloc::collections::BTreeMap;
+use alloc::sync::{Arc, Weak};
+use alloc::vec;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+/// User address of the TrapContext page of thread `idx`.
+pub fn trap_cx_va(idx: usize) -> usize {
+    if idx == 0 {
+        TRAP_CONTEXT
+    } else {
+        USER_TRAP_BUFFER - idx * PAGE_SIZE
+    }
+}
+
+/// Top of the user stack of thread `idx`, which must not be 0.
+pub fn thread_stack_top(idx: usize) -> usize {
+    THREAD_STACK_TOP - (idx - 1) * (USER_STACK_SIZE + PAGE_SIZE)
+}
+
+/// Map the user stack and TrapContext page of thread `idx`.
+pub fn map_thread(memory_set: &mut MemorySet, idx: usize) -> Result<(), Errno> {
+    let stack_bottom = thread_stack_top(idx) - USER_STACK_SIZE;
+    memory_set.insert_framed_area(
+        stack_bottom.into(),
+        thread_stack_top(idx).into(),
+        MapPermission::R | MapPermission::W | MapPermission::U,
+    )?;
+    memory_set
+        .insert_framed_area(
+            trap_cx_va(idx).into(),
+            (trap_cx_va(idx) + PAGE_SIZE).into(),
+            MapPermission::R | MapPermission::W,
+        )
+        .map_err(|errno| {
+            memory_set.remove_area_with_start_vpn(VirtAddr::from(stack_bottom).into());
+            errno
+        })
+}
+
+pub fn unmap_thread(memory_set: &mut MemorySet, idx: usize) {
+    memory_set
+        .remove_area_with_start_vpn(VirtAddr::from(thread_stack_top(idx) - USER_STACK_SIZE).into());
+    memory_set.remove_area_with_start_vpn(VirtAddr::from(trap_cx_va(idx)).into());
+}
+
+/// Tasks sharing one address space and fd table. Thread index 0 is the
+/// leader, whose pid is the tgid.
+#[derive(Debug)]
+pub struct ThreadGroup {
+    pub tgid: usize,
+    inner: Mutex<ThreadGroupInner>,
+    /// Threads blocked in waittid
+    thread_exited: WaitQueue,
+}
+
+#[derive(Debug)]
+struct ThreadGroupInner {
+    /// Slot `i` is used by thread index `i`
+    threads: Vec<Option<Weak<TaskControlBlock>>>,
+    /// Exit codes of threads not joined yet, by tid
+    exit_codes: BTreeMap<usize, i32>,
+    /// Attached threads that have not left yet, the last one to leave
+    /// frees the address space
+    live: usize,
+}
+
+impl ThreadGroup {
+    pub fn new(tgid: usize) -> Self {
+        Self {
+            tgid,
+            inner: Mutex::new(ThreadGroupInner {
+                threads: vec![Some(Weak::new())],
+                exit_codes: BTreeMap::new(),
+                live: 0,
+            }),
+            thread_exited: WaitQueue::new(),
+        }
+    }
+
+    /// Keep thread index `idx` from being reused, e.g. because its stack
+    /// is still mapped in a forked address space.
+    pub fn reserve(&self, idx: usize) {
+        let mut inner = self.inner.lock();
+        if inner.threads.len() <= idx {
+            inner.threads.resize(idx + 1, None);
+        }
+        inner.threads[idx] = Some(Weak::new());
+    }
+
+    pub fn alloc_idx(&self) -> Result<usize, Errno> {
+        let mut inner = self.inner.lock();
+        if let Some(idx) = (1..inner.threads.len()).find(|idx| inner.threads[*idx].is_none()) {
+            inner.threads[idx] = Some(Weak::new());
+            Ok(idx)
+        } else if inner.threads.len() < MAX_THREADS {
+            inner.threads.push(Some(Weak::new()));
+            Ok(inner.threads.len() - 1)
+        } else {
+            Err(Errno::EAGAIN)
+        }
+    }
+
+    /// Give back an index from `alloc_idx` that never got a thread.
+    pub fn free_idx(&self, idx: usize) {
+        self.inner.lock().threads[idx] = None;
+    }
+
+    pub fn attach(&self, idx: usize, task: &Arc<TaskControlBlock>) {
+        let mut inner = self.inner.lock();
+        inner.threads[idx] = Some(Arc::downgrade(task));
+        inner.live += 1;
+    }
+
+    /// Threads that may still run on the address space, even if they are
+    /// already zombies.
+    pub fn live(&self) -> usize {
+        self.inner.lock().live
+    }
+
+    /// Called by an exiting thread once it is off user space for good.
+    /// Returns true if it was the last one.
+    pub fn leave(&self) -> bool {
+        let mut inner = self.inner.lock();
+        inner.live -= 1;
+        inner.live == 0
+    }
+
+    /// Live threads other than `idx`.
+    pub fn others(&self, idx: usize) -> Vec<Arc<TaskControlBlock>> {
+        self.inner
+            .lock()
+            .threads
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| *i != idx)
+            .filter_map(|(_, slot)| slot.as_ref().and_then(|weak| weak.upgrade()))
+            .filter(|task| !task.acquire_inner_lock().is_zombie())
+            .collect()
+    }
+
+    /// Thread indices in use other than the leader and `idx`.
+    pub fn other_idxs(&self, idx: usize) -> Vec<usize> {
+        let inner = self.inner.lock();
+        (1..inner.threads.len())
+            .filter(|i| *i != idx && inner.threads[*i].is_some())
+            .collect()
+    }
+
+    /// Kill all threads except `idx` with `signo`. They exit at their next
+    /// trap, blocked ones are woken for that.
+    pub fn kill_others(&self, idx: usize, signo: usize) {
+        for task in self.others(idx) {
+            task.kill(signo);
+            wake_task(task);
+        }
+    }
+
+    /// Record the exit of thread `idx` and wake joiners.
+    pub fn exit(&self, idx: usize, tid: usize, exit_code: i32) {
+        let mut inner = self.inner.lock();
+        inner.threads[idx] = None;
+        inner.exit_codes.insert(tid, exit_code);
+        drop(inner);
+        self.thread_exited.wake_all();
+    }
+
+    /// Block until thread `tid` exits and return its exit code.
+    pub fn join(&self, tid: usize) -> Result<i32, Errno> {
+        let task = current_task().unwrap();
+        loop {
+            if task.is_interrupted() {
+                return Err(Errno::EINTR);
+            }
+            let mut inner = self.inner.lock();
+            if let Some(exit_code) = inner.exit_codes.remove(&tid) {
+                return Ok(exit_code);
+            }
+            if !inner
+                .threads
+                .iter()
+                .filter_map(|slot| slot.as_ref().and_then(|weak| weak.upgrade()))
+                .any(|thread| thread.getpid() == tid)
+            {
+                return Err(Errno::ESRCH);
+            }
+            // exits are recorded under the same lock, so no wakeup is lost
+            self.thread_exited.prepare_to_wait();
+            drop(inner);
+            block_current_and_run_next();
+        }
+    }
+}
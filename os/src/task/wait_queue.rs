@@ -0,0 +1,80 @@
+use super::{block_current_and_run_next, current_task, wake_task, TaskControlBlock, TaskStatus};
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use core::fmt::{self, Debug, Formatter};
+use spin::Mutex;
+
+/// Tasks blocked until some condition becomes true.
+///
+/// To avoid lost wakeups, check the condition and call `prepare_to_wait`
+/// while holding the lock that guards the condition, release that lock,
+/// then call `block_current_and_run_next`. Wakers update the condition
+/// under the same lock before calling `wake_one` or `wake_all`.
+pub struct WaitQueue {
+    queue: Mutex<VecDeque<Arc<TaskControlBlock>>>,
+}
+
+impl WaitQueue {
+    pub fn new() -> Self {
+        Self {
+            queue: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Enqueue the current task and mark it as blocked. It keeps running
+    /// until it gives up the hart.
+    pub fn prepare_to_wait(&self) {
+        let task = current_task().unwrap();
+        task.acquire_inner_lock().task_status = TaskStatus::Blocked;
+        self.queue.lock().push_back(task);
+    }
+
+    /// Block the current task until it is woken. The caller must not hold
+    /// any lock.
+    pub fn wait(&self) {
+        self.prepare_to_wait();
+        block_current_and_run_next();
+    }
+
+    pub fn wake_one(&self) -> bool {
+        let task = self.queue.lock().pop_front();
+        if let Some(task) = task {
+            wake_task(task);
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn wake_all(&self) -> usize {
+        let tasks: VecDeque<_> = self.queue.lock().drain(..).collect();
+        let count = tasks.len();
+        for task in tasks {
+            wake_task(task);
+        }
+        count
+    }
+
+    /// Take `task` out of the queue without waking it, e.g. when it was
+    /// woken by a signal instead. Returns false if it was not queued.
+    pub fn remove(&self, task: &Arc<TaskControlBlock>) -> bool {
+        let mut queue = self.queue.lock();
+        match queue.iter().position(|t| Arc::ptr_eq(t, task)) {
+            Some(idx) => {
+                queue.remove(idx);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.lock().is_empty()
+    }
+}
+
+impl Debug for WaitQueue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_fmt(format_args!("WaitQueue({} tasks)", self.queue.lock().len()))
+    }
+}
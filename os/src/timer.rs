@@ -1,6 +1,9 @@
-use crate::config::{CLOCK_FREQ, CPU_NUM};
+use crate::board::clock_freq;
+use crate::config::CPU_NUM;
 use crate::sbi::set_timer;
-use crate::task::hart_id;
+use crate::task::{
+    block_current_and_run_next, current_task, hart_id, wake_task, TaskControlBlock, TaskStatus,
+};
 use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
 use lazy_static::*;
 use riscv::register::time;
@@ -9,6 +12,7 @@ use spin::Mutex;
 const TICKS_PER_SEC: usize = 100;
 const MSEC_PER_SEC: usize = 1000;
 pub const USEC_PER_SEC: usize = 1_000_000;
+pub const NSEC_PER_SEC: usize = 1_000_000_000;
 
 #[repr(C)]
 #[derive(Debug)]
@@ -24,12 +28,19 @@ impl TimeVal {
     }
 }
 
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct TimeSpec {
+    pub sec: usize,
+    pub nsec: usize,
+}
+
 #[allow(unused_variables)]
 pub fn get_time(mut ts: Vec<*mut usize>, tz: usize) -> isize {
     let t = time::read();
     unsafe {
-        *ts[0] = t / CLOCK_FREQ;
-        *ts[1] = (t % CLOCK_FREQ) * 1000000 / CLOCK_FREQ;
+        *ts[0] = t / clock_freq();
+        *ts[1] = (t % clock_freq()) * 1000000 / clock_freq();
         trace!("t {} sec {} usec {}", t, *ts[0], *ts[1]);
     }
 
@@ -38,24 +49,39 @@ pub fn get_time(mut ts: Vec<*mut usize>, tz: usize) -> isize {
 
 #[allow(dead_code)]
 pub fn get_time_ms() -> usize {
-    time::read() / (CLOCK_FREQ / MSEC_PER_SEC)
+    time::read() / (clock_freq() / MSEC_PER_SEC)
 }
 
 #[allow(dead_code)]
 pub fn get_time_us() -> usize {
-    time::read() * USEC_PER_SEC / CLOCK_FREQ
+    time::read() * USEC_PER_SEC / clock_freq()
 }
 
 pub fn set_next_trigger() {
-    // set_timer(time::read() + CLOCK_FREQ / TICKS_PER_SEC);
-    set_virtual_timer(time::read() + CLOCK_FREQ / TICKS_PER_SEC, 0);
+    // set_timer(time::read() + clock_freq() / TICKS_PER_SEC);
+    set_virtual_timer(
+        time::read() + clock_freq() / TICKS_PER_SEC,
+        TimerEvent::Tick,
+    );
+}
+
+pub enum TimerEvent {
+    /// Scheduler tick of the kernel
+    Tick,
+    /// User timer of a process, delivered as a user trap
+    UserTimer(usize),
+    /// End of a sleep
+    Wakeup(Arc<TaskControlBlock>),
 }
 
 lazy_static! {
-    pub static ref TIMER_MAP: [Arc<Mutex<BTreeMap<usize, usize>>>; CPU_NUM] = Default::default();
+    pub static ref TIMER_MAP: [Arc<Mutex<BTreeMap<usize, TimerEvent>>>; CPU_NUM] =
+        Default::default();
 }
 
-pub fn set_virtual_timer(mut time: usize, pid: usize) {
+/// Register `event` on the current hart. Returns the time it was stored
+/// under, which is later than `time` if that was taken.
+pub fn set_virtual_timer(mut time: usize, event: TimerEvent) -> usize {
     if time < time::read() {
         warn!("Time travel!");
         // return;
@@ -64,10 +90,43 @@ pub fn set_virtual_timer(mut time: usize, pid: usize) {
     while timer_map.contains_key(&time) {
         time += 1;
     }
-    timer_map.insert(time, pid);
+    timer_map.insert(time, event);
     if let Some((timer_min, _)) = timer_map.first_key_value() {
         if time == *timer_min {
             set_timer(time);
         }
     }
+    time
+}
+
+/// Drop the wakeup of `task` stored under `time` on `hart` if it has not
+/// fired yet.
+fn cancel_wakeup(hart: usize, time: usize, task: &Arc<TaskControlBlock>) {
+    let mut timer_map = TIMER_MAP[hart].lock();
+    if matches!(timer_map.get(&time), Some(TimerEvent::Wakeup(t)) if Arc::ptr_eq(t, task)) {
+        timer_map.remove(&time);
+    }
+}
+
+/// Block the current task until `time`. Returns false if it was woken
+/// early by a signal or kill.
+pub fn sleep_until(time: usize) -> bool {
+    let task = current_task().unwrap();
+    loop {
+        if time::read() >= time {
+            return true;
+        }
+        if task.is_interrupted() {
+            return false;
+        }
+        task.acquire_inner_lock().task_status = TaskStatus::Blocked;
+        let hart = hart_id();
+        let key = set_virtual_timer(time, TimerEvent::Wakeup(task.clone()));
+        // a signal raised before `Blocked` was set found nothing to wake
+        if task.is_interrupted() {
+            wake_task(task.clone());
+        }
+        block_current_and_run_next();
+        cancel_wakeup(hart, key, &task);
+    }
 }
@@ -1,4 +1,4 @@
-use crate::config::MEMORY_END;
+use crate::config::TRACE_BASE;
 // S trap
 pub const S_TRAP_VEC_ENTER: usize = 0x57ab_0000;
 pub const S_TRAP_VEC_RESTORE: usize = 0x57ab_1000;
@@ -69,7 +69,7 @@ pub fn push_trace(event_id: usize) -> usize {
         sd {cy}, 1*8({tail})",
         eid = in(reg) event_id,
         step = in(reg) 16,
-        mem_end = in(reg) MEMORY_END,
+        mem_end = in(reg) TRACE_BASE,
         cy = out(reg) cycle,
         tail = out(reg) _,
         eid_ext = out(reg) _,
@@ -79,19 +79,24 @@ pub fn push_trace(event_id: usize) -> usize {
 }
 
 pub fn init() {
+    // without the trace feature the buffer may hold frames
+    #[cfg(feature = "trace")]
     unsafe {
-        (MEMORY_END as *mut usize).write_volatile(MEMORY_END + 16);
-        ((MEMORY_END + 8) as *mut u32).write_volatile(0xbaad_f00d);
-        ((MEMORY_END + 12) as *mut u32).write_volatile(0xdead_beef);
+        (TRACE_BASE as *mut usize).write_volatile(TRACE_BASE + 16);
+        ((TRACE_BASE + 8) as *mut u32).write_volatile(0xbaad_f00d);
+        ((TRACE_BASE + 12) as *mut u32).write_volatile(0xdead_beef);
     }
 }
 
 #[allow(unused)]
 pub fn trace_test() {
+    if !cfg!(feature = "trace") {
+        return;
+    }
     let c1 = push_trace(TRACE_TEST);
     let c2 = push_trace(TRACE_TEST);
-    let trace_end = unsafe { (MEMORY_END as *mut usize).read_volatile() };
-    let trace_magic = unsafe { ((MEMORY_END + 8) as *mut usize).read_volatile() };
+    let trace_end = unsafe { (TRACE_BASE as *mut usize).read_volatile() };
+    let trace_magic = unsafe { ((TRACE_BASE + 8) as *mut usize).read_volatile() };
     info!("[trace] push_trace() takes {} cycles.", c2 - c1);
     info!(
         "[trace] trace tail addr: {:#x}, trace magic: {:#x}",
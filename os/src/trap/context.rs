@@ -15,9 +15,12 @@ impl TrapContext {
     pub fn set_sp(&mut self, sp: usize) {
         self.x[2] = sp;
     }
+    /// `argc` and `argv` are passed to `_start` in a0 and a1.
     pub fn app_init_context(
         entry: usize,
         sp: usize,
+        argc: usize,
+        argv: usize,
         kernel_satp: usize,
         kernel_sp: usize,
         trap_handler: usize,
@@ -35,6 +38,8 @@ impl TrapContext {
             trap_handler,
         };
         cx.set_sp(sp);
+        cx.x[10] = argc;
+        cx.x[11] = argv;
         cx
     }
 }
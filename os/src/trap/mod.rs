@@ -1,22 +1,24 @@
 mod context;
 mod usertrap;
 
-use crate::config::{TRAMPOLINE, TRAP_CONTEXT};
+use crate::config::TRAMPOLINE;
+use crate::mm::{MapPermission, VirtAddr};
 use crate::plic;
 use crate::sbi::set_timer;
 use crate::syscall::syscall;
 use crate::task::{
-    current_task, current_trap_cx, current_user_token, exit_current_and_run_next, hart_id,
-    suspend_current_and_run_next,
+    current_task, current_trap_cx, exit_current_and_run_next, handle_signals, hart_id,
+    suspend_current_and_run_next, tick_current, wake_task,
 };
-use crate::timer::{get_time_us, set_next_trigger, TIMER_MAP};
+use crate::timer::{get_time_us, set_next_trigger, TimerEvent, TIMER_MAP};
 use crate::trace::{push_trace, S_TRAP_HANDLER, S_TRAP_RETURN};
+use alloc::vec::Vec;
 use core::arch::{asm, global_asm};
 use riscv::register::scounteren;
 use riscv::register::{
     mtvec::TrapMode,
     scause::{self, Exception, Interrupt, Trap},
-    sepc, sideleg, sie, sip, sstatus, stval, stvec,
+    sepc, sideleg, sie, sip, sstatus, stval, stvec, time,
 };
 
 global_asm!(include_str!("trap.asm"));
@@ -78,6 +80,7 @@ pub fn trap_handler() -> ! {
                 cx.x[10] = result as usize;
             }
         }
+        Trap::Exception(exception) if handle_page_fault(exception, stval) => {}
         Trap::Exception(Exception::StoreFault)
         | Trap::Exception(Exception::StorePageFault)
         | Trap::Exception(Exception::InstructionFault)
@@ -105,39 +108,55 @@ pub fn trap_handler() -> ! {
             exit_current_and_run_next(-3);
         }
         Trap::Interrupt(Interrupt::SupervisorTimer) => {
-            // let current_time = time::read();
+            let current_time = time::read();
             let mut timer_map = TIMER_MAP[hart_id()].lock();
-            while let Some((_, pid)) = timer_map.pop_first() {
-                if let Some((next_time, _)) = timer_map.first_key_value() {
-                    set_timer(*next_time);
+            let mut events = Vec::new();
+            while let Some(entry) = timer_map.first_entry() {
+                if *entry.key() > current_time {
+                    break;
                 }
-                drop(timer_map);
-                if pid == 0 {
-                    set_next_trigger();
-                    // static mut CNT: usize = 0;
-                    // unsafe {
-                    //     CNT += 1;
-                    //     if CNT > 6000 {
-                    //         debug!("kernel tick");
-                    //         CNT = 0;
-                    //     }
-                    // }
-                    suspend_current_and_run_next();
-                } else if pid == current_task().unwrap().pid.0 {
-                    debug!("set UTIP for pid {}", pid);
-                    unsafe {
-                        sip::set_utimer();
+                events.push(entry.remove());
+            }
+            if let Some((next_time, _)) = timer_map.first_key_value() {
+                set_timer(*next_time);
+            }
+            drop(timer_map);
+            let mut tick = false;
+            for event in events {
+                match event {
+                    TimerEvent::Tick => tick = true,
+                    TimerEvent::UserTimer(pid) if pid == current_task().unwrap().pid.0 => {
+                        debug!("set UTIP for pid {}", pid);
+                        unsafe {
+                            sip::set_utimer();
+                        }
+                    }
+                    TimerEvent::UserTimer(pid) => {
+                        let _ = push_trap_record(
+                            pid,
+                            UserTrapRecord {
+                                cause: 4,
+                                message: get_time_us(),
+                            },
+                        );
                     }
-                } else {
-                    let _ = push_trap_record(
-                        pid,
-                        UserTrapRecord {
-                            cause: 4,
-                            message: get_time_us(),
-                        },
-                    );
+                    TimerEvent::Wakeup(task) => wake_task(task),
+                }
+            }
+            // last, as it may switch to another task
+            if tick {
+                set_next_trigger();
+                // static mut CNT: usize = 0;
+                // unsafe {
+                //     CNT += 1;
+                //     if CNT > 6000 {
+                //         debug!("kernel tick");
+                //         CNT = 0;
+                //     }
+                // }
+                if tick_current() {
+                    suspend_current_and_run_next();
                 }
-                break;
             }
         }
         Trap::Interrupt(Interrupt::SupervisorExternal) => {
@@ -156,21 +175,48 @@ pub fn trap_handler() -> ! {
             );
         }
     }
+    let signo = current_task().unwrap().kill_signal();
+    if signo != 0 {
+        exit_current_and_run_next(-(signo as i32));
+    }
+    handle_signals();
     trap_return();
 }
 
+/// Demand paging and copy-on-write, returns false for a real fault.
+fn handle_page_fault(exception: Exception, addr: usize) -> bool {
+    let access = match exception {
+        Exception::LoadPageFault => MapPermission::R,
+        Exception::StorePageFault => MapPermission::W,
+        Exception::InstructionPageFault => MapPermission::X,
+        _ => return false,
+    };
+    let result = current_task()
+        .unwrap()
+        .memory_set
+        .lock()
+        .handle_page_fault(VirtAddr::from(addr).floor(), access);
+    match result {
+        Ok(()) => true,
+        // the faulting instruction runs again once memory is freed
+        #[cfg(feature = "oom_killer")]
+        Err(crate::errno::Errno::ENOMEM) => crate::task::oom_kill(),
+        Err(_) => false,
+    }
+}
+
 #[no_mangle]
 pub fn trap_return() -> ! {
     unsafe {
         sstatus::clear_sie();
     }
-    current_task()
-        .unwrap()
-        .acquire_inner_lock()
-        .restore_user_trap_info();
+    let task = current_task().unwrap();
+    task.acquire_inner_lock().restore_user_trap_info();
+    let trap_cx_ptr = task.trap_cx_va();
+    // TLB shootdowns of this address space reach this hart from now on
+    let user_satp = task.memory_set.lock().enter_user(hart_id());
+    drop(task);
     set_user_trap_entry();
-    let trap_cx_ptr = TRAP_CONTEXT;
-    let user_satp = current_user_token();
     extern "C" {
         fn __alltraps();
         fn __restore();
@@ -40,16 +40,31 @@ __alltraps:
     # move to kernel_sp
     ld sp, 35*8(sp)
     # switch to kernel space
+    csrr t2, satp
     csrw satp, t0
+    # flush only if the user space has the kernel ASID, i.e. no ASIDs.
+    # Otherwise kernel space is kept coherent by shootdowns on every change
+    # to it, see MemorySet::flush_range.
+    xor t2, t2, t0
+    srli t2, t2, 44
+    slli t2, t2, 48
+    bnez t2, 1f
     sfence.vma
+1:
     # jump to trap_handler
     jr t1
 
 __restore:
     # a0: *TrapContext in user space(Constant); a1: user space token
-    # switch to user space
+    # switch to user space, flushing as above
+    csrr t0, satp
     csrw satp, a1
+    xor t0, t0, a1
+    srli t0, t0, 44
+    slli t0, t0, 48
+    bnez t0, 1f
     sfence.vma
+1:
     csrw sscratch, a0
     mv sp, a0
     # now sp points to TrapContext in user space, start restoring based on it
@@ -1,6 +1,6 @@
 const MAX_USER_TRAP_NUM: usize = 128;
 
-use crate::config::CPU_NUM;
+use crate::board::hart_num;
 use crate::plic::Plic;
 use crate::sbi::send_ipi;
 use crate::task::hart_id;
@@ -53,7 +53,7 @@ impl UserTrapInfo {
 
         let u_context = get_context(hart_id(), 'U');
         for (device_id, is_enabled) in &self.devices {
-            for hart_id in 0..CPU_NUM {
+            for hart_id in 0..hart_num() {
                 Plic::disable(get_context(hart_id, 'S'), *device_id);
             }
             if *is_enabled {
@@ -112,7 +112,7 @@ impl UserTrapInfo {
 
     pub fn remove_user_ext_int_map(&self) {
         let mut int_map = USER_EXT_INT_MAP.lock();
-        for hart_id in 0..CPU_NUM {
+        for hart_id in 0..hart_num() {
             let s_context = get_context(hart_id, 'S');
             let u_context = get_context(hart_id, 'U');
             for (device_id, _) in &self.devices {
@@ -160,8 +160,7 @@ pub fn push_trap_record(pid: usize, trap_record: UserTrapRecord) -> Result<(), U
             let res = trap_info.push_trap_record(trap_record);
             // if let Running(task_hart_id) = tcb_inner.task_status {
             //     if task_hart_id != hart_id() {
-            //         let mask: usize = 1 << task_hart_id;
-            //         send_ipi(&mask as *const _ as usize);
+            //         send_ipi(1 << task_hart_id);
             //     }
             // }
             // push_trace(PUSH_TRAP_RECORD_EXIT);
@@ -1,3 +1,4 @@
+use crate::board::board;
 use alloc::collections::VecDeque;
 use core::convert::Infallible;
 use embedded_hal::serial::{Read, Write};
@@ -13,17 +14,10 @@ mod serial_config {
     pub type SerialHardware = MmioUart8250<'static>;
     pub const FIFO_DEPTH: usize = 16;
     pub const SERIAL_NUM: usize = 4;
+    // defaults for when the device tree lists no UARTs
     pub const SERIAL_BASE_ADDRESS: usize = 0x1000_2000;
     pub const SERIAL_ADDRESS_STRIDE: usize = 0x1000;
-    pub fn irq_to_serial_id(irq: u16) -> usize {
-        match irq {
-            12 => 0,
-            13 => 1,
-            14 => 2,
-            15 => 3,
-            _ => 0,
-        }
-    }
+    pub const SERIAL_IRQ_BASE: u16 = 12;
 }
 
 #[cfg(feature = "board_lrv")]
@@ -32,24 +26,27 @@ mod serial_config {
     pub type SerialHardware = MmioUartAxi16550<'static>;
     pub const FIFO_DEPTH: usize = 16;
     pub const SERIAL_NUM: usize = 4;
+    // defaults for when the device tree lists no UARTs
     pub const SERIAL_BASE_ADDRESS: usize = 0x6000_1000;
     pub const SERIAL_ADDRESS_STRIDE: usize = 0x1000;
-    pub fn irq_to_serial_id(irq: u16) -> usize {
-        match irq {
-            4 => 0,
-            5 => 1,
-            6 => 2,
-            7 => 3,
-            _ => 0,
-        }
-    }
+    pub const SERIAL_IRQ_BASE: u16 = 4;
 }
 
 pub use serial_config::*;
 
-pub fn get_base_addr_from_irq(irq: u16) -> usize {
-    SERIAL_BASE_ADDRESS + irq_to_serial_id(irq) * SERIAL_ADDRESS_STRIDE
+/// UARTs found, the `BUFFERED_SERIAL`s past them have no hardware
+pub fn serial_num() -> usize {
+    board().serials.len()
 }
+
+pub fn irq_to_serial_id(irq: u16) -> Option<usize> {
+    board().serials.iter().position(|serial| serial.irq == irq)
+}
+
+pub fn get_base_addr(serial_id: usize) -> usize {
+    board().serials[serial_id].base
+}
+
 pub struct BufferedSerial {
     pub hardware: SerialHardware,
     pub rx_buffer: VecDeque<u8>,
@@ -202,7 +199,7 @@ impl Drop for BufferedSerial {
 lazy_static! {
     pub static ref BUFFERED_SERIAL: [Mutex<BufferedSerial>; SERIAL_NUM] =
         array_init::array_init(|i| Mutex::new(BufferedSerial::new(
-            SERIAL_BASE_ADDRESS + i * SERIAL_ADDRESS_STRIDE,
+            board().serials.get(i).map_or(0, |serial| serial.base),
         )));
 }
 
@@ -217,10 +214,10 @@ lazy_static! {
 
 #[cfg(any(feature = "board_qemu", feature = "board_lrv"))]
 pub fn init() {
-    for serial_id in 0..2 {
+    for serial_id in 0..serial_num().min(2) {
         BUFFERED_SERIAL[serial_id].lock().hardware_init(115200);
     }
-    for serial_id in 2..SERIAL_NUM {
+    for serial_id in 2..serial_num() {
         BUFFERED_SERIAL[serial_id].lock().hardware_init(6_250_000);
         // BUFFERED_SERIAL[serial_id].lock().hardware_init(1_250_000);
     }
@@ -231,10 +228,8 @@ pub fn init() {
     SERIAL.lock().enable_interrupt();
 }
 
-pub fn handle_interrupt(irq: u16) {
-    BUFFERED_SERIAL[irq_to_serial_id(irq)]
-        .lock()
-        .interrupt_handler();
+pub fn handle_interrupt(serial_id: usize) {
+    BUFFERED_SERIAL[serial_id].lock().interrupt_handler();
 }
 
 #[cfg(feature = "board_lrv_seriallite")]
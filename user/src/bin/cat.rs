@@ -0,0 +1,24 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{read, write};
+
+/// Copy fd 0 to fd 1, e.g. `cat < a > b` in the shell.
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut buf = [0u8; 256];
+    loop {
+        let len = read(0, &mut buf);
+        if len <= 0 {
+            break;
+        }
+        if write(1, &buf[..len as usize]) < 0 {
+            println!("[cat] write error");
+            return -1;
+        }
+    }
+    0
+}
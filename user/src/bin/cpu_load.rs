@@ -7,7 +7,8 @@ use core::sync::atomic::{AtomicBool, Ordering::Relaxed};
 use rand_core::{RngCore, SeedableRng};
 use rand_xorshift::XorShiftRng;
 use riscv::register::uie;
-use user_lib::init_user_trap;
+use user_lib::signal::SIGTERM;
+use user_lib::{init_user_trap, sigaction, SignalAction};
 
 static IS_TIMEOUT: AtomicBool = AtomicBool::new(false);
 
@@ -16,6 +17,7 @@ pub fn main() -> i32 {
     let mut rng = XorShiftRng::seed_from_u64(0x1020304050607080u64);
     let mut ret: u64 = 0;
     init_user_trap();
+    sigaction(SIGTERM, Some(&SignalAction::new(on_sigterm)), None);
     unsafe {
         uie::set_usoft();
     }
@@ -24,7 +26,6 @@ pub fn main() -> i32 {
     }
     ret as i32
 }
-#[no_mangle]
-pub fn soft_intr_handler(_pid: usize, _msg: usize) {
+fn on_sigterm(_signo: usize) {
     IS_TIMEOUT.store(true, Relaxed);
 }
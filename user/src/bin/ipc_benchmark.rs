@@ -6,7 +6,8 @@ extern crate user_lib;
 extern crate alloc;
 
 use bitflags::bitflags;
-use user_lib::{send_msg, sleep, spawn, waitpid};
+use user_lib::signal::SIGTERM;
+use user_lib::{kill, send_msg, sleep, spawn, waitpid};
 
 const CPU_LOAD_NUM: usize = 1;
 
@@ -51,7 +52,7 @@ pub fn main() -> i32 {
     sleep(1000);
 
     for i in cpu_load_pid {
-        send_msg(i, 15);
+        kill(i, SIGTERM);
         waitpid(i, &mut exit_code);
     }
     0
@@ -12,10 +12,10 @@ use lazy_static::*;
 use rand_core::{RngCore, SeedableRng};
 use rand_xorshift::XorShiftRng;
 use riscv::register::uie;
-use spin::Mutex;
 use user_lib::{
     claim_ext_int, get_time, init_user_trap, mailread, mailwrite, send_msg, set_ext_int_enable,
     set_timer, sleep,
+    sync::Mutex,
     trap::{get_context, hart_id, Plic},
 };
 
@@ -50,13 +50,27 @@ bitflags! {
     }
 }
 
+/// Usage: `ipc_load [config dst_pid]`. Without arguments, wait for
+/// `config | dst_pid << 8` to arrive as a soft interrupt message.
 #[no_mangle]
-pub fn main() -> i32 {
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
     let init_res = init_user_trap();
-    println!(
-        "[ipc load] trap init result: {:#x}, now waiting for config init...",
-        init_res
-    );
+    if argc > 2 {
+        let config = parse_number(argv[1]).map(|bits| bits as u32);
+        let dst_pid = parse_number(argv[2]).unwrap_or(0);
+        match config {
+            Some(bits) if dst_pid > 0 && apply_config(bits, dst_pid) => {}
+            _ => {
+                println!("[ipc load] Invalid config {} {}!", argv[1], argv[2]);
+                return -1;
+            }
+        }
+    } else {
+        println!(
+            "[ipc load] trap init result: {:#x}, now waiting for config init...",
+            init_res
+        );
+    }
     unsafe {
         uie::set_usoft();
         uie::set_utimer();
@@ -82,6 +96,36 @@ pub fn main() -> i32 {
     0
 }
 
+/// Accept decimal, `0x` hex or `0b` binary numbers.
+fn parse_number(arg: &str) -> Option<usize> {
+    if let Some(hex) = arg.strip_prefix("0x") {
+        usize::from_str_radix(hex, 16).ok()
+    } else if let Some(bin) = arg.strip_prefix("0b") {
+        usize::from_str_radix(bin, 2).ok()
+    } else {
+        arg.parse().ok()
+    }
+}
+
+fn apply_config(bits: u32, dst_pid: usize) -> bool {
+    if let Some(config) = IpcLoadConfig::from_bits(bits) {
+        DST_PID.store(dst_pid, Relaxed);
+        let mode = config & IpcLoadConfig::ALL_MODE;
+        MODE.store(mode.bits(), Relaxed);
+        if dst_pid & 1 == 1 {
+            TX_SEED.store(20210821, Relaxed);
+            RX_SEED.store(1000000007, Relaxed);
+        } else {
+            RX_SEED.store(20210821, Relaxed);
+            TX_SEED.store(1000000007, Relaxed);
+        }
+        IS_INITIALIZED.store(true, Relaxed);
+        true
+    } else {
+        false
+    }
+}
+
 fn sendmsg_test() -> (usize, usize, usize) {
     let mut hasher = Hasher::new();
     let dst_pid = DST_PID.load(Relaxed);
@@ -237,20 +281,7 @@ mod user_trap {
         // }
         let dst_pid = msg >> 8;
         if dst_pid > 0 {
-            DST_PID.store(dst_pid, Relaxed);
-            let config_bits = msg as u32 & IpcLoadConfig::ALL_MODE.bits();
-            if let Some(config) = IpcLoadConfig::from_bits(config_bits) {
-                let mode = config & IpcLoadConfig::ALL_MODE;
-                MODE.store(mode.bits(), Relaxed);
-                if dst_pid & 1 == 1 {
-                    TX_SEED.store(20210821, Relaxed);
-                    RX_SEED.store(1000000007, Relaxed);
-                } else {
-                    RX_SEED.store(20210821, Relaxed);
-                    TX_SEED.store(1000000007, Relaxed);
-                }
-                IS_INITIALIZED.store(true, Relaxed);
-            } else {
+            if !apply_config(msg as u32 & IpcLoadConfig::ALL_MODE.bits(), dst_pid) {
                 println!("[uart load] Invalid config {:#x}!", msg);
             }
         } else {
@@ -0,0 +1,39 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{errno, mmap, mprotect, munmap, Errno};
+
+const START: usize = 0x2000_0000;
+const PAGE_SIZE: usize = 0x1000;
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // R | W
+    assert_eq!(mmap(START, 4 * PAGE_SIZE, 0b11), (4 * PAGE_SIZE) as isize);
+    for i in 0..4 {
+        unsafe { *((START + i * PAGE_SIZE) as *mut usize) = i };
+    }
+    // punch a hole in the middle
+    assert_eq!(munmap(START + PAGE_SIZE, PAGE_SIZE), PAGE_SIZE as isize);
+    assert_eq!(
+        errno::check(munmap(START, 2 * PAGE_SIZE)),
+        Err(Errno::EINVAL)
+    );
+    // read only from the third page on
+    assert_eq!(mprotect(START + 2 * PAGE_SIZE, 2 * PAGE_SIZE, 0b1), 0);
+    for i in [0, 2, 3] {
+        assert_eq!(unsafe { *((START + i * PAGE_SIZE) as *const usize) }, i);
+    }
+    assert_eq!(mprotect(START + 3 * PAGE_SIZE, PAGE_SIZE, 0b11), 0);
+    unsafe { *((START + 3 * PAGE_SIZE) as *mut usize) = 42 };
+    assert_eq!(
+        munmap(START + 2 * PAGE_SIZE, 2 * PAGE_SIZE),
+        (2 * PAGE_SIZE) as isize
+    );
+    assert_eq!(munmap(START, PAGE_SIZE), PAGE_SIZE as isize);
+    println!("[mmap test] partial munmap and mprotect passed");
+    0
+}
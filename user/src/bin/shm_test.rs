@@ -0,0 +1,38 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{exit, fork, shm_create, shm_map, shm_unmap, waitpid};
+
+const SHM_ADDR: usize = 0x4000_0000;
+const SHM_SIZE: usize = 0x2000;
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let id = shm_create(SHM_SIZE);
+    assert!(id >= 0);
+    // R | W
+    assert_eq!(shm_map(id as usize, SHM_ADDR, 0b11), SHM_ADDR as isize);
+    let buf = unsafe { core::slice::from_raw_parts_mut(SHM_ADDR as *mut u8, SHM_SIZE) };
+    let pid = fork();
+    if pid == 0 {
+        // the child writes through the same frames instead of its own copy
+        for (i, byte) in buf.iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        exit(0);
+    }
+    let mut exit_code = 0;
+    waitpid(pid as usize, &mut exit_code);
+    for (i, byte) in buf.iter().enumerate() {
+        assert_eq!(*byte, i as u8);
+    }
+    assert_eq!(shm_unmap(SHM_ADDR), 0);
+    println!(
+        "[shm test] child wrote {} bytes through shared memory",
+        SHM_SIZE
+    );
+    0
+}
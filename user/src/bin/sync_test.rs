@@ -0,0 +1,67 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    enable_deadlock_detect, errno, exit, mutex_create, mutex_lock, mutex_unlock, semaphore_create,
+    semaphore_down, semaphore_up, thread_create, waittid, yield_, Errno,
+};
+
+const THREAD_NUM: usize = 4;
+const ADD_PER_THREAD: usize = 100;
+
+static mut COUNTER: usize = 0;
+static mut MUTEX_ID: usize = 0;
+static mut DONE_SEM_ID: usize = 0;
+
+fn worker(_: usize) -> ! {
+    for _ in 0..ADD_PER_THREAD {
+        unsafe {
+            mutex_lock(MUTEX_ID);
+            let value = COUNTER;
+            // give others a chance to race
+            yield_();
+            COUNTER = value + 1;
+            mutex_unlock(MUTEX_ID);
+        }
+    }
+    unsafe {
+        semaphore_up(DONE_SEM_ID);
+    }
+    exit(0)
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    unsafe {
+        MUTEX_ID = mutex_create(true) as usize;
+        DONE_SEM_ID = semaphore_create(0) as usize;
+    }
+    let mut tids = [0usize; THREAD_NUM];
+    for tid in tids.iter_mut() {
+        *tid = thread_create(worker, 0) as usize;
+    }
+    for _ in 0..THREAD_NUM {
+        unsafe {
+            semaphore_down(DONE_SEM_ID);
+        }
+    }
+    for tid in tids.iter() {
+        let mut exit_code = 0;
+        waittid(*tid, &mut exit_code);
+    }
+    let count = unsafe { COUNTER };
+    println!("[sync test] counter = {}", count);
+    assert_eq!(count, THREAD_NUM * ADD_PER_THREAD);
+
+    enable_deadlock_detect(true);
+    let id = mutex_create(true) as usize;
+    assert_eq!(mutex_lock(id), 0);
+    // locking it again would block forever
+    assert_eq!(errno::check(mutex_lock(id)), Err(Errno::EDEADLK));
+    mutex_unlock(id);
+    println!("[sync test] deadlock detected");
+    0
+}
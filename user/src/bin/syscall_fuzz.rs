@@ -0,0 +1,118 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use rand_core::{RngCore, SeedableRng};
+use rand_xorshift::XorShiftRng;
+use user_lib::{close, exit, fork, raw_syscall, waitpid};
+
+const ROUNDS: usize = 32;
+const CALLS_PER_ROUND: usize = 256;
+
+/// Syscalls that return on their own whatever the arguments. Left out are
+/// those that block (read, futex, nanosleep, locks), end the child (exit),
+/// create tasks (fork, spawn, thread_create), touch other processes or
+/// devices (kill, affinity, user traps) and take memory the kernel heap may
+/// need until the child exits (shm_create). Every round runs in a forked
+/// child, so an exec that finds a program only ends that round early.
+const SYSCALLS: &[usize] = &[
+    24,   // dup
+    56,   // open
+    57,   // close
+    59,   // pipe
+    62,   // lseek
+    64,   // write
+    80,   // fstat
+    124,  // yield
+    134,  // sigaction
+    135,  // sigprocmask
+    140,  // set_priority
+    169,  // get_time
+    172,  // getpid
+    196,  // shm_map
+    197,  // shm_unmap
+    213,  // sbrk
+    214,  // brk
+    215,  // munmap
+    221,  // exec
+    222,  // mmap
+    226,  // mprotect
+    260,  // waitpid
+    401,  // mailread
+    402,  // mailwrite
+    469,  // enable_deadlock_detect
+    556,  // void
+    1001, // gettid
+    1002, // waittid
+    1010, // mutex_create
+    1012, // mutex_unlock
+    1020, // semaphore_create
+    1021, // semaphore_up
+    1030, // condvar_create
+    1031, // condvar_signal
+];
+
+/// Mostly values near the edges of what the kernel checks: small numbers,
+/// pointers into our own stack, kernel and non-canonical addresses.
+fn arg(rng: &mut XorShiftRng, stack: usize) -> usize {
+    let raw = rng.next_u64() as usize;
+    match raw % 8 {
+        0 => 0,
+        1 => raw >> 56,
+        2 => stack.wrapping_add(raw % 0x2000).wrapping_sub(0x1000),
+        3 => 0x8020_0000 + (raw >> 40),
+        4 => usize::MAX - (raw >> 48),
+        5 => 1 << (raw >> 58),
+        6 => (1 << 38) - (raw >> 52),
+        _ => raw,
+    }
+}
+
+fn fuzz(seed: u64) -> ! {
+    // keep our garbage off the console
+    close(1);
+    close(2);
+    let mut rng = XorShiftRng::seed_from_u64(seed);
+    let stack = &rng as *const _ as usize;
+    for _ in 0..CALLS_PER_ROUND {
+        let raw = rng.next_u64() as usize;
+        let id = if raw % 16 == 0 {
+            // not a syscall most of the time, must give ENOSYS
+            raw >> 52
+        } else {
+            SYSCALLS[raw % SYSCALLS.len()]
+        };
+        let args = [
+            arg(&mut rng, stack),
+            arg(&mut rng, stack),
+            arg(&mut rng, stack),
+        ];
+        raw_syscall(id, args);
+    }
+    exit(0)
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut survived = 0;
+    for round in 0..ROUNDS {
+        let pid = fork();
+        assert!(pid >= 0);
+        if pid == 0 {
+            fuzz(0x5eed_0000 + round as u64);
+        }
+        let mut exit_code = 0;
+        waitpid(pid as usize, &mut exit_code);
+        // a child may well be killed after unmapping its own code
+        if exit_code == 0 {
+            survived += 1;
+        }
+    }
+    println!(
+        "[syscall fuzz] {} rounds of {} calls, {} children exited normally",
+        ROUNDS, CALLS_PER_ROUND, survived
+    );
+    0
+}
@@ -0,0 +1,49 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use user_lib::{exit, getpid, gettid, thread_create, waittid};
+
+const THREAD_NUM: usize = 4;
+const ADD_PER_THREAD: usize = 1000;
+
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+fn worker(id: usize) -> ! {
+    for _ in 0..ADD_PER_THREAD {
+        COUNTER.fetch_add(1, Ordering::Relaxed);
+    }
+    println!(
+        "[thread test] thread {} tid {} in process {} done",
+        id,
+        gettid(),
+        getpid()
+    );
+    exit(id as i32)
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    println!("[thread test] main tid {}", gettid());
+    let mut tids = [0usize; THREAD_NUM];
+    for (i, tid) in tids.iter_mut().enumerate() {
+        let ret = thread_create(worker, i);
+        if ret < 0 {
+            println!("[thread test] thread_create failed: {}", ret);
+            return -1;
+        }
+        *tid = ret as usize;
+    }
+    for (i, tid) in tids.iter().enumerate() {
+        let mut exit_code = 0;
+        waittid(*tid, &mut exit_code);
+        assert_eq!(exit_code, i as i32);
+    }
+    let count = COUNTER.load(Ordering::Relaxed);
+    println!("[thread test] counter = {}", count);
+    assert_eq!(count, THREAD_NUM * ADD_PER_THREAD);
+    0
+}
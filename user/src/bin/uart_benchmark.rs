@@ -5,8 +5,10 @@
 extern crate user_lib;
 extern crate alloc;
 
+use alloc::format;
 use bitflags::bitflags;
-use user_lib::{send_msg, sleep, spawn, trace::clear_trace, waitpid};
+use user_lib::signal::SIGTERM;
+use user_lib::{kill, spawn, spawn_with_args, trace::clear_trace, waitpid};
 
 const CPU_LOAD_NUM: usize = 1;
 
@@ -80,20 +82,23 @@ pub fn main() -> i32 {
     // println!("[uart benchmark] User mode async driver benchmark finished.");
 
     println!("[uart benchmark] User mode unbuffered async driver benchmark begin.");
-    let pid1 = spawn("uart_load\0") as usize;
-    let pid2 = spawn("uart_load\0") as usize;
-    sleep(1000);
     let config1 = UartLoadConfig::UNBUF_ASYNC_MODE | UartLoadConfig::UART3;
     let config2 = UartLoadConfig::UNBUF_ASYNC_MODE | UartLoadConfig::UART4;
-    send_msg(pid1, config1.bits() as usize);
-    send_msg(pid2, config2.bits() as usize);
+    let pid1 = spawn_uart_load(config1);
+    let pid2 = spawn_uart_load(config2);
     waitpid(pid1, &mut exit_code);
     waitpid(pid2, &mut exit_code);
     println!("[uart benchmark] User mode unbuffered async driver benchmark finished.");
 
     for i in cpu_load_pid {
-        send_msg(i, 15);
+        kill(i, SIGTERM);
         waitpid(i, &mut exit_code);
     }
     0
 }
+
+fn spawn_uart_load(config: UartLoadConfig) -> usize {
+    let config = format!("{:#x}\0", config.bits());
+    let args = ["uart_load\0".as_ptr(), config.as_ptr(), core::ptr::null()];
+    spawn_with_args("uart_load\0", &args) as usize
+}
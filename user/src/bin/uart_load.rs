@@ -67,7 +67,8 @@ const SERIAL_ASYNC_WRITE: usize = 68;
 
 // type Rng = Mutex<XorShiftRng>;
 type RngInner = IncOneRng;
-type Rng = Mutex<RngInner>;
+/// Blocks in the kernel instead of spinning when contended
+type Rng = user_lib::sync::Mutex<RngInner>;
 type Hasher = blake3::Hasher;
 
 struct IncOneRng {
@@ -118,8 +119,8 @@ impl SeedableRng for IncOneRng {
 }
 
 lazy_static! {
-    static ref RX_RNG: Rng = Mutex::new(RngInner::seed_from_u64(RX_SEED.load(Relaxed) as u64));
-    static ref TX_RNG: Rng = Mutex::new(RngInner::seed_from_u64(TX_SEED.load(Relaxed) as u64));
+    static ref RX_RNG: Rng = Rng::new(RngInner::seed_from_u64(RX_SEED.load(Relaxed) as u64));
+    static ref TX_RNG: Rng = Rng::new(RngInner::seed_from_u64(TX_SEED.load(Relaxed) as u64));
 }
 
 bitflags! {
@@ -135,13 +136,25 @@ bitflags! {
     }
 }
 
+/// Usage: `uart_load [config]`. Without a config on the command line, wait
+/// for it to arrive as a soft interrupt message.
 #[no_mangle]
-pub fn main() -> i32 {
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
     let init_res = init_user_trap();
-    println!(
-        "[uart load] trap init result: {:#x}, now waiting for config init...",
-        init_res
-    );
+    if argc > 1 {
+        match parse_config(argv[1]) {
+            Some(bits) if apply_config(bits) => {}
+            _ => {
+                println!("[uart load] Invalid config {}!", argv[1]);
+                return -1;
+            }
+        }
+    } else {
+        println!(
+            "[uart load] trap init result: {:#x}, now waiting for config init...",
+            init_res
+        );
+    }
     unsafe {
         uie::set_usoft();
         uie::set_utimer();
@@ -171,6 +184,45 @@ pub fn main() -> i32 {
     0
 }
 
+/// Accept decimal, `0x` hex or `0b` binary config bits.
+fn parse_config(arg: &str) -> Option<u32> {
+    if let Some(hex) = arg.strip_prefix("0x") {
+        u32::from_str_radix(hex, 16).ok()
+    } else if let Some(bin) = arg.strip_prefix("0b") {
+        u32::from_str_radix(bin, 2).ok()
+    } else {
+        arg.parse().ok()
+    }
+}
+
+fn apply_config(bits: u32) -> bool {
+    if let Some(config) = UartLoadConfig::from_bits(bits) {
+        let mode = config & UartLoadConfig::ALL_MODE;
+        MODE.store(mode.bits(), Relaxed);
+        if config.contains(UartLoadConfig::UART3) {
+            TX_SEED.store(20210821, Relaxed);
+            RX_SEED.store(1000000007, Relaxed);
+            #[cfg(feature = "board_qemu")]
+            UART_IRQN.store(14, Relaxed);
+            #[cfg(feature = "board_lrv")]
+            UART_IRQN.store(6, Relaxed);
+        } else if config.contains(UartLoadConfig::UART4) {
+            RX_SEED.store(20210821, Relaxed);
+            TX_SEED.store(1000000007, Relaxed);
+            #[cfg(feature = "board_qemu")]
+            UART_IRQN.store(15, Relaxed);
+            #[cfg(feature = "board_lrv")]
+            UART_IRQN.store(7, Relaxed);
+        } else {
+            println!("[uart load] UART config invalid!");
+        }
+        IS_INITIALIZED.store(true, Relaxed);
+        true
+    } else {
+        false
+    }
+}
+
 fn kernel_driver_test() -> (usize, usize, usize) {
     let mut tx_rng = TX_RNG.lock();
     let mut rx_rng = RX_RNG.lock();
@@ -1032,28 +1084,7 @@ mod user_trap {
         //     println!("[uart load] Received message 0x{:x} from pid {}", msg, pid);
         // }
         // push_trace(U_TRAP_HANDLER | 0 | 128);
-        if let Some(config) = UartLoadConfig::from_bits(msg as u32) {
-            let mode = config & UartLoadConfig::ALL_MODE;
-            MODE.store(mode.bits(), Relaxed);
-            if config.contains(UartLoadConfig::UART3) {
-                TX_SEED.store(20210821, Relaxed);
-                RX_SEED.store(1000000007, Relaxed);
-                #[cfg(feature = "board_qemu")]
-                UART_IRQN.store(14, Relaxed);
-                #[cfg(feature = "board_lrv")]
-                UART_IRQN.store(6, Relaxed);
-            } else if config.contains(UartLoadConfig::UART4) {
-                RX_SEED.store(20210821, Relaxed);
-                TX_SEED.store(1000000007, Relaxed);
-                #[cfg(feature = "board_qemu")]
-                UART_IRQN.store(15, Relaxed);
-                #[cfg(feature = "board_lrv")]
-                UART_IRQN.store(7, Relaxed);
-            } else {
-                println!("[uart load] UART config invalid!");
-            }
-            IS_INITIALIZED.store(true, Relaxed);
-        } else {
+        if !apply_config(msg as u32) {
             println!("[uart load] Invalid config {:#x}!", msg);
         }
         // push_trace(U_TRAP_RETURN | 0 | 128);
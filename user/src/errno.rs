@@ -0,0 +1,81 @@
+/// Error numbers returned by the kernel as negative values, see `os::errno`.
+#[repr(isize)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Errno {
+    EPERM = 1,
+    ENOENT = 2,
+    ESRCH = 3,
+    EINTR = 4,
+    EIO = 5,
+    E2BIG = 7,
+    ENOEXEC = 8,
+    EBADF = 9,
+    ECHILD = 10,
+    EAGAIN = 11,
+    ENOMEM = 12,
+    EACCES = 13,
+    EFAULT = 14,
+    EBUSY = 16,
+    EEXIST = 17,
+    ENODEV = 19,
+    ENOTDIR = 20,
+    EISDIR = 21,
+    EINVAL = 22,
+    EMFILE = 24,
+    EFBIG = 27,
+    ENOSPC = 28,
+    ESPIPE = 29,
+    EPIPE = 32,
+    EDEADLK = 35,
+    ENAMETOOLONG = 36,
+    ENOSYS = 38,
+    /// Any code this table does not know about.
+    EUNKNOWN = 4095,
+}
+
+impl Errno {
+    pub fn from_ret(ret: isize) -> Self {
+        use Errno::*;
+        match -ret {
+            1 => EPERM,
+            2 => ENOENT,
+            3 => ESRCH,
+            4 => EINTR,
+            5 => EIO,
+            7 => E2BIG,
+            8 => ENOEXEC,
+            9 => EBADF,
+            10 => ECHILD,
+            11 => EAGAIN,
+            12 => ENOMEM,
+            13 => EACCES,
+            14 => EFAULT,
+            16 => EBUSY,
+            17 => EEXIST,
+            19 => ENODEV,
+            20 => ENOTDIR,
+            21 => EISDIR,
+            22 => EINVAL,
+            24 => EMFILE,
+            27 => EFBIG,
+            28 => ENOSPC,
+            29 => ESPIPE,
+            32 => EPIPE,
+            35 => EDEADLK,
+            36 => ENAMETOOLONG,
+            38 => ENOSYS,
+            _ => EUNKNOWN,
+        }
+    }
+}
+
+pub type Result<T> = core::result::Result<T, Errno>;
+
+/// Turn a raw syscall return value into a `Result`.
+pub fn check(ret: isize) -> Result<usize> {
+    if ret < 0 {
+        Err(Errno::from_ret(ret))
+    } else {
+        Ok(ret as usize)
+    }
+}
@@ -5,8 +5,11 @@
 
 #[macro_use]
 pub mod console;
+pub mod errno;
 pub mod future;
 mod lang_items;
+pub mod signal;
+pub mod sync;
 mod syscall;
 pub mod trace;
 pub mod trap;
@@ -17,17 +20,49 @@ extern crate alloc;
 extern crate bitflags;
 
 use alloc::vec::Vec;
-use buddy_system_allocator::LockedHeap;
+use buddy_system_allocator::Heap;
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::{null_mut, NonNull};
+use spin::Mutex;
 use syscall::*;
 
+pub use errno::Errno;
+pub use signal::SignalAction;
 pub use trap::{UserTrapContext, UserTrapQueue, UserTrapRecord};
 
 const USER_HEAP_SIZE: usize = 32768;
+/// Least the heap grows by at a time
+const USER_HEAP_GROW_SIZE: usize = 0x10000;
 
 static mut HEAP_SPACE: [u8; USER_HEAP_SIZE] = [0; USER_HEAP_SIZE];
 
+/// Starts on `HEAP_SPACE` and grows with sbrk when an allocation fails.
+struct GrowableHeap(Mutex<Heap>);
+
+unsafe impl GlobalAlloc for GrowableHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut heap = self.0.lock();
+        if let Ok(ptr) = heap.alloc(layout) {
+            return ptr.as_ptr();
+        }
+        // twice the block size holds an aligned block wherever it starts
+        let size =
+            (layout.size().max(layout.align()).next_power_of_two() * 2).max(USER_HEAP_GROW_SIZE);
+        let start = sys_sbrk(size as isize);
+        if start < 0 {
+            return null_mut();
+        }
+        heap.add_to_heap(start as usize, start as usize + size);
+        heap.alloc(layout).map_or(null_mut(), |ptr| ptr.as_ptr())
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.0.lock().dealloc(NonNull::new_unchecked(ptr), layout);
+    }
+}
+
 #[global_allocator]
-static HEAP: LockedHeap = LockedHeap::empty();
+static HEAP: GrowableHeap = GrowableHeap(Mutex::new(Heap::empty()));
 
 #[alloc_error_handler]
 pub fn handle_alloc_error(layout: core::alloc::Layout) -> ! {
@@ -47,7 +82,8 @@ pub extern "C" fn _start(argc: usize, argv: usize) -> ! {
     }
 
     unsafe {
-        HEAP.lock()
+        HEAP.0
+            .lock()
             .init(HEAP_SPACE.as_ptr() as usize, USER_HEAP_SIZE);
     }
     let mut v: Vec<&'static str> = Vec::new();
@@ -83,6 +119,44 @@ bitflags! {
     }
 }
 
+bitflags! {
+    pub struct StatMode: u32 {
+        const NULL = 0;
+        const FIFO = 0o010000;
+        const CHR = 0o020000;
+        const DIR = 0o040000;
+        const FILE = 0o100000;
+    }
+}
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct Stat {
+    pub dev: u64,
+    pub ino: u64,
+    pub mode: StatMode,
+    pub nlink: u32,
+    pub size: u64,
+    pad: [u64; 6],
+}
+
+impl Stat {
+    pub fn new() -> Self {
+        Stat {
+            dev: 0,
+            ino: 0,
+            mode: StatMode::NULL,
+            nlink: 0,
+            size: 0,
+            pad: [0; 6],
+        }
+    }
+}
+
+pub const SEEK_SET: usize = 0;
+pub const SEEK_CUR: usize = 1;
+pub const SEEK_END: usize = 2;
+
 pub fn dup(fd: usize) -> isize {
     sys_dup(fd)
 }
@@ -105,12 +179,52 @@ pub fn read(fd: usize, buf: &mut [u8]) -> isize {
 pub fn write(fd: usize, buf: &[u8]) -> isize {
     sys_write(fd, buf)
 }
+pub fn lseek(fd: usize, offset: isize, whence: usize) -> isize {
+    sys_lseek(fd, offset, whence)
+}
+pub fn fstat(fd: usize, st: &mut Stat) -> isize {
+    sys_fstat(fd, st)
+}
 pub fn exit(exit_code: i32) -> ! {
     sys_exit(exit_code);
 }
 pub fn yield_() -> isize {
     sys_yield()
 }
+pub fn kill(pid: usize, signo: usize) -> isize {
+    sys_kill(pid, signo)
+}
+/// Set the action for `signo`, the old one is stored in `old_action`.
+pub fn sigaction(
+    signo: usize,
+    action: Option<&SignalAction>,
+    old_action: Option<&mut SignalAction>,
+) -> isize {
+    sys_sigaction(
+        signo,
+        action.map_or(core::ptr::null(), |action| action as *const _),
+        old_action.map_or(core::ptr::null_mut(), |old| old as *mut _),
+    )
+}
+/// Change the blocked signals according to `how`, returns the old mask.
+pub fn sigprocmask(how: usize, set: u32) -> isize {
+    let mut old_set = 0u32;
+    match sys_sigprocmask(how, &set, &mut old_set) {
+        ret if ret < 0 => ret,
+        _ => old_set as isize,
+    }
+}
+/// Pin `pid` (0 for the caller) to the harts in `mask`, one bit per hart.
+pub fn sched_setaffinity(pid: usize, mask: usize) -> isize {
+    sys_sched_setaffinity(pid, &mask)
+}
+pub fn sched_getaffinity(pid: usize) -> isize {
+    let mut mask = 0usize;
+    match sys_sched_getaffinity(pid, &mut mask) {
+        ret if ret < 0 => ret,
+        _ => mask as isize,
+    }
+}
 #[repr(C)]
 #[derive(Debug)]
 pub struct TimeVal {
@@ -124,6 +238,13 @@ impl TimeVal {
     }
 }
 
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct TimeSpec {
+    pub sec: usize,
+    pub nsec: usize,
+}
+
 pub fn get_time() -> isize {
     let time = TimeVal::new();
     match sys_get_time(&time, 0) {
@@ -150,36 +271,177 @@ pub fn exec(path: &str, args: &[*const u8]) -> isize {
     sys_exec(path, args)
 }
 pub fn spawn(path: &str) -> isize {
-    sys_spawn(path)
+    sys_spawn(path, &[])
+}
+/// `args` is a null-terminated array of C strings, starting with the program name.
+pub fn spawn_with_args(path: &str, args: &[*const u8]) -> isize {
+    sys_spawn(path, args)
 }
+/// Block until any child exits.
 pub fn wait(exit_code: &mut i32) -> isize {
-    loop {
-        match sys_waitpid(-1, exit_code as *mut _) {
-            -2 => {
-                yield_();
-            }
-            // -1 or a real pid
-            exit_pid => return exit_pid,
-        }
-    }
+    sys_waitpid(-1, exit_code as *mut _)
 }
 
+/// Block until the child `pid` exits.
 pub fn waitpid(pid: usize, exit_code: &mut i32) -> isize {
-    loop {
-        match sys_waitpid(pid as isize, exit_code as *mut _) {
-            -2 => {
-                yield_();
-            }
-            // -1 or a real pid
-            exit_pid => return exit_pid,
-        }
-    }
+    sys_waitpid(pid as isize, exit_code as *mut _)
+}
+/// Run `entry(arg)` in a new thread of this process. The thread ends by
+/// calling `exit`, which ends the whole process if called by the main thread.
+pub fn thread_create(entry: fn(usize) -> !, arg: usize) -> isize {
+    sys_thread_create(entry as usize, arg)
+}
+pub fn gettid() -> isize {
+    sys_gettid()
+}
+pub fn waittid(tid: usize, exit_code: &mut i32) -> isize {
+    sys_waittid(tid, exit_code as *mut _)
+}
+/// Kernel mutex; a non-blocking one yields while contended.
+pub fn mutex_create(blocking: bool) -> isize {
+    sys_mutex_create(blocking)
 }
+pub fn mutex_lock(id: usize) -> isize {
+    sys_mutex_lock(id)
+}
+pub fn mutex_unlock(id: usize) -> isize {
+    sys_mutex_unlock(id)
+}
+pub fn semaphore_create(count: usize) -> isize {
+    sys_semaphore_create(count)
+}
+pub fn semaphore_up(id: usize) -> isize {
+    sys_semaphore_up(id)
+}
+pub fn semaphore_down(id: usize) -> isize {
+    sys_semaphore_down(id)
+}
+pub fn condvar_create() -> isize {
+    sys_condvar_create()
+}
+pub fn condvar_signal(id: usize) -> isize {
+    sys_condvar_signal(id)
+}
+pub fn condvar_wait(condvar_id: usize, mutex_id: usize) -> isize {
+    sys_condvar_wait(condvar_id, mutex_id)
+}
+/// Make mutex_lock and semaphore_down return EDEADLK instead of blocking
+/// into a possible deadlock.
+pub fn enable_deadlock_detect(enabled: bool) -> isize {
+    sys_enable_deadlock_detect(enabled)
+}
+/// Set the program break, 0 just returns the current one.
+pub fn brk(addr: usize) -> isize {
+    sys_brk(addr)
+}
+/// Move the program break by `increment` bytes, returns the old one.
+pub fn sbrk(increment: isize) -> isize {
+    sys_sbrk(increment)
+}
+/// Map anonymous pages at `start`, `prot` has R = 1, W = 2 and X = 4.
+/// Pages are allocated on first touch.
+pub fn mmap(start: usize, len: usize, prot: usize) -> isize {
+    sys_mmap(start, len, prot)
+}
+/// The range may cover parts of earlier mappings, but no holes.
+pub fn munmap(start: usize, len: usize) -> isize {
+    sys_munmap(start, len)
+}
+pub fn mprotect(start: usize, len: usize, prot: usize) -> isize {
+    sys_mprotect(start, len, prot)
+}
+/// Shared memory region of at least `size` bytes, returns its id.
+pub fn shm_create(size: usize) -> isize {
+    sys_shm_create(size)
+}
+/// Map region `id` at `addr`, `prot` as in mmap. The mapping is inherited
+/// by forked children.
+pub fn shm_map(id: usize, addr: usize, prot: usize) -> isize {
+    sys_shm_map(id, addr, prot)
+}
+pub fn shm_unmap(addr: usize) -> isize {
+    sys_shm_unmap(addr)
+}
+/// Issue syscall `id` with unchecked arguments, for poking at the kernel.
+pub fn raw_syscall(id: usize, args: [usize; 3]) -> isize {
+    syscall(id, args)
+}
+
+// `Result`-returning variants of the calls above.
+
+pub fn try_open(path: &str, flags: OpenFlags) -> errno::Result<usize> {
+    errno::check(open(path, flags))
+}
+pub fn try_dup(fd: usize) -> errno::Result<usize> {
+    errno::check(dup(fd))
+}
+pub fn try_close(fd: usize) -> errno::Result<()> {
+    errno::check(close(fd)).map(|_| ())
+}
+pub fn try_pipe(pipe_fd: &mut [usize]) -> errno::Result<()> {
+    errno::check(pipe(pipe_fd)).map(|_| ())
+}
+pub fn try_read(fd: usize, buf: &mut [u8]) -> errno::Result<usize> {
+    errno::check(read(fd, buf))
+}
+pub fn try_write(fd: usize, buf: &[u8]) -> errno::Result<usize> {
+    errno::check(write(fd, buf))
+}
+pub fn try_lseek(fd: usize, offset: isize, whence: usize) -> errno::Result<usize> {
+    errno::check(lseek(fd, offset, whence))
+}
+pub fn try_fstat(fd: usize, st: &mut Stat) -> errno::Result<()> {
+    errno::check(fstat(fd, st)).map(|_| ())
+}
+pub fn try_fork() -> errno::Result<usize> {
+    errno::check(fork())
+}
+pub fn try_exec(path: &str, args: &[*const u8]) -> errno::Result<()> {
+    errno::check(exec(path, args)).map(|_| ())
+}
+pub fn try_spawn(path: &str) -> errno::Result<usize> {
+    errno::check(spawn(path))
+}
+pub fn try_spawn_with_args(path: &str, args: &[*const u8]) -> errno::Result<usize> {
+    errno::check(spawn_with_args(path, args))
+}
+pub fn try_waitpid(pid: usize, exit_code: &mut i32) -> errno::Result<usize> {
+    errno::check(waitpid(pid, exit_code))
+}
+pub fn try_kill(pid: usize, signo: usize) -> errno::Result<()> {
+    errno::check(kill(pid, signo)).map(|_| ())
+}
+pub fn try_thread_create(entry: fn(usize) -> !, arg: usize) -> errno::Result<usize> {
+    errno::check(thread_create(entry, arg))
+}
+pub fn try_waittid(tid: usize, exit_code: &mut i32) -> errno::Result<usize> {
+    errno::check(waittid(tid, exit_code))
+}
+pub fn try_mailread(buf: &mut [u8]) -> errno::Result<usize> {
+    errno::check(mailread(buf))
+}
+pub fn try_mailwrite(pid: usize, buf: &[u8]) -> errno::Result<usize> {
+    errno::check(mailwrite(pid, buf))
+}
+pub fn try_send_msg(pid: usize, msg: usize) -> errno::Result<()> {
+    errno::check(send_msg(pid, msg)).map(|_| ())
+}
+
 pub fn sleep(period_ms: usize) {
-    let start = get_time();
-    while get_time() < start + period_ms as isize {
-        // sys_yield();
-    }
+    let req = TimeSpec {
+        sec: period_ms / 1000,
+        nsec: period_ms % 1000 * 1_000_000,
+    };
+    sys_nanosleep(&req, core::ptr::null_mut());
+}
+
+/// Sleep for `req`. If interrupted, returns EINTR and stores the time left
+/// in `rem`.
+pub fn nanosleep(req: &TimeSpec, rem: Option<&mut TimeSpec>) -> isize {
+    sys_nanosleep(
+        req,
+        rem.map_or(core::ptr::null_mut(), |rem| rem as *mut TimeSpec),
+    )
 }
 
 pub fn mailread(buf: &mut [u8]) -> isize {
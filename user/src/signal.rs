@@ -0,0 +1,57 @@
+/// Signal numbers and actions, see `os::task::signal`.
+pub const SIGHUP: usize = 1;
+pub const SIGINT: usize = 2;
+pub const SIGQUIT: usize = 3;
+pub const SIGILL: usize = 4;
+pub const SIGABRT: usize = 6;
+pub const SIGKILL: usize = 9;
+pub const SIGUSR1: usize = 10;
+pub const SIGSEGV: usize = 11;
+pub const SIGUSR2: usize = 12;
+pub const SIGPIPE: usize = 13;
+pub const SIGALRM: usize = 14;
+pub const SIGTERM: usize = 15;
+pub const SIGCHLD: usize = 17;
+pub const SIGCONT: usize = 18;
+pub const SIGSTOP: usize = 19;
+
+pub const SIG_DFL: usize = 0;
+pub const SIG_IGN: usize = 1;
+
+pub const SIG_BLOCK: usize = 0;
+pub const SIG_UNBLOCK: usize = 1;
+pub const SIG_SETMASK: usize = 2;
+
+/// Low bits of `UserTrapRecord::cause` for a signal, the signal number is
+/// in the upper bits and the handler address in the message.
+pub const SIGNAL_TRAP_CAUSE: usize = 0xF;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct SignalAction {
+    pub handler: usize,
+}
+
+impl SignalAction {
+    /// Handlers are called from the user trap handler, so the process
+    /// must have called `init_user_trap`. Otherwise the default action is
+    /// taken.
+    pub fn new(handler: fn(usize)) -> Self {
+        Self {
+            handler: handler as usize,
+        }
+    }
+    pub const fn ignore() -> Self {
+        Self { handler: SIG_IGN }
+    }
+}
+
+impl Default for SignalAction {
+    fn default() -> Self {
+        Self { handler: SIG_DFL }
+    }
+}
+
+pub fn sigmask(signo: usize) -> u32 {
+    1 << signo
+}
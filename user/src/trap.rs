@@ -10,6 +10,8 @@ const MAX_USER_TRAP_NUM: usize = 128;
 
 use rv_plic::PLIC;
 
+use crate::signal::SIGNAL_TRAP_CAUSE;
+
 use crate::trace::{
     push_trace, PLIC_CLAIM, TRAP_QUEUE_ENTER, TRAP_QUEUE_EXIT, U_TRAP_HANDLER, U_TRAP_RETURN,
 };
@@ -81,6 +83,9 @@ pub fn user_trap_handler(cx: &mut UserTrapContext) -> &mut UserTrapContext {
                     // "real" soft interrupt
                     let pid = cause >> 4;
                     soft_intr_handler(pid, msg);
+                } else if cause & 0xF == SIGNAL_TRAP_CAUSE {
+                    let handler: fn(usize) = unsafe { core::mem::transmute(msg) };
+                    handler(cause >> 4);
                 } else if ucause::Interrupt::from(cause) == ucause::Interrupt::UserExternal {
                     let irq = trap_record.message as u16;
                     // push_trace(U_TRAP_HANDLER | 8 | 128);